@@ -0,0 +1,8 @@
+-- Named lists of history entries, for putting together runbooks
+create table if not exists collections (
+	name text not null,
+	history_id text not null,
+	added integer not null,
+
+	primary key(name, history_id)
+);
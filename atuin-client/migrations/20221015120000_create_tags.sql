@@ -0,0 +1,9 @@
+-- Freeform labels on history entries, such as deploy or oncall
+create table if not exists tags (
+	history_id text not null,
+	tag text not null,
+
+	primary key(history_id, tag)
+);
+
+create index if not exists idx_tags_tag on tags(tag);
@@ -0,0 +1,23 @@
+-- A full text index of commands, for the fulltext-indexed search mode, and
+-- triggers to keep it up to date. It's keyed on history ids rather than the
+-- implicit rowid of the history table. That table's primary key is text, so a
+-- vacuum is free to renumber its rowids, which would leave the index pointing
+-- at the wrong commands
+create virtual table if not exists history_fts using fts5(
+	id unindexed,
+	command
+);
+
+insert into history_fts(id, command) select id, command from history;
+
+create trigger if not exists history_fts_insert after insert on history begin
+	insert into history_fts(id, command) values (new.id, new.command);
+end;
+
+create trigger if not exists history_fts_delete after delete on history begin
+	delete from history_fts where id = old.id;
+end;
+
+create trigger if not exists history_fts_update after update of id, command on history begin
+	update history_fts set id = new.id, command = new.command where id = old.id;
+end;
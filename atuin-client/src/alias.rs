@@ -0,0 +1,142 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+
+use eyre::{Result, WrapErr};
+
+/// Shell aliases, used to show commands as they actually ran
+#[derive(Debug, Clone, Default)]
+pub struct Aliases {
+    aliases: HashMap<String, String>,
+}
+
+impl Aliases {
+    pub fn new(aliases: HashMap<String, String>) -> Self {
+        Self { aliases }
+    }
+
+    /// Load aliases from a file, in the format printed by the `alias` builtin
+    /// of bash (`alias g='git'`) or zsh (`g=git`). Blank lines, comments, and
+    /// anything else that doesn't look like an alias are ignored
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let contents = fs_err::read_to_string(path)
+            .wrap_err_with(|| format!("could not read aliases from {:?}", path))?;
+
+        self.aliases.extend(contents.lines().filter_map(parse_line));
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    /// Expand an alias used as the first word of a command. Like the shell, an
+    /// alias is only expanded once, so aliases referring to themselves are fine
+    pub fn resolve<'a>(&self, command: &'a str) -> Cow<'a, str> {
+        let trimmed = command.trim_start();
+        let (word, rest) = trimmed
+            .find(char::is_whitespace)
+            .map_or((trimmed, ""), |i| trimmed.split_at(i));
+
+        match self.aliases.get(word) {
+            Some(expansion) => Cow::Owned(format!("{}{}", expansion, rest)),
+            None => Cow::Borrowed(command),
+        }
+    }
+
+    /// The opposite of `resolve`, putting the alias back in place of what it
+    /// expands to at the start of a command. The longest expansion wins, so
+    /// `git checkout -b` becomes `gcb` rather than `gco -b`, given both
+    pub fn contract<'a>(&self, command: &'a str) -> Cow<'a, str> {
+        let trimmed = command.trim_start();
+
+        self.aliases
+            .iter()
+            .filter(|(_, expansion)| !expansion.is_empty())
+            .filter_map(|(name, expansion)| {
+                let rest = trimmed.strip_prefix(expansion.as_str())?;
+                (rest.is_empty() || rest.starts_with(char::is_whitespace))
+                    .then(|| (expansion.len(), name, rest))
+            })
+            .max_by(|a, b| a.0.cmp(&b.0).then_with(|| b.1.cmp(a.1)))
+            .map_or(Cow::Borrowed(command), |(_, name, rest)| {
+                Cow::Owned(format!("{}{}", name, rest))
+            })
+    }
+}
+
+fn parse_line(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    let line = line.strip_prefix("alias ").unwrap_or(line);
+    let (name, value) = line.split_once('=')?;
+
+    if name.is_empty() || name.starts_with('#') || name.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let value = value
+        .strip_prefix('\'')
+        .and_then(|v| v.strip_suffix('\''))
+        .or_else(|| value.strip_prefix('"').and_then(|v| v.strip_suffix('"')))
+        .unwrap_or(value);
+
+    Some((name.to_string(), value.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{parse_line, Aliases};
+
+    #[test]
+    fn parse_alias_lines() {
+        assert_eq!(
+            parse_line("alias g='git'"),
+            Some(("g".to_string(), "git".to_string()))
+        );
+        assert_eq!(
+            parse_line("gco='git checkout'"),
+            Some(("gco".to_string(), "git checkout".to_string()))
+        );
+        assert_eq!(
+            parse_line("ll=\"ls -la\""),
+            Some(("ll".to_string(), "ls -la".to_string()))
+        );
+        assert_eq!(parse_line("# g=git"), None);
+        assert_eq!(parse_line(""), None);
+        assert_eq!(parse_line("export FOO"), None);
+    }
+
+    #[test]
+    fn resolve() {
+        let aliases = Aliases::new(HashMap::from([
+            ("g".to_string(), "git".to_string()),
+            ("ls".to_string(), "ls --color".to_string()),
+        ]));
+
+        assert_eq!(aliases.resolve("g push"), "git push");
+        assert_eq!(aliases.resolve("g"), "git");
+        assert_eq!(aliases.resolve("ls /tmp"), "ls --color /tmp");
+        assert_eq!(aliases.resolve("go build"), "go build");
+        assert_eq!(aliases.resolve("echo g"), "echo g");
+    }
+
+    #[test]
+    fn contract() {
+        let aliases = Aliases::new(HashMap::from([
+            ("g".to_string(), "git".to_string()),
+            ("gco".to_string(), "git checkout".to_string()),
+            ("gcb".to_string(), "git checkout -b".to_string()),
+        ]));
+
+        assert_eq!(aliases.contract("git checkout main"), "gco main");
+        assert_eq!(aliases.contract("git checkout -b fix"), "gcb fix");
+        assert_eq!(aliases.contract("git push"), "g push");
+        assert_eq!(aliases.contract("git"), "g");
+        assert_eq!(aliases.contract("gitk"), "gitk");
+        assert_eq!(aliases.contract("echo git"), "echo git");
+    }
+}
@@ -1,5 +1,8 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::path::Path;
 use std::str::FromStr;
+use std::time::Instant;
 
 use async_trait::async_trait;
 use chrono::prelude::*;
@@ -8,14 +11,16 @@ use chrono::Utc;
 use itertools::Itertools;
 use regex::Regex;
 
+use eyre::{Result, WrapErr};
 use fs_err as fs;
 use sqlx::{
     sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteRow},
-    Result, Row,
+    Row,
 };
 
 use super::history::History;
 use super::ordering;
+use super::query::{Filters, Predicates};
 use super::settings::SearchMode;
 
 #[async_trait]
@@ -45,7 +50,87 @@ pub trait Database {
         query: &str,
     ) -> Result<Vec<History>>;
 
+    /// As with `search`, but only the commands whose latest run passes the
+    /// filters. The filtering is done by the database, and any limit applies
+    /// afterwards
+    async fn search_filtered(
+        &self,
+        limit: Option<i64>,
+        search_mode: SearchMode,
+        query: &str,
+        filters: &Filters,
+    ) -> Result<Vec<History>>;
+
+    /// Commands containing the query exactly as given, case and all. None of
+    /// the search modes' wildcards, operators or words apply
+    async fn search_fixed(
+        &self,
+        limit: Option<i64>,
+        query: &str,
+        filters: &Filters,
+    ) -> Result<Vec<History>>;
+
     async fn query_history(&self, query: &str) -> Result<Vec<History>>;
+
+    /// Build the full text index again from scratch, in case it has drifted
+    /// from the history
+    async fn rebuild_index(&self) -> Result<()>;
+
+    /// Rebuild everything derived from the history table, such as after an
+    /// import or a migration: the full text index, the other indexes, and
+    /// the statistics the query planner uses. Tags and collection entries
+    /// left behind by deleted history are dropped. `progress` is told about
+    /// each step as it starts
+    async fn reindex(&self, progress: &(dyn Fn(&'static str) + Send + Sync)) -> Result<Reindexed>;
+
+    /// How many times each of the given commands has been ran
+    async fn command_counts(&self, commands: &[String]) -> Result<HashMap<String, i64>>;
+
+    async fn add_to_collection(&self, name: &str, h: &History) -> Result<()>;
+    async fn remove_from_collection(&self, name: &str, h: &History) -> Result<()>;
+
+    /// The entries in a collection, in the order they were added
+    async fn collection(&self, name: &str) -> Result<Vec<History>>;
+
+    /// Every collection, along with how many entries are in it
+    async fn collections(&self) -> Result<Vec<(String, i64)>>;
+
+    /// How many times each command was ran between two times, most ran first.
+    /// Commands ran as often as each other go most recently ran first
+    async fn top_commands(
+        &self,
+        from: chrono::DateTime<Utc>,
+        to: chrono::DateTime<Utc>,
+    ) -> Result<Vec<(String, i64)>>;
+
+    /// The distinct directories commands were ran in, most recently used first
+    async fn recent_dirs(&self, limit: i64) -> Result<Vec<String>>;
+
+    /// Every command ran in a session, oldest first
+    async fn session_history(&self, session: &str) -> Result<Vec<History>>;
+
+    /// Runs of a command that started within `window` of the run before it
+    /// in the same session, oldest first. The first run of each burst isn't
+    /// included, so these are the copies a misbehaving shell hook left behind
+    async fn duplicates(&self, window: chrono::Duration) -> Result<Vec<History>>;
+
+    async fn add_tag(&self, h: &History, tag: &str) -> Result<()>;
+
+    /// Remove a tag from every run of the entry's command
+    async fn remove_tag(&self, h: &History, tag: &str) -> Result<()>;
+
+    /// The tags on any run of each of the given commands, sorted
+    async fn command_tags(&self, commands: &[String]) -> Result<HashMap<String, Vec<String>>>;
+
+    /// Every command with a run that has this tag
+    async fn tagged_commands(&self, tag: &str) -> Result<Vec<String>>;
+}
+
+/// What `Database::reindex` cleaned up
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Reindexed {
+    pub orphaned_tags: u64,
+    pub orphaned_collection_entries: u64,
 }
 
 // Intended for use on a developer machine and not a sync server.
@@ -77,6 +162,29 @@ impl Sqlite {
         Ok(Self { pool })
     }
 
+    /// Open an existing database read only, without running any migrations.
+    /// Useful for inspecting a backup, or history from another machine
+    pub async fn open_read_only(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        debug!("opening sqlite database at {:?} read only", path);
+
+        let opts =
+            SqliteConnectOptions::from_str(path.as_os_str().to_str().unwrap())?.read_only(true);
+
+        let pool = SqlitePoolOptions::new().connect_with(opts).await?;
+
+        // Make sure this really is an atuin database, rather than failing on
+        // the first real query
+        sqlx::query(
+            "select id, timestamp, duration, exit, command, cwd, session, hostname
+                from history limit 1",
+        )
+        .fetch_optional(&pool)
+        .await?;
+
+        Ok(Self { pool })
+    }
+
     async fn setup_db(pool: &SqlitePool) -> Result<()> {
         debug!("running sqlite database setup");
 
@@ -104,6 +212,112 @@ impl Sqlite {
         Ok(())
     }
 
+    /// Search the full text index. Each word of the query has to start a word
+    /// of the command, and the best matches come first
+    async fn search_indexed(
+        &self,
+        limit: Option<i64>,
+        query: &str,
+        filters: &Filters,
+    ) -> Result<Vec<History>> {
+        let limit = limit.map_or("".to_owned(), |l| format!("limit {}", l));
+        let predicates = Predicates::from_filters(2, filters);
+
+        let res = predicates
+            .bind(
+                sqlx::query(
+                    format!(
+                        "select * from (
+                            select h.*, f.rank from history h
+                                join (select id, rank from history_fts where history_fts match ?1) f
+                                    on f.id = h.id
+                                group by h.command
+                                having max(h.timestamp)
+                        ) {}
+                        order by rank, timestamp desc {}",
+                        predicates.where_clause(),
+                        limit
+                    )
+                    .as_str(),
+                )
+                .bind(fts_query(query)),
+            )
+            .map(Self::query_history)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(res)
+    }
+
+    /// Match the latest run of each command against a regex. SQLite has no
+    /// regexes of its own, so every command that passes the filters is
+    /// fetched and matched here
+    async fn search_regex(
+        &self,
+        limit: Option<i64>,
+        query: &str,
+        filters: &Filters,
+    ) -> Result<Vec<History>> {
+        let regex = Regex::new(query).wrap_err("invalid regex")?;
+        let limit = limit
+            .and_then(|l| usize::try_from(l).ok())
+            .unwrap_or(usize::MAX);
+        let predicates = Predicates::from_filters(1, filters);
+
+        let res = predicates
+            .bind(sqlx::query(
+                format!(
+                    "select * from (
+                        select * from history h
+                            group by command
+                            having max(timestamp)
+                    ) {}
+                    order by timestamp desc",
+                    predicates.where_clause(),
+                )
+                .as_str(),
+            ))
+            .map(Self::query_history)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(res
+            .into_iter()
+            .filter(|h| regex.is_match(&h.command))
+            .take(limit)
+            .collect())
+    }
+
+    /// Run an aggregate over the history of each of the given commands
+    async fn aggregate_by_command(
+        &self,
+        aggregate: &str,
+        commands: &[String],
+    ) -> Result<HashMap<String, i64>> {
+        let mut res = HashMap::with_capacity(commands.len());
+
+        // Stay well under SQLite's limit on the number of bound parameters
+        for chunk in commands.chunks(500) {
+            let query = format!(
+                "select command, {} from history where command in ({}) group by command",
+                aggregate,
+                std::iter::repeat("?").take(chunk.len()).join(", ")
+            );
+
+            let rows: Vec<(String, i64)> = chunk
+                .iter()
+                .fold(sqlx::query_as(query.as_str()), |query, command| {
+                    query.bind(command)
+                })
+                .fetch_all(&self.pool)
+                .await?;
+
+            res.extend(rows);
+        }
+
+        Ok(res)
+    }
+
     fn query_history(row: SqliteRow) -> History {
         History {
             id: row.get("id"),
@@ -118,6 +332,16 @@ impl Sqlite {
     }
 }
 
+/// Turn a query into an FTS5 one, where each word has to start a word of the
+/// command. Quoting the words keeps FTS5 from reading operators into them
+fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .join(" ")
+}
+
 #[async_trait]
 impl Database for Sqlite {
     async fn save(&mut self, h: &History) -> Result<()> {
@@ -226,9 +450,9 @@ impl Database for Sqlite {
         let res = sqlx::query(
             "select * from history where timestamp >= ?1 and timestamp <= ?2 order by timestamp asc",
         )
-        .bind(from)
-        .bind(to)
-            .map(Self::query_history)
+        .bind(from.timestamp_nanos())
+        .bind(to.timestamp_nanos())
+        .map(Self::query_history)
         .fetch_all(&self.pool)
         .await?;
 
@@ -283,13 +507,49 @@ impl Database for Sqlite {
         search_mode: SearchMode,
         query: &str,
     ) -> Result<Vec<History>> {
+        self.search_filtered(limit, search_mode, query, &Filters::default())
+            .await
+    }
+
+    async fn search_filtered(
+        &self,
+        limit: Option<i64>,
+        search_mode: SearchMode,
+        query: &str,
+        filters: &Filters,
+    ) -> Result<Vec<History>> {
+        debug!(
+            "searching for {:?} in {:?} mode with {:?}",
+            query, search_mode, filters
+        );
+        let start = Instant::now();
+
+        if let SearchMode::FullTextIndexed = search_mode {
+            if !fts_query(query).is_empty() {
+                let res = self.search_indexed(limit, query, filters).await?;
+                debug!("found {} commands in {:?}", res.len(), start.elapsed());
+                return Ok(res);
+            }
+        }
+
+        if let SearchMode::Regex = search_mode {
+            let res = self.search_regex(limit, query, filters).await?;
+            debug!("found {} commands in {:?}", res.len(), start.elapsed());
+            return Ok(res);
+        }
+
         let orig_query = query;
         let query = query.to_string().replace('*', "%"); // allow wildcard char
         let limit = limit.map_or("".to_owned(), |l| format!("limit {}", l));
 
         let (query_sql, query_params) = match search_mode {
             SearchMode::Prefix => ("command like ?1".to_string(), vec![format!("{}%", query)]),
-            SearchMode::FullText => ("command like ?1".to_string(), vec![format!("%{}%", query)]),
+            // With nothing to look up in the index, match everything the same
+            // way as fulltext
+            SearchMode::FullText | SearchMode::FullTextIndexed => {
+                ("command like ?1".to_string(), vec![format!("%{}%", query)])
+            }
+            SearchMode::Regex => unreachable!("regexes are matched by search_regex"),
             SearchMode::Fuzzy => {
                 let split_regex = Regex::new(r" +").unwrap();
                 let terms: Vec<&str> = split_regex.split(query.as_str()).collect();
@@ -350,26 +610,34 @@ impl Database for Sqlite {
             }
         };
 
-        let res = query_params
-            .iter()
-            .fold(
-                sqlx::query(
-                    format!(
-                        "select * from history h
-                                           where {}
-                                           group by command
-                                           having max(timestamp)
-                                           order by timestamp desc {}",
-                        query_sql.as_str(),
-                        limit.clone()
-                    )
-                    .as_str(),
+        // The filters apply to the latest run of each command, once grouped
+        let predicates = Predicates::from_filters(query_params.len() + 1, filters);
+
+        let res = predicates
+            .bind(
+                query_params.iter().fold(
+                    sqlx::query(
+                        format!(
+                            "select * from (
+                            select * from history h
+                                where {}
+                                group by command
+                                having max(timestamp)
+                        ) {}
+                        order by timestamp desc {}",
+                            query_sql.as_str(),
+                            predicates.where_clause(),
+                            limit.clone()
+                        )
+                        .as_str(),
+                    ),
+                    |query, query_param| query.bind(query_param),
                 ),
-                |query, query_param| query.bind(query_param),
             )
             .map(Self::query_history)
             .fetch_all(&self.pool)
             .await?;
+        debug!("found {} commands in {:?}", res.len(), start.elapsed());
 
         Ok(ordering::reorder_fuzzy(search_mode, orig_query, res))
     }
@@ -382,11 +650,282 @@ impl Database for Sqlite {
 
         Ok(res)
     }
+
+    async fn search_fixed(
+        &self,
+        limit: Option<i64>,
+        query: &str,
+        filters: &Filters,
+    ) -> Result<Vec<History>> {
+        debug!(
+            "searching for {:?} as a fixed string with {:?}",
+            query, filters
+        );
+        let limit = limit.map_or("".to_owned(), |l| format!("limit {}", l));
+        let predicates = Predicates::from_filters(2, filters);
+
+        let res = predicates
+            .bind(
+                sqlx::query(
+                    format!(
+                        "select * from (
+                            select * from history h
+                                where instr(command, ?1) > 0
+                                group by command
+                                having max(timestamp)
+                        ) {}
+                        order by timestamp desc {}",
+                        predicates.where_clause(),
+                        limit
+                    )
+                    .as_str(),
+                )
+                .bind(query),
+            )
+            .map(Self::query_history)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(res)
+    }
+
+    async fn rebuild_index(&self) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("delete from history_fts")
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("insert into history_fts(id, command) select id, command from history")
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn reindex(&self, progress: &(dyn Fn(&'static str) + Send + Sync)) -> Result<Reindexed> {
+        progress("rebuilding the full text index");
+        self.rebuild_index().await?;
+
+        progress("dropping tags of deleted history");
+        let orphaned_tags =
+            sqlx::query("delete from tags where history_id not in (select id from history)")
+                .execute(&self.pool)
+                .await?
+                .rows_affected();
+
+        progress("dropping deleted history from collections");
+        let orphaned_collection_entries =
+            sqlx::query("delete from collections where history_id not in (select id from history)")
+                .execute(&self.pool)
+                .await?
+                .rows_affected();
+
+        progress("rebuilding indexes");
+        sqlx::query("reindex").execute(&self.pool).await?;
+
+        progress("updating query planner statistics");
+        sqlx::query("analyze").execute(&self.pool).await?;
+
+        Ok(Reindexed {
+            orphaned_tags,
+            orphaned_collection_entries,
+        })
+    }
+
+    async fn command_counts(&self, commands: &[String]) -> Result<HashMap<String, i64>> {
+        self.aggregate_by_command("count(1)", commands).await
+    }
+
+    async fn add_to_collection(&self, name: &str, h: &History) -> Result<()> {
+        debug!("adding {} to collection {}", h.id, name);
+
+        sqlx::query(
+            "insert or ignore into collections(name, history_id, added)
+                values(?1, ?2, ?3)",
+        )
+        .bind(name)
+        .bind(h.id.as_str())
+        .bind(Utc::now().timestamp_nanos())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn remove_from_collection(&self, name: &str, h: &History) -> Result<()> {
+        debug!("removing {} from collection {}", h.id, name);
+
+        sqlx::query("delete from collections where name = ?1 and history_id = ?2")
+            .bind(name)
+            .bind(h.id.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn collection(&self, name: &str) -> Result<Vec<History>> {
+        let res = sqlx::query(
+            "select history.* from collections
+                join history on history.id = collections.history_id
+                where collections.name = ?1
+                order by collections.added asc",
+        )
+        .bind(name)
+        .map(Self::query_history)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(res)
+    }
+
+    async fn collections(&self) -> Result<Vec<(String, i64)>> {
+        let res = sqlx::query_as(
+            "select name, count(1) from collections group by name order by name asc",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(res)
+    }
+
+    async fn top_commands(
+        &self,
+        from: chrono::DateTime<Utc>,
+        to: chrono::DateTime<Utc>,
+    ) -> Result<Vec<(String, i64)>> {
+        let res = sqlx::query_as(
+            "select command, count(1) from history
+                where timestamp >= ?1 and timestamp <= ?2
+                group by command
+                order by count(1) desc, max(timestamp) desc",
+        )
+        .bind(from.timestamp_nanos())
+        .bind(to.timestamp_nanos())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(res)
+    }
+
+    async fn recent_dirs(&self, limit: i64) -> Result<Vec<String>> {
+        let res = sqlx::query_scalar(
+            "select cwd from history group by cwd order by max(timestamp) desc limit ?1",
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(res)
+    }
+
+    async fn add_tag(&self, h: &History, tag: &str) -> Result<()> {
+        debug!("tagging {} with {}", h.id, tag);
+
+        sqlx::query("insert or ignore into tags(history_id, tag) values(?1, ?2)")
+            .bind(h.id.as_str())
+            .bind(tag)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn remove_tag(&self, h: &History, tag: &str) -> Result<()> {
+        debug!("removing tag {} from {:?}", tag, h.command);
+
+        sqlx::query(
+            "delete from tags where tag = ?1
+                and history_id in (select id from history where command = ?2)",
+        )
+        .bind(tag)
+        .bind(h.command.as_str())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn command_tags(&self, commands: &[String]) -> Result<HashMap<String, Vec<String>>> {
+        let mut res = HashMap::<String, Vec<String>>::new();
+
+        // Stay well under SQLite's limit on the number of bound parameters
+        for chunk in commands.chunks(500) {
+            let query = format!(
+                "select distinct history.command, tags.tag from tags
+                    join history on history.id = tags.history_id
+                    where history.command in ({})
+                    order by tags.tag asc",
+                std::iter::repeat("?").take(chunk.len()).join(", ")
+            );
+
+            let rows: Vec<(String, String)> = chunk
+                .iter()
+                .fold(sqlx::query_as(query.as_str()), |query, command| {
+                    query.bind(command)
+                })
+                .fetch_all(&self.pool)
+                .await?;
+
+            for (command, tag) in rows {
+                res.entry(command).or_default().push(tag);
+            }
+        }
+
+        Ok(res)
+    }
+
+    async fn tagged_commands(&self, tag: &str) -> Result<Vec<String>> {
+        let res = sqlx::query_scalar(
+            "select distinct history.command from tags
+                join history on history.id = tags.history_id
+                where tags.tag = ?1",
+        )
+        .bind(tag)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(res)
+    }
+
+    async fn session_history(&self, session: &str) -> Result<Vec<History>> {
+        let res = sqlx::query("select * from history where session = ?1 order by timestamp asc")
+            .bind(session)
+            .map(Self::query_history)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(res)
+    }
+
+    async fn duplicates(&self, window: chrono::Duration) -> Result<Vec<History>> {
+        debug!("finding runs within {} of the one before", window);
+
+        let res = sqlx::query(
+            "select * from (
+                select *, timestamp - lag(timestamp) over (
+                    partition by session, command order by timestamp
+                ) as gap
+                from history
+            )
+            where gap <= ?1
+            order by timestamp asc",
+        )
+        .bind(window.num_nanoseconds().unwrap_or(i64::MAX))
+        .map(Self::query_history)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(res)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use atuin_common::utils::uuid_v4;
     use std::time::{Duration, Instant};
 
     async fn assert_search_eq<'a>(
@@ -432,6 +971,33 @@ mod test {
         return db.save(&history).await;
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_open_read_only() {
+        let dir = std::env::temp_dir().join(format!("atuin-test-{}", uuid_v4()));
+        let path = dir.join("history.db");
+
+        assert!(Sqlite::open_read_only(&path).await.is_err());
+
+        let mut db = Sqlite::new(&path).await.unwrap();
+        new_history_item(&mut db, "ls /home/ellie").await.unwrap();
+
+        let ro = Sqlite::open_read_only(&path).await.unwrap();
+        assert_search_eq(&ro, SearchMode::Prefix, "ls", 1)
+            .await
+            .unwrap();
+        assert!(
+            new_history_item(&mut Sqlite::open_read_only(&path).await.unwrap(), "ls")
+                .await
+                .is_err()
+        );
+
+        let other = dir.join("other.db");
+        fs::write(&other, "not a database").unwrap();
+        assert!(Sqlite::open_read_only(&other).await.is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_search_prefix() {
         let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
@@ -561,6 +1127,597 @@ mod test {
             .unwrap();
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_command_counts() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        for _ in 0..3 {
+            new_history_item(&mut db, "ls").await.unwrap();
+        }
+        new_history_item(&mut db, "cd /").await.unwrap();
+
+        let counts = db
+            .command_counts(&["ls".to_string(), "cd /".to_string(), "vim".to_string()])
+            .await
+            .unwrap();
+
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts["ls"], 3);
+        assert_eq!(counts["cd /"], 1);
+
+        let commands: Vec<String> = (0..1200).map(|i| format!("echo {}", i)).collect();
+        assert!(db.command_counts(&commands).await.unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_range() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        let now = chrono::Utc::now();
+        for days in 0..3 {
+            let history = History::new(
+                now - chrono::Duration::days(days),
+                format!("echo {}", days),
+                String::from("/"),
+                0,
+                1,
+                None,
+                None,
+            );
+            db.save(&history).await.unwrap();
+        }
+
+        let commands: Vec<String> = db
+            .range(
+                now - chrono::Duration::hours(36),
+                now + chrono::Duration::hours(1),
+            )
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|h| h.command)
+            .collect();
+
+        assert_eq!(commands, vec!["echo 1", "echo 0"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_collections() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        new_history_item(&mut db, "make build").await.unwrap();
+        new_history_item(&mut db, "make deploy").await.unwrap();
+
+        let history = db.list(None, false).await.unwrap();
+        let build = history.iter().find(|h| h.command == "make build").unwrap();
+        let deploy = history.iter().find(|h| h.command == "make deploy").unwrap();
+
+        db.add_to_collection("deploy-steps", build).await.unwrap();
+        db.add_to_collection("deploy-steps", deploy).await.unwrap();
+        // Adding twice keeps a single entry, in its original place
+        db.add_to_collection("deploy-steps", build).await.unwrap();
+        db.add_to_collection("other", deploy).await.unwrap();
+
+        let commands: Vec<String> = db
+            .collection("deploy-steps")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|h| h.command)
+            .collect();
+        assert_eq!(commands, vec!["make build", "make deploy"]);
+
+        assert_eq!(
+            db.collections().await.unwrap(),
+            vec![
+                (String::from("deploy-steps"), 2),
+                (String::from("other"), 1)
+            ]
+        );
+
+        db.remove_from_collection("deploy-steps", build)
+            .await
+            .unwrap();
+        let commands: Vec<String> = db
+            .collection("deploy-steps")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|h| h.command)
+            .collect();
+        assert_eq!(commands, vec!["make deploy"]);
+
+        assert!(db.collection("missing").await.unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_recent_dirs() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+
+        let now = chrono::Utc::now();
+        for (ago, cwd) in [(3, "/src"), (2, "/tmp"), (1, "/src"), (4, "/etc")] {
+            let h = History::new(
+                now - chrono::Duration::minutes(ago),
+                String::from("ls"),
+                String::from(cwd),
+                0,
+                1,
+                None,
+                None,
+            );
+            db.save(&h).await.unwrap();
+        }
+
+        assert_eq!(
+            db.recent_dirs(10).await.unwrap(),
+            vec!["/src", "/tmp", "/etc"]
+        );
+        assert_eq!(db.recent_dirs(1).await.unwrap(), vec!["/src"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_search_indexed() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        new_history_item(&mut db, "git status").await.unwrap();
+        new_history_item(&mut db, "git stash").await.unwrap();
+        new_history_item(&mut db, "git log --oneline status")
+            .await
+            .unwrap();
+        new_history_item(&mut db, "cargo build").await.unwrap();
+
+        let commands = |results: Vec<History>| -> Vec<String> {
+            results.into_iter().map(|h| h.command).collect()
+        };
+        let search = |query: &'static str| {
+            let db = &db;
+            async move {
+                commands(
+                    db.search(None, SearchMode::FullTextIndexed, query)
+                        .await
+                        .unwrap(),
+                )
+            }
+        };
+
+        // The shorter command matches status better
+        assert_eq!(
+            search("status").await,
+            vec!["git status", "git log --oneline status"]
+        );
+        assert_eq!(search("git st").await.len(), 3);
+        assert_eq!(search("\"carg").await, vec!["cargo build"]);
+        assert!(search("build status").await.is_empty());
+
+        // Without any words to look up in the index, it falls back to a
+        // plain full text search
+        assert_eq!(search("--").await, vec!["git log --oneline status"]);
+        assert_eq!(search("").await.len(), 4);
+
+        db.rebuild_index().await.unwrap();
+        assert_eq!(search("oneline").await, vec!["git log --oneline status"]);
+
+        // A vacuum is free to renumber the rows of the history table, which
+        // the index mustn't depend on
+        sqlx::query("update history set rowid = rowid + 100")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(search("oneline").await, vec!["git log --oneline status"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_search_fixed() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        for command in [
+            "ps aux | grep -v grep",
+            "grep -v x",
+            "echo 100%_done",
+            "echo 1000 done",
+        ] {
+            new_history_item(&mut db, command).await.unwrap();
+        }
+
+        let search = |query: &'static str| {
+            let db = &db;
+            async move {
+                let mut commands: Vec<String> = db
+                    .search_fixed(None, query, &Filters::default())
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|h| h.command)
+                    .collect();
+                commands.sort();
+                commands
+            }
+        };
+
+        assert_eq!(search("| grep -v").await, ["ps aux | grep -v grep"]);
+        assert_eq!(search("grep -v").await.len(), 2);
+        assert_eq!(search("100%_").await, ["echo 100%_done"]);
+        assert!(search("GREP").await.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_search_regex() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        for command in [
+            "sudo rm -rf /tmp/build",
+            "sudo apt install rmlint",
+            "git push",
+            "git pull --rebase",
+            "git status",
+            "Git pull",
+        ] {
+            new_history_item(&mut db, command).await.unwrap();
+        }
+
+        assert_search_commands(
+            &db,
+            SearchMode::Regex,
+            "^git (push|pull)",
+            vec!["git pull --rebase", "git push"],
+        )
+        .await;
+        assert_search_commands(
+            &db,
+            SearchMode::Regex,
+            "^sudo .*rm",
+            vec!["sudo apt install rmlint", "sudo rm -rf /tmp/build"],
+        )
+        .await;
+        assert_search_eq(&db, SearchMode::Regex, "(?i)^git pull", 2)
+            .await
+            .unwrap();
+        assert_search_eq(&db, SearchMode::Regex, "", 6)
+            .await
+            .unwrap();
+
+        // The limit applies to matches, newest first
+        let limited = db.search(Some(1), SearchMode::Regex, "^git").await.unwrap();
+        assert_eq!(limited.len(), 1);
+        assert_eq!(limited[0].command, "git status");
+
+        let err = db
+            .search(None, SearchMode::Regex, "(git")
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "invalid regex");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_search_filtered() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        let start = Utc.timestamp(1_600_000_000, 0);
+
+        let runs = [
+            ("make", "/src", 0, "laptop:me"),
+            ("make test", "/src/app", 1, "prod-1:deploy"),
+            ("make lint", "/src_old", 1, "prod-10:deploy"),
+            ("make deploy", "/srv/100%", 2, "prod-1"),
+            // Only its latest run counts, which was elsewhere
+            ("make clean", "/src", 0, "laptop:me"),
+            ("make clean", "/tmp", 0, "laptop:me"),
+        ];
+        let mut saved = Vec::new();
+        for (i, &(command, cwd, exit, hostname)) in runs.iter().enumerate() {
+            let h = History::new(
+                start + chrono::Duration::seconds(i as i64),
+                command.to_string(),
+                cwd.to_string(),
+                exit,
+                1,
+                Some(String::from("session")),
+                Some(hostname.to_string()),
+            );
+            db.save(&h).await.unwrap();
+            saved.push(h);
+        }
+
+        // A tag on any run tags the command
+        db.add_tag(&saved[3], "prod").await.unwrap();
+        db.add_tag(&saved[4], "prod").await.unwrap();
+        db.add_tag(&saved[4], "old").await.unwrap();
+
+        let search = |mode: Option<SearchMode>, filters: Filters| {
+            let db = &db;
+            async move {
+                let results = match mode {
+                    Some(mode) => db.search_filtered(None, mode, "make", &filters).await,
+                    None => db.search_fixed(None, "make", &filters).await,
+                };
+                let mut commands: Vec<String> =
+                    results.unwrap().into_iter().map(|h| h.command).collect();
+                commands.sort();
+                commands
+            }
+        };
+
+        for mode in [
+            Some(SearchMode::Prefix),
+            Some(SearchMode::Fuzzy),
+            Some(SearchMode::FullTextIndexed),
+            Some(SearchMode::Regex),
+            None,
+        ] {
+            let cwd = |cwd: &str, subdirs: bool| Filters {
+                cwd: Some(cwd.to_string()),
+                subdirs,
+                ..Filters::default()
+            };
+
+            assert_eq!(search(mode, cwd("/src", false)).await, ["make"]);
+            assert_eq!(search(mode, cwd("/src", true)).await, ["make", "make test"]);
+            // As with the directory on its own, a trailing slash has to match
+            // exactly
+            assert_eq!(search(mode, cwd("/src/", true)).await, ["make test"]);
+            assert_eq!(
+                search(mode, cwd("/srv/1%", true)).await,
+                Vec::<String>::new()
+            );
+
+            let filters = Filters {
+                exit: Some(1),
+                exclude_cwd: Some(String::from("/src_old")),
+                ..Filters::default()
+            };
+            assert_eq!(search(mode, filters).await, ["make test"]);
+
+            let filters = Filters {
+                exclude_exit: Some(0),
+                cwd: Some(String::from("/srv/100%")),
+                ..Filters::default()
+            };
+            assert_eq!(search(mode, filters).await, ["make deploy"]);
+
+            let host = |host: &str| Filters {
+                host: Some(host.to_string()),
+                ..Filters::default()
+            };
+            assert_eq!(
+                search(mode, host("prod-1")).await,
+                ["make deploy", "make test"]
+            );
+            assert_eq!(search(mode, host("prod-1:deploy")).await, ["make test"]);
+            assert_eq!(search(mode, host("prod")).await, Vec::<String>::new());
+
+            let tags = |tags: &[&str]| Filters {
+                tags: tags.iter().map(ToString::to_string).collect(),
+                ..Filters::default()
+            };
+            assert_eq!(
+                search(mode, tags(&["prod"])).await,
+                ["make clean", "make deploy"]
+            );
+            assert_eq!(search(mode, tags(&["prod", "old"])).await, ["make clean"]);
+
+            let filters = Filters {
+                before: Some(start + chrono::Duration::seconds(1)),
+                ..Filters::default()
+            };
+            assert_eq!(search(mode, filters).await, ["make", "make test"]);
+
+            // Only the latest run of make clean counts, which is after
+            let filters = Filters {
+                after: Some(start + chrono::Duration::seconds(4)),
+                ..Filters::default()
+            };
+            assert_eq!(search(mode, filters).await, ["make clean"]);
+
+            assert_eq!(search(mode, Filters::default()).await.len(), 5);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_duplicates() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        let start = Utc.timestamp(1_600_000_000, 0);
+
+        let runs = [
+            ("ls", 0, "a"),
+            ("ls", 200, "a"),
+            ("ls", 400, "a"),
+            // Too long after the last
+            ("ls", 5000, "a"),
+            // Not the same session
+            ("ls", 5100, "b"),
+            ("make", 5200, "a"),
+            ("make", 5300, "a"),
+        ];
+        for (command, millis, session) in runs {
+            let h = History::new(
+                start + chrono::Duration::milliseconds(millis),
+                command.to_string(),
+                String::from("/"),
+                0,
+                1,
+                Some(session.to_string()),
+                None,
+            );
+            db.save(&h).await.unwrap();
+        }
+
+        let duplicates: Vec<(String, i64)> = db
+            .duplicates(chrono::Duration::seconds(1))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|h| (h.command, (h.timestamp - start).num_milliseconds()))
+            .collect();
+
+        assert_eq!(
+            duplicates,
+            [
+                (String::from("ls"), 200),
+                (String::from("ls"), 400),
+                (String::from("make"), 5300)
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reindex() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+
+        let mut kept = Vec::new();
+        for command in ["make deploy", "git push"] {
+            let h = History::new(
+                chrono::Utc::now(),
+                String::from(command),
+                String::from("/"),
+                0,
+                1,
+                None,
+                None,
+            );
+            db.save(&h).await.unwrap();
+            db.add_tag(&h, "deploy").await.unwrap();
+            db.add_to_collection("steps", &h).await.unwrap();
+            kept.push(h);
+        }
+
+        // Deleted behind the database's back, as an older version might have
+        sqlx::query("delete from history where id = ?1")
+            .bind(kept[1].id.as_str())
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let steps = std::sync::Mutex::new(Vec::new());
+        let reindexed = db
+            .reindex(&|step| steps.lock().unwrap().push(step))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            reindexed,
+            Reindexed {
+                orphaned_tags: 1,
+                orphaned_collection_entries: 1
+            }
+        );
+        assert_eq!(steps.lock().unwrap().len(), 5);
+        assert_eq!(db.tagged_commands("deploy").await.unwrap(), ["make deploy"]);
+        assert_eq!(
+            db.collections().await.unwrap(),
+            [(String::from("steps"), 1)]
+        );
+        assert_eq!(
+            db.search(None, SearchMode::FullTextIndexed, "deploy")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_top_commands() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+
+        let now = chrono::Utc::now();
+        for (ago, command) in [(5, "ls"), (4, "make"), (3, "ls"), (2, "vim"), (1, "make")] {
+            let h = History::new(
+                now - chrono::Duration::days(ago),
+                String::from(command),
+                String::from("/"),
+                0,
+                1,
+                None,
+                None,
+            );
+            db.save(&h).await.unwrap();
+        }
+
+        let top = |commands: &[(&str, i64)]| -> Vec<(String, i64)> {
+            commands.iter().map(|(c, n)| (c.to_string(), *n)).collect()
+        };
+
+        assert_eq!(
+            db.top_commands(now - chrono::Duration::days(10), now)
+                .await
+                .unwrap(),
+            top(&[("make", 2), ("ls", 2), ("vim", 1)])
+        );
+        assert_eq!(
+            db.top_commands(
+                now - chrono::Duration::days(10),
+                now - chrono::Duration::hours(36)
+            )
+            .await
+            .unwrap(),
+            top(&[("ls", 2), ("vim", 1), ("make", 1)])
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_tags() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        new_history_item(&mut db, "make deploy").await.unwrap();
+        new_history_item(&mut db, "make deploy").await.unwrap();
+        new_history_item(&mut db, "kubectl logs").await.unwrap();
+
+        let history = db.list(None, false).await.unwrap();
+        let deploys: Vec<&History> = history
+            .iter()
+            .filter(|h| h.command == "make deploy")
+            .collect();
+        let logs = history
+            .iter()
+            .find(|h| h.command == "kubectl logs")
+            .unwrap();
+
+        db.add_tag(deploys[0], "deploy").await.unwrap();
+        db.add_tag(deploys[1], "oncall").await.unwrap();
+        db.add_tag(logs, "oncall").await.unwrap();
+        // Tagging twice keeps a single tag
+        db.add_tag(logs, "oncall").await.unwrap();
+
+        let commands = vec![String::from("make deploy"), String::from("kubectl logs")];
+        let tags = db.command_tags(&commands).await.unwrap();
+        assert_eq!(tags["make deploy"], vec!["deploy", "oncall"]);
+        assert_eq!(tags["kubectl logs"], vec!["oncall"]);
+
+        let mut oncall = db.tagged_commands("oncall").await.unwrap();
+        oncall.sort();
+        assert_eq!(oncall, vec!["kubectl logs", "make deploy"]);
+
+        // Removing goes for every run of the command, whichever was tagged
+        db.remove_tag(deploys[0], "oncall").await.unwrap();
+        assert_eq!(
+            db.tagged_commands("oncall").await.unwrap(),
+            vec!["kubectl logs"]
+        );
+        assert!(db.tagged_commands("missing").await.unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_session_history() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+
+        let now = chrono::Utc::now();
+        for (ago, command, session) in [(1, "make", "a"), (3, "cd src", "a"), (2, "ls", "b")] {
+            let h = History::new(
+                now - chrono::Duration::minutes(ago),
+                String::from(command),
+                String::from("/src"),
+                0,
+                1,
+                Some(String::from(session)),
+                None,
+            );
+            db.save(&h).await.unwrap();
+        }
+
+        let commands: Vec<String> = db
+            .session_history("a")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|h| h.command)
+            .collect();
+        assert_eq!(commands, vec!["cd src", "make"]);
+
+        assert!(db.session_history("missing").await.unwrap().is_empty());
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_search_bench_dupes() {
         let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
@@ -45,3 +45,43 @@ impl History {
         }
     }
 }
+
+impl History {
+    /// Whether this command was ran with elevated privileges, either via sudo
+    /// or doas, or from a root shell
+    pub fn is_privileged(&self) -> bool {
+        let command = self.command.trim_start();
+
+        command.starts_with("sudo ")
+            || command.starts_with("doas ")
+            || self.hostname.ends_with(":root")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+
+    fn history(command: &str, hostname: &str) -> History {
+        History::new(
+            chrono::Utc::now(),
+            command.to_string(),
+            "/".to_string(),
+            0,
+            0,
+            None,
+            Some(hostname.to_string()),
+        )
+    }
+
+    #[test]
+    fn is_privileged() {
+        assert!(history("sudo rm -rf /tmp/foo", "box:ellie").is_privileged());
+        assert!(history("  doas reboot", "box:ellie").is_privileged());
+        assert!(history("ls", "box:root").is_privileged());
+
+        assert!(!history("ls", "box:ellie").is_privileged());
+        assert!(!history("sudoedit /etc/hosts", "box:ellie").is_privileged());
+        assert!(!history("echo sudo ", "box:ellie").is_privileged());
+    }
+}
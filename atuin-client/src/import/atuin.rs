@@ -0,0 +1,191 @@
+// history exported with `atuin search --export-atuin`, for moving a slice of
+// history to another machine
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use eyre::{eyre, Result};
+
+use super::{count_lines, Importer};
+use crate::history::History;
+
+/// One line of an export. This is kept apart from `History`, so the format
+/// only changes on purpose. New fields must be optional, so older exports
+/// can still be imported
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct AtuinEntry {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub duration: i64,
+    pub exit: i64,
+    pub command: String,
+    pub cwd: String,
+    pub session: String,
+    pub hostname: String,
+}
+
+impl From<&History> for AtuinEntry {
+    fn from(h: &History) -> Self {
+        Self {
+            id: h.id.clone(),
+            timestamp: h.timestamp,
+            duration: h.duration,
+            exit: h.exit,
+            command: h.command.clone(),
+            cwd: h.cwd.clone(),
+            session: h.session.clone(),
+            hostname: h.hostname.clone(),
+        }
+    }
+}
+
+impl From<AtuinEntry> for History {
+    fn from(e: AtuinEntry) -> Self {
+        Self {
+            id: e.id,
+            timestamp: e.timestamp,
+            duration: e.duration,
+            exit: e.exit,
+            command: e.command,
+            cwd: e.cwd,
+            session: e.session,
+            hostname: e.hostname,
+        }
+    }
+}
+
+/// Write history as JSON, one entry per line, in the order given. Entries
+/// keep their ids, so importing the same export twice doesn't duplicate them
+pub fn export<'a>(mut w: impl Write, history: impl IntoIterator<Item = &'a History>) -> Result<()> {
+    for h in history {
+        serde_json::to_writer(&mut w, &AtuinEntry::from(h))?;
+        writeln!(w)?;
+    }
+
+    w.flush()?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct Atuin<R> {
+    file: BufReader<R>,
+    strbuf: String,
+    loc: usize,
+}
+
+impl<R: Read + Seek> Atuin<R> {
+    fn new(r: R) -> Result<Self> {
+        let mut buf = BufReader::new(r);
+        let loc = count_lines(&mut buf)?;
+
+        Ok(Self {
+            file: buf,
+            strbuf: String::new(),
+            loc,
+        })
+    }
+}
+
+impl Importer for Atuin<File> {
+    const NAME: &'static str = "atuin";
+
+    // There's nowhere exports are kept, so the file always has to be given
+    fn histpath() -> Result<PathBuf> {
+        Err(eyre!(
+            "give the file written by `atuin search --export-atuin` to import"
+        ))
+    }
+
+    fn parse(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(File::open(path)?)
+    }
+}
+
+impl<R: Read> Iterator for Atuin<R> {
+    type Item = Result<History>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.strbuf.clear();
+            match self.file.read_line(&mut self.strbuf) {
+                Ok(0) => return None,
+                Ok(_) => (),
+                Err(e) => return Some(Err(eyre!("failed to read line: {}", e))),
+            }
+
+            self.loc = self.loc.saturating_sub(1);
+
+            if !self.strbuf.trim().is_empty() {
+                break;
+            }
+        }
+
+        Some(
+            serde_json::from_str::<AtuinEntry>(&self.strbuf)
+                .map(History::from)
+                .map_err(|e| eyre!("invalid entry in atuin export: {}", e)),
+        )
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.loc))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{export, Atuin};
+    use crate::history::History;
+
+    #[test]
+    fn round_trip() {
+        let history = vec![
+            History::new(
+                Utc.timestamp(1_613_322_469, 123_456_789),
+                String::from("cargo install atuin"),
+                String::from("/home/me"),
+                0,
+                1_000_000_000,
+                Some(String::from("session-1")),
+                Some(String::from("box:me")),
+            ),
+            History::new(
+                Utc.timestamp(1_613_322_480, 0),
+                String::from("cat <<EOF\n\"quoted\" ✓\nEOF"),
+                String::from("/tmp"),
+                130,
+                -1,
+                Some(String::from("session-1")),
+                Some(String::from("box:me")),
+            ),
+        ];
+
+        let mut exported = Vec::new();
+        export(&mut exported, &history).unwrap();
+        assert_eq!(String::from_utf8_lossy(&exported).lines().count(), 2);
+
+        // Blank lines, like a trailing one, are skipped
+        exported.push(b'\n');
+
+        let imported: Vec<History> = Atuin::new(Cursor::new(exported))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(imported, history);
+    }
+
+    #[test]
+    fn invalid_entry() {
+        let mut entries = Atuin::new(Cursor::new("{\"command\": \"ls\"}\n")).unwrap();
+        assert!(entries.next().unwrap().is_err());
+        assert!(entries.next().is_none());
+    }
+}
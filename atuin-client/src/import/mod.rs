@@ -5,6 +5,7 @@ use eyre::Result;
 
 use crate::history::History;
 
+pub mod atuin;
 pub mod bash;
 pub mod fish;
 pub mod resh;
@@ -6,11 +6,14 @@ extern crate log;
 #[macro_use]
 extern crate serde_derive;
 
+pub mod alias;
 pub mod api_client;
 pub mod database;
 pub mod encryption;
 pub mod history;
 pub mod import;
 pub mod ordering;
+pub mod query;
+pub mod redact;
 pub mod settings;
 pub mod sync;
@@ -1,3 +1,4 @@
+use super::alias::Aliases;
 use super::history::History;
 use super::settings::SearchMode;
 use minspan::minspan;
@@ -30,3 +31,163 @@ where
     });
     r
 }
+
+/// How much shorter a match counts as for each query character found at the
+/// start of a segment
+const BOUNDARY_BONUS: i64 = 2;
+
+/// Fuzzy rank results again, favouring matches that line up with the segments
+/// of a command, as split by whitespace and any of `boundaries`. With "r/h",
+/// `rm /home` then beats `rm -rf ./tmp/shell`. The sort is stable, so entries
+/// that score the same keep their order
+pub fn reorder_boundaries(query: &str, boundaries: &str, res: Vec<History>) -> Vec<History> {
+    if boundaries.is_empty() {
+        return res;
+    }
+
+    let mut r = res;
+    let qvec: Vec<char> = query.chars().collect();
+    r.sort_by_cached_key(|h| boundary_score(&qvec, &h.command, boundaries));
+    r
+}
+
+/// Lower is better. Results without a match score worst of all
+#[allow(clippy::cast_possible_wrap)]
+fn boundary_score(query: &[char], command: &str, boundaries: &str) -> i64 {
+    let command: Vec<char> = command.chars().collect();
+    let (from, to) = match minspan::span(&query.to_vec(), &command) {
+        Some(x) => x,
+        None => return i64::MAX,
+    };
+
+    let starts_segment =
+        |i: usize| i == 0 || command[i - 1].is_whitespace() || boundaries.contains(command[i - 1]);
+
+    // The span starts and ends on a query character, so walking it greedily
+    // finds a full match
+    let mut bonus = 0;
+    let mut q = query.iter().peekable();
+    for (i, c) in command.iter().enumerate().take(to + 1).skip(from) {
+        if q.peek() == Some(&c) {
+            q.next();
+            if starts_segment(i) {
+                bonus += BOUNDARY_BONUS;
+            }
+        }
+    }
+
+    (1 + to - from) as i64 - bonus
+}
+
+/// How closely a command matches a fuzzy query, from 0 to 1. Each term of the
+/// query scores its length over the length of the shortest stretch of the
+/// command containing it, so 1 means the characters were found side by side.
+/// The score is the average over the terms that match. Negated terms and
+/// search operators are left out, and case is ignored, like the search itself
+pub fn fuzzy_score(query: &str, command: &str) -> f64 {
+    let command: Vec<char> = command.to_lowercase().chars().collect();
+
+    let scores: Vec<f64> = query
+        .to_lowercase()
+        .split_whitespace()
+        .filter(|term| *term != "|" && !term.starts_with('!'))
+        .map(|term| {
+            term.trim_start_matches(|c| c == '^' || c == '\'')
+                .trim_end_matches('$')
+                .chars()
+                .collect::<Vec<char>>()
+        })
+        .filter(|term| !term.is_empty())
+        .filter_map(|term| {
+            let (from, to) = minspan::span(&term, &command)?;
+            Some(term.len() as f64 / (1 + to - from) as f64)
+        })
+        .collect();
+
+    if scores.is_empty() {
+        return 0.0;
+    }
+
+    scores.iter().sum::<f64>() / scores.len() as f64
+}
+
+/// The best [`fuzzy_score`] of a command as it is, or with the alias at its
+/// start expanded or put back, if there are aliases
+pub fn alias_fuzzy_score(query: &str, command: &str, aliases: Option<&Aliases>) -> f64 {
+    let score = fuzzy_score(query, command);
+
+    aliases.map_or(score, |aliases| {
+        score
+            .max(fuzzy_score(query, &aliases.resolve(command)))
+            .max(fuzzy_score(query, &aliases.contract(command)))
+    })
+}
+
+/// Drop results scoring under `min_score`, as given by [`alias_fuzzy_score`]
+pub fn filter_fuzzy(
+    query: &str,
+    min_score: f64,
+    aliases: Option<&Aliases>,
+    res: Vec<History>,
+) -> Vec<History> {
+    if min_score <= 0.0 || query.trim().is_empty() {
+        return res;
+    }
+
+    res.into_iter()
+        .filter(|h| alias_fuzzy_score(query, &h.command, aliases) >= min_score)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{alias_fuzzy_score, boundary_score, fuzzy_score};
+    use crate::alias::Aliases;
+
+    fn score(query: &str, command: &str) -> i64 {
+        boundary_score(&query.chars().collect::<Vec<_>>(), command, "/-_")
+    }
+
+    #[test]
+    fn boundaries_rank_higher() {
+        assert!(score("r/h", "rm /home") < score("r/h", "rm -rf ./tmp/shell"));
+        assert!(score("sb", "src/bin") < score("sb", "xsbx"));
+        assert_eq!(score("xyz", "ls"), i64::MAX);
+    }
+
+    #[test]
+    fn fuzzy_scores() {
+        assert!((fuzzy_score("curl", "curl example.com") - 1.0).abs() < f64::EPSILON);
+        assert!((fuzzy_score("curl", "corburl") - 4.0 / 7.0).abs() < f64::EPSILON);
+        assert!((fuzzy_score("^Git 'st", "git status") - 1.0).abs() < f64::EPSILON);
+        assert!((fuzzy_score("ls !ellie", "ls /home") - 1.0).abs() < f64::EPSILON);
+        assert!(fuzzy_score("xyz", "ls").abs() < f64::EPSILON);
+        assert!(fuzzy_score("", "ls").abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn alias_fuzzy_scores() {
+        let aliases = Aliases::new(HashMap::from([(
+            "gco".to_string(),
+            "git checkout".to_string(),
+        )]));
+
+        assert!(fuzzy_score("git checkout", "gco main") < 1.0);
+        assert!(
+            (alias_fuzzy_score("git checkout", "gco main", Some(&aliases)) - 1.0).abs()
+                < f64::EPSILON
+        );
+        assert!(
+            (alias_fuzzy_score("gco", "git checkout main", Some(&aliases)) - 1.0).abs()
+                < f64::EPSILON
+        );
+        assert!(
+            (alias_fuzzy_score("gco", "git checkout main", None)
+                - fuzzy_score("gco", "git checkout main"))
+            .abs()
+                < f64::EPSILON
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use chrono::{DateTime, Utc};
+use sqlx::{query::Query, sqlite::SqliteArguments, Sqlite};
+
+/// Filters a search can be narrowed down by in the database, rather than by
+/// going through every result afterwards. Anything left as None matches
+/// everything
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Filters {
+    pub exit: Option<i64>,
+    pub exclude_exit: Option<i64>,
+    pub cwd: Option<String>,
+    /// Also match directories beneath `cwd`
+    pub subdirs: bool,
+    pub exclude_cwd: Option<String>,
+    /// Only commands ran on this host, which can leave off the user that
+    /// hostnames are recorded with
+    pub host: Option<String>,
+    /// Only commands last ran at or before this time
+    pub before: Option<DateTime<Utc>>,
+    /// Only commands last ran at or after this time
+    pub after: Option<DateTime<Utc>>,
+    /// Only commands that have finished, so their duration is known
+    pub finished: bool,
+    /// Only commands tagged with every one of these, on any of their runs
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Param {
+    Int(i64),
+    Text(String),
+}
+
+/// Predicates joined with `and` into a `where` clause, along with the
+/// parameters they're bound to. Parameters are numbered from `first`, so the
+/// predicates can be added after a query with parameters of its own
+#[derive(Debug)]
+pub(crate) struct Predicates {
+    first: usize,
+    clauses: Vec<String>,
+    params: Vec<Param>,
+}
+
+impl Predicates {
+    pub fn new(first: usize) -> Self {
+        Self {
+            first,
+            clauses: Vec::new(),
+            params: Vec::new(),
+        }
+    }
+
+    /// The predicates for the given filters
+    pub fn from_filters(first: usize, filters: &Filters) -> Self {
+        let mut predicates = Self::new(first);
+
+        if let Some(exit) = filters.exit {
+            predicates.push("exit = ?", Param::Int(exit));
+        }
+
+        if let Some(exit) = filters.exclude_exit {
+            predicates.push("exit != ?", Param::Int(exit));
+        }
+
+        if let Some(cwd) = &filters.exclude_cwd {
+            predicates.push("cwd != ?", Param::Text(cwd.clone()));
+        }
+
+        if let Some(cwd) = &filters.cwd {
+            predicates.push("cwd = ?", Param::Text(cwd.clone()));
+
+            // Or with the directory and a slash at the start. This is
+            // compared as is, as `like` would treat any % or _ in the
+            // directory as wildcards
+            if filters.subdirs {
+                let parent = format!("{}/", cwd.trim_end_matches('/'));
+                predicates.or("substr(cwd, 1, length(?)) = ?", Param::Text(parent));
+            }
+        }
+
+        if let Some(host) = &filters.host {
+            predicates.push("hostname = ?", Param::Text(host.clone()));
+
+            // Or with the user after it, and the colon it's joined by
+            let prefix = format!("{}:", host);
+            predicates.or("substr(hostname, 1, length(?)) = ?", Param::Text(prefix));
+        }
+
+        if let Some(before) = filters.before {
+            predicates.push("timestamp <= ?", Param::Int(before.timestamp_nanos()));
+        }
+
+        if let Some(after) = filters.after {
+            predicates.push("timestamp >= ?", Param::Int(after.timestamp_nanos()));
+        }
+
+        if filters.finished {
+            predicates.clauses.push(String::from("duration != -1"));
+        }
+
+        for tag in &filters.tags {
+            predicates.push(
+                "command in (select history.command from tags
+                    join history on history.id = tags.history_id
+                    where tags.tag = ?)",
+                Param::Text(tag.clone()),
+            );
+        }
+
+        predicates
+    }
+
+    /// Add a predicate, with each `?` in it standing for the parameter
+    fn push(&mut self, clause: &str, param: Param) {
+        let clause = self.number(clause);
+        self.clauses.push(clause);
+        self.params.push(param);
+    }
+
+    /// Widen the last predicate added to also match this one
+    fn or(&mut self, clause: &str, param: Param) {
+        let clause = self.number(clause);
+        if let Some(last) = self.clauses.last_mut() {
+            *last = format!("({} or {})", last, clause);
+        }
+        self.params.push(param);
+    }
+
+    fn number(&self, clause: &str) -> String {
+        clause.replace('?', &format!("?{}", self.first + self.params.len()))
+    }
+
+    /// The `where` clause, or nothing when there are no predicates
+    pub fn where_clause(&self) -> String {
+        if self.clauses.is_empty() {
+            String::new()
+        } else {
+            format!("where {}", self.clauses.join(" and "))
+        }
+    }
+
+    /// Bind the parameters, after any the query was already bound to
+    pub fn bind<'q>(
+        &'q self,
+        query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    ) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+        self.params.iter().fold(query, |query, param| match param {
+            Param::Int(i) => query.bind(*i),
+            Param::Text(s) => query.bind(s.as_str()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Filters, Predicates};
+
+    #[test]
+    fn where_clause() {
+        assert_eq!(
+            Predicates::from_filters(1, &Filters::default()).where_clause(),
+            ""
+        );
+
+        let filters = Filters {
+            exit: Some(1),
+            cwd: Some(String::from("/src/")),
+            subdirs: true,
+            ..Filters::default()
+        };
+        let predicates = Predicates::from_filters(3, &filters);
+        assert_eq!(
+            predicates.where_clause(),
+            "where exit = ?3 and (cwd = ?4 or substr(cwd, 1, length(?5)) = ?5)"
+        );
+        assert_eq!(predicates.params.len(), 3);
+    }
+}
@@ -1,16 +1,22 @@
 use fs_err::{create_dir_all, File};
+use std::collections::HashMap;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+use chrono::format::{Item, StrftimeItems};
 use chrono::prelude::*;
 use chrono::Utc;
 use config::{Config, Environment, File as ConfigFile, FileFormat};
 use eyre::{eyre, Context, Result};
 use parse_duration::parse;
 
+use crate::alias::Aliases;
+use crate::redact::EnvMask;
+
 pub const HISTORY_PAGE_SIZE: i64 = 100;
 
-#[derive(Clone, Debug, Deserialize, Copy)]
+#[derive(Clone, Debug, Deserialize, Copy, PartialEq, Eq)]
 pub enum SearchMode {
     #[serde(rename = "prefix")]
     Prefix,
@@ -20,6 +26,30 @@ pub enum SearchMode {
 
     #[serde(rename = "fuzzy")]
     Fuzzy,
+
+    /// Words anywhere in the command, found with the full text index and
+    /// ranked by how well they match
+    #[serde(rename = "fulltext-indexed")]
+    FullTextIndexed,
+
+    /// The query is a regular expression, matched anywhere in the command
+    #[serde(rename = "regex")]
+    Regex,
+}
+
+impl FromStr for SearchMode {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "prefix" => Ok(SearchMode::Prefix),
+            "fulltext" => Ok(SearchMode::FullText),
+            "fuzzy" => Ok(SearchMode::Fuzzy),
+            "fulltext-indexed" => Ok(SearchMode::FullTextIndexed),
+            "regex" => Ok(SearchMode::Regex),
+            _ => Err(eyre!("invalid search mode: {}", s)),
+        }
+    }
 }
 
 // FIXME: Can use upstream Dialect enum if https://github.com/stevedonovan/chrono-english/pull/16 is merged
@@ -53,6 +83,124 @@ pub enum Style {
     Compact,
 }
 
+impl Default for Style {
+    fn default() -> Self {
+        Self::Auto
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Copy)]
+pub enum RequeryMode {
+    #[serde(rename = "token")]
+    Token,
+
+    #[serde(rename = "command")]
+    Command,
+}
+
+/// How to order commands that have been ran as often as each other
+#[derive(Clone, Debug, Deserialize, Copy, PartialEq, Eq)]
+pub enum Tiebreak {
+    #[serde(rename = "recency")]
+    Recency,
+
+    #[serde(rename = "alphabetical")]
+    Alphabetical,
+}
+
+/// What the search UI shows before anything has been typed
+#[derive(Clone, Debug, Deserialize, Copy, PartialEq, Eq)]
+pub enum EmptyQuery {
+    /// The most recent commands
+    #[serde(rename = "recent")]
+    Recent,
+
+    /// Nothing at all
+    #[serde(rename = "blank")]
+    Blank,
+}
+
+impl Default for EmptyQuery {
+    fn default() -> Self {
+        Self::Recent
+    }
+}
+
+/// What the numbers for Alt-<num> in the search UI count from
+#[derive(Clone, Debug, Deserialize, Copy, PartialEq, Eq)]
+pub enum JumpMode {
+    /// Results above the selected one
+    #[serde(rename = "relative")]
+    Relative,
+
+    /// Results on screen, from the bottom of the list
+    #[serde(rename = "absolute")]
+    Absolute,
+}
+
+impl Default for JumpMode {
+    fn default() -> Self {
+        Self::Relative
+    }
+}
+
+/// What moving past the first or last search result does
+#[derive(Clone, Debug, Deserialize, Copy, PartialEq, Eq)]
+pub enum EdgeBehavior {
+    /// Stay on the result at the edge
+    #[serde(rename = "stop")]
+    Stop,
+
+    /// Carry on from the result at the other edge
+    #[serde(rename = "wrap")]
+    Wrap,
+
+    /// Clear the selection. Moving again starts from the newest result
+    #[serde(rename = "deselect")]
+    Deselect,
+}
+
+/// A column of the search UI results
+#[derive(Clone, Debug, Deserialize, Copy, PartialEq, Eq)]
+pub enum Column {
+    #[serde(rename = "index")]
+    Index,
+
+    #[serde(rename = "duration")]
+    Duration,
+
+    #[serde(rename = "ago")]
+    Ago,
+
+    #[serde(rename = "exit")]
+    Exit,
+
+    #[serde(rename = "cwd")]
+    Cwd,
+
+    #[serde(rename = "host")]
+    Host,
+
+    #[serde(rename = "count")]
+    Count,
+
+    #[serde(rename = "terms")]
+    Terms,
+
+    #[serde(rename = "command")]
+    Command,
+}
+
+impl Column {
+    /// Numbers line up best on the right, text on the left
+    pub const fn right_aligned(self) -> bool {
+        matches!(
+            self,
+            Self::Index | Self::Duration | Self::Ago | Self::Exit | Self::Count | Self::Terms
+        )
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct Settings {
     pub dialect: Dialect,
@@ -64,11 +212,55 @@ pub struct Settings {
     pub key_path: String,
     pub session_path: String,
     pub search_mode: SearchMode,
+    pub requery_mode: RequeryMode,
+    pub highlight_privileged: bool,
+    pub render_ansi: bool,
+    pub show_host_marker: bool,
+    pub dangerous_patterns: Vec<String>,
+    pub search_timeout: String,
+    pub aliases: HashMap<String, String>,
+    pub min_command_length: usize,
+    pub trim_commands: bool,
+    pub time_format: String,
+    pub cd_commands: Vec<String>,
+    pub hide_unknown_duration: bool,
+    pub show_selection_index: bool,
+    pub redact_patterns: Vec<String>,
+    pub cwd_include_subdirs: bool,
+    pub ui_columns: Vec<Column>,
+    pub fuzzy_boundaries: String,
+    pub fuzzy_min_score: f64,
+    pub fuzzy_aliases: bool,
+    pub frequency_tiebreak: Tiebreak,
+    pub never_execute: bool,
+    pub edge_behavior: EdgeBehavior,
+    pub empty_query_behavior: EmptyQuery,
+    pub show_matching_line: bool,
+    pub mask_env_values: bool,
+    pub mask_env_pattern: String,
+    pub share_format: String,
+    pub jump_mode: JumpMode,
+    pub max_display_length: usize,
+    pub incremental_search: bool,
+    pub locale: String,
+    pub group_separators: bool,
+    pub auto_refresh: String,
+    pub cluster_results: bool,
     // This is automatically loaded when settings is created. Do not set in
     // config! Keep secrets and settings apart.
     pub session_token: String,
 }
 
+/// Make sure a strftime style pattern only uses specifiers chrono knows, as
+/// it would otherwise print garbage
+fn check_time_format(format: &str) -> Result<()> {
+    if StrftimeItems::new(format).any(|i| i == Item::Error) {
+        return Err(eyre!("invalid time_format {:?}", format));
+    }
+
+    Ok(())
+}
+
 impl Settings {
     pub fn save_sync_time() -> Result<()> {
         let data_dir = atuin_common::utils::data_dir();
@@ -113,6 +305,22 @@ impl Settings {
         }
     }
 
+    /// The aliases fuzzy search also matches commands against, if
+    /// `fuzzy_aliases` is on and there are any
+    pub fn fuzzy_aliases(&self) -> Option<Aliases> {
+        (self.fuzzy_aliases && !self.aliases.is_empty()).then(|| Aliases::new(self.aliases.clone()))
+    }
+
+    /// What hides secret environment variables when showing commands, if
+    /// that is turned on
+    pub fn env_mask(&self) -> Result<Option<EnvMask>> {
+        if self.mask_env_values {
+            EnvMask::new(&self.mask_env_pattern).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
     pub fn new() -> Result<Self> {
         let config_dir = atuin_common::utils::config_dir();
 
@@ -148,6 +356,43 @@ impl Settings {
             .set_default("search_mode", "prefix")?
             .set_default("session_token", "")?
             .set_default("style", "auto")?
+            .set_default("requery_mode", "token")?
+            .set_default("highlight_privileged", true)?
+            .set_default("render_ansi", false)?
+            .set_default("show_host_marker", false)?
+            .set_default("dangerous_patterns", Vec::<String>::new())?
+            .set_default("search_timeout", "2s")?
+            .set_default("aliases", HashMap::<String, String>::new())?
+            .set_default("min_command_length", 0)?
+            .set_default("trim_commands", true)?
+            .set_default("time_format", "%Y-%m-%d %H:%M:%S")?
+            .set_default("cd_commands", vec!["cd", "pushd", "popd"])?
+            .set_default("hide_unknown_duration", false)?
+            .set_default("show_selection_index", true)?
+            .set_default("redact_patterns", Vec::<String>::new())?
+            .set_default("cwd_include_subdirs", false)?
+            .set_default("ui_columns", vec!["index", "duration", "ago", "command"])?
+            .set_default("fuzzy_boundaries", "")?
+            .set_default("fuzzy_min_score", 0.0)?
+            .set_default("fuzzy_aliases", false)?
+            .set_default("frequency_tiebreak", "recency")?
+            .set_default("never_execute", false)?
+            .set_default("edge_behavior", "stop")?
+            .set_default("empty_query_behavior", "recent")?
+            .set_default("show_matching_line", true)?
+            .set_default("mask_env_values", false)?
+            .set_default("mask_env_pattern", "(?i)token|secret|key|pass")?
+            .set_default(
+                "share_format",
+                "# {time} in {cwd}, exited {exit}\n{command}",
+            )?
+            .set_default("jump_mode", "relative")?
+            .set_default("max_display_length", 4096)?
+            .set_default("incremental_search", false)?
+            .set_default("locale", "")?
+            .set_default("group_separators", false)?
+            .set_default("auto_refresh", "0")?
+            .set_default("cluster_results", false)?
             .add_source(
                 Environment::with_prefix("atuin")
                     .prefix_separator("_")
@@ -173,6 +418,9 @@ impl Settings {
             .try_deserialize()
             .map_err(|e| eyre!("failed to deserialize: {}", e))?;
 
+        check_time_format(&settings.time_format)?;
+        settings.env_mask()?;
+
         // all paths should be expanded
         let db_path = settings.db_path;
         let db_path = shellexpand::full(&db_path)?;
@@ -197,3 +445,18 @@ impl Settings {
         Ok(settings)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::check_time_format;
+
+    #[test]
+    fn time_format() {
+        assert!(check_time_format("%Y-%m-%d %H:%M").is_ok());
+        assert!(check_time_format("%a %b %d").is_ok());
+        assert!(check_time_format("no specifiers").is_ok());
+
+        assert!(check_time_format("%Y-%Q").is_err());
+        assert!(check_time_format("%").is_err());
+    }
+}
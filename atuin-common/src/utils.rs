@@ -42,14 +42,30 @@ pub fn home_dir() -> PathBuf {
 pub fn config_dir() -> PathBuf {
     let config_dir =
         std::env::var("XDG_CONFIG_HOME").map_or_else(|_| home_dir().join(".config"), PathBuf::from);
-    config_dir.join("atuin")
+    profile_dir(config_dir.join("atuin"), profile().as_deref())
 }
 
 pub fn data_dir() -> PathBuf {
     let data_dir = std::env::var("XDG_DATA_HOME")
         .map_or_else(|_| home_dir().join(".local").join("share"), PathBuf::from);
 
-    data_dir.join("atuin")
+    profile_dir(data_dir.join("atuin"), profile().as_deref())
+}
+
+/// The profile set with `ATUIN_PROFILE`, if any. Each profile has its own
+/// config and data directories
+pub fn profile() -> Option<String> {
+    std::env::var("ATUIN_PROFILE")
+        .ok()
+        .filter(|p| !p.is_empty())
+}
+
+/// Where a profile keeps the contents of `dir`
+pub fn profile_dir(dir: PathBuf, profile: Option<&str>) -> PathBuf {
+    match profile {
+        Some(profile) => dir.join("profiles").join(profile),
+        None => dir,
+    }
 }
 
 pub fn get_days_from_month(year: i32, month: u32) -> i64 {
@@ -100,6 +116,16 @@ mod tests {
         env::remove_var("XDG_DATA_HOME");
     }
 
+    #[test]
+    fn test_profile_dir() {
+        let dir = PathBuf::from("/home/user/.local/share/atuin");
+        assert_eq!(profile_dir(dir.clone(), None), dir);
+        assert_eq!(
+            profile_dir(dir, Some("work")),
+            PathBuf::from("/home/user/.local/share/atuin/profiles/work")
+        );
+    }
+
     #[test]
     fn test_data_dir() {
         env::set_var("HOME", "/home/user");
@@ -12,16 +12,20 @@ mod event;
 mod history;
 mod import;
 mod init;
+mod locale;
 mod login;
 mod logout;
 mod register;
 mod search;
+mod signal;
 mod stats;
+mod substitution;
 mod sync;
 use std::path::PathBuf;
 
 #[derive(Subcommand)]
 #[clap(infer_subcommands = true)]
+#[allow(clippy::large_enum_variant)]
 pub enum Cmd {
     /// Manipulate shell history
     #[clap(subcommand)]
@@ -78,7 +82,14 @@ pub enum Cmd {
 
 impl Cmd {
     pub async fn run(self) -> Result<()> {
-        pretty_env_logger::init();
+        // ATUIN_LOG=debug asks for logs to attach to bug reports. RUST_LOG
+        // still comes first, so the RUST_LOG=error the shell integrations set
+        // keeps logs out of the command the search UI hands back
+        if std::env::var_os("RUST_LOG").is_none() && std::env::var_os("ATUIN_LOG").is_some() {
+            pretty_env_logger::init_custom_env("ATUIN_LOG");
+        } else {
+            pretty_env_logger::init();
+        }
 
         let settings = Settings::new().wrap_err("could not load client settings")?;
 
@@ -2,16 +2,25 @@ use std::thread;
 use std::time::Duration;
 
 use crossbeam_channel::unbounded;
-use termion::event::Key;
+use termion::event::{self, Key};
 use termion::input::TermRead;
+use tokio::signal::unix::{signal, SignalKind};
 
 pub enum Event<I> {
     Input(I),
+    Paste(String),
     Tick,
+    Resize,
 }
 
-/// A small event handler that wrap termion input and tick events. Each event
-/// type is handled in its own thread and returned to a common `Receiver`
+// What the terminal sends around pasted text, once bracketed paste is enabled
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// A small event handler that wrap termion input, tick and resize events. Each
+/// event type is handled in its own thread (or task, for the `SIGWINCH`
+/// listener) and returned to a common `Receiver`. Pasted text is gathered up
+/// and sent as a single event, rather than a key at a time
 pub struct Events {
     rx: crossbeam_channel::Receiver<Event<Key>>,
 }
@@ -43,8 +52,25 @@ impl Events {
             let tx = tx.clone();
             thread::spawn(move || {
                 let tty = termion::get_tty().expect("Could not find tty");
-                for key in tty.keys().flatten() {
-                    if let Err(err) = tx.send(Event::Input(key)) {
+                let mut paste: Option<String> = None;
+                for input in tty.events().flatten() {
+                    let event = match (input, &mut paste) {
+                        (event::Event::Unsupported(seq), _) if seq == PASTE_START => {
+                            paste = Some(String::new());
+                            continue;
+                        }
+                        (event::Event::Unsupported(seq), Some(_)) if seq == PASTE_END => {
+                            Event::Paste(paste.take().unwrap_or_default())
+                        }
+                        (event::Event::Key(Key::Char(c)), Some(text)) => {
+                            text.push(c);
+                            continue;
+                        }
+                        (event::Event::Key(key), None) => Event::Input(key),
+                        _ => continue,
+                    };
+
+                    if let Err(err) = tx.send(event) {
                         eprintln!("{}", err);
                         return;
                     }
@@ -52,6 +78,25 @@ impl Events {
             })
         };
 
+        {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let mut winch = match signal(SignalKind::window_change()) {
+                    Ok(winch) => winch,
+                    Err(err) => {
+                        debug!("could not listen for window changes: {}", err);
+                        return;
+                    }
+                };
+
+                while winch.recv().await.is_some() {
+                    if tx.send(Event::Resize).is_err() {
+                        return;
+                    }
+                }
+            })
+        };
+
         thread::spawn(move || loop {
             if tx.send(Event::Tick).is_err() {
                 break;
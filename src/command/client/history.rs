@@ -1,13 +1,17 @@
+use std::borrow::Cow;
 use std::env;
-use std::io::Write;
+use std::io::{self, Write};
+use std::str::FromStr;
 use std::time::Duration;
 
 use clap::Subcommand;
 use eyre::Result;
 use tabwriter::TabWriter;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 use atuin_client::database::Database;
 use atuin_client::history::History;
+use atuin_client::redact::EnvMask;
 use atuin_client::settings::Settings;
 use atuin_client::sync;
 
@@ -51,44 +55,360 @@ pub enum Cmd {
     },
 }
 
-#[allow(clippy::cast_sign_loss)]
-pub fn print_list(h: &[History], human: bool, cmd_only: bool) {
-    let mut writer = TabWriter::new(std::io::stdout()).padding(2);
+/// A column that can be printed for each history entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Time,
+    Command,
+    Duration,
+    Exit,
+    Cwd,
+    Session,
+    Host,
+    Id,
+}
 
-    let lines = h.iter().map(|h| {
-        if human {
-            let duration = humantime::format_duration(Duration::from_nanos(std::cmp::max(
-                h.duration, 0,
-            ) as u64))
-            .to_string();
-            let duration: Vec<&str> = duration.split(' ').collect();
-            let duration = duration[0];
-
-            format!(
-                "{}\t{}\t{}\n",
-                h.timestamp.format("%Y-%m-%d %H:%M:%S"),
-                h.command.trim(),
-                duration,
-            )
-        } else if cmd_only {
-            format!("{}\n", h.command.trim())
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "time" => Ok(Self::Time),
+            "command" => Ok(Self::Command),
+            "duration" => Ok(Self::Duration),
+            "exit" => Ok(Self::Exit),
+            "cwd" => Ok(Self::Cwd),
+            "session" => Ok(Self::Session),
+            "host" => Ok(Self::Host),
+            "id" => Ok(Self::Id),
+            _ => Err(format!(
+                "invalid field {:?}, expected one of time, command, duration, exit, cwd, session, host, id",
+                s
+            )),
+        }
+    }
+}
+
+/// How a list of history entries is printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Columns lined up with spaces
+    Text,
+    /// A GitHub flavoured Markdown table, for pasting into docs and issues
+    Markdown,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "text" => Ok(Self::Text),
+            "markdown" => Ok(Self::Markdown),
+            _ => Err(format!(
+                "invalid format {:?}, expected one of text, markdown",
+                s
+            )),
+        }
+    }
+}
+
+/// A width to pin a field's column to, like `command=60`. Longer values are
+/// cut short with an ellipsis
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldWidth {
+    pub field: Field,
+    pub width: usize,
+}
+
+impl FromStr for FieldWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, width) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid field width {:?}, expected field=width", s))?;
+
+        match width.trim().parse() {
+            Ok(width) if width > 0 => Ok(Self {
+                field: field.parse()?,
+                width,
+            }),
+            _ => Err(format!(
+                "invalid width {:?} for {}, expected a number above 0",
+                width, field
+            )),
+        }
+    }
+}
+
+/// Cut a value down to `width` columns, ending it with an ellipsis if
+/// anything was cut. With `pad`, shorter values are filled out with spaces
+fn fit(value: String, width: usize, pad: bool) -> String {
+    let mut fitted = if value.width() > width {
+        let mut used = 0;
+        let mut cut: String = value
+            .chars()
+            .take_while(|c| {
+                used += c.width().unwrap_or(0);
+                used < width
+            })
+            .collect();
+
+        cut.push('…');
+        cut
+    } else {
+        value
+    };
+
+    if pad {
+        let fill = width.saturating_sub(fitted.width());
+        fitted.extend(std::iter::repeat(' ').take(fill));
+    }
+
+    fitted
+}
+
+/// Cut a command longer than `max` bytes short, noting how big it really was,
+/// so pasted blobs don't take over the screen. A `max` of 0 means no limit
+pub fn truncate_display(command: &str, max: usize) -> Cow<'_, str> {
+    if max == 0 || command.len() <= max {
+        return Cow::Borrowed(command);
+    }
+
+    let mut end = max;
+    while !command.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    Cow::Owned(format!(
+        "{}… [truncated, {}]",
+        &command[..end],
+        format_size(command.len())
+    ))
+}
+
+fn format_size(bytes: usize) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{}MB", bytes / (1024 * 1024))
+    } else if bytes >= 1024 {
+        format!("{}KB", bytes / 1024)
+    } else {
+        format!("{}B", bytes)
+    }
+}
+
+impl Field {
+    /// The fields printed when none are asked for explicitly
+    pub fn defaults(human: bool, cmd_only: bool, format: Format) -> Vec<Self> {
+        if format == Format::Markdown {
+            vec![Self::Time, Self::Command, Self::Exit, Self::Duration]
+        } else if cmd_only && !human {
+            vec![Self::Command]
         } else {
-            format!(
-                "{}\t{}\t{}\n",
-                h.timestamp.timestamp_nanos(),
-                h.command.trim(),
-                h.duration
-            )
+            vec![Self::Time, Self::Command, Self::Duration]
+        }
+    }
+
+    /// The heading of this field's column
+    const fn title(self) -> &'static str {
+        match self {
+            Self::Time => "Time",
+            Self::Command => "Command",
+            Self::Duration => "Duration",
+            Self::Exit => "Exit",
+            Self::Cwd => "Directory",
+            Self::Session => "Session",
+            Self::Host => "Host",
+            Self::Id => "Id",
         }
+    }
+
+    fn format(
+        self,
+        h: &History,
+        human: bool,
+        settings: &Settings,
+        mask: Option<&EnvMask>,
+    ) -> String {
+        match self {
+            Self::Time if !human => h.timestamp.timestamp_nanos().to_string(),
+            Self::Command => {
+                let command = truncate_display(&h.command, settings.max_display_length);
+                let command = mask.map_or(Cow::Borrowed(command.as_ref()), |m| m.mask(&command));
+
+                if settings.trim_commands {
+                    command.trim().to_string()
+                } else {
+                    command.into_owned()
+                }
+            }
+            Self::Duration if !human => h.duration.to_string(),
+            Self::Exit if !human => h.exit.to_string(),
+            _ => self.format_human(h, &settings.time_format, mask),
+        }
+    }
+
+    /// This field as it is shown with `--human`, with the command trimmed
+    #[allow(clippy::cast_sign_loss)]
+    pub fn format_human(self, h: &History, time_format: &str, mask: Option<&EnvMask>) -> String {
+        match self {
+            Self::Time => h.timestamp.format(time_format).to_string(),
+            Self::Command => mask
+                .map_or(Cow::Borrowed(h.command.as_str()), |m| m.mask(&h.command))
+                .trim()
+                .to_string(),
+            Self::Duration => {
+                let duration = humantime::format_duration(Duration::from_nanos(std::cmp::max(
+                    h.duration, 0,
+                )
+                    as u64))
+                .to_string();
+                let duration: Vec<&str> = duration.split(' ').collect();
+
+                duration[0].to_string()
+            }
+            Self::Exit => {
+                super::signal::name(h.exit).map_or_else(|| h.exit.to_string(), String::from)
+            }
+            Self::Cwd => h.cwd.clone(),
+            Self::Session => h.session.clone(),
+            Self::Host => h.hostname.clone(),
+            Self::Id => h.id.clone(),
+        }
+    }
+}
+
+/// Fill in a template such as `{time} {command}`, where each `{name}` is one
+/// of the fields `--fields` takes
+pub fn render_template(
+    template: &str,
+    mut value: impl FnMut(Field) -> String,
+) -> Result<String, String> {
+    let mut rendered = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed {{ in {:?}", template))?;
+        rendered.push_str(&value(rest[start + 1..start + end].parse()?));
+
+        rest = &rest[start + end + 1..];
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+pub fn print_list(h: &[History], human: bool, cmd_only: bool, settings: &Settings) {
+    let fields = Field::defaults(human, cmd_only, Format::Text);
+    print_fields(h, human, &fields, &[], Format::Text, settings);
+}
+
+pub fn print_fields(
+    h: &[History],
+    human: bool,
+    fields: &[Field],
+    widths: &[FieldWidth],
+    format: Format,
+    settings: &Settings,
+) {
+    write_list(
+        std::io::stdout(),
+        h,
+        human,
+        fields,
+        widths,
+        format,
+        settings,
+    )
+    .expect("failed to write history list");
+}
+
+/// Write the entries in columns, oldest first. Fields given a width in
+/// `widths` are cut or padded to exactly that width, so columns line up the
+/// same way every time
+pub fn write_list(
+    w: impl Write,
+    h: &[History],
+    human: bool,
+    fields: &[Field],
+    widths: &[FieldWidth],
+    format: Format,
+    settings: &Settings,
+) -> io::Result<()> {
+    let mask = settings
+        .env_mask()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+
+    if format == Format::Markdown {
+        return write_markdown(w, h, fields, settings, mask.as_ref());
+    }
+
+    let mut writer = TabWriter::new(w).padding(2);
+
+    let lines = h.iter().map(|h| {
+        let columns: Vec<String> = fields
+            .iter()
+            .enumerate()
+            .map(|(i, f)| {
+                let value = f.format(h, human, settings, mask.as_ref());
+
+                match widths.iter().rev().find(|w| w.field == *f) {
+                    Some(w) => fit(value, w.width, i + 1 < fields.len()),
+                    None => value,
+                }
+            })
+            .collect();
+
+        format!("{}\n", columns.join("\t"))
     });
 
     for i in lines.rev() {
-        writer
-            .write_all(i.as_bytes())
-            .expect("failed to write to tab writer");
+        writer.write_all(i.as_bytes())?;
+    }
+
+    writer.flush()
+}
+
+/// Write the entries as a Markdown table, oldest first. Times and durations
+/// are always human readable, as the table is meant to be read
+fn write_markdown(
+    mut w: impl Write,
+    h: &[History],
+    fields: &[Field],
+    settings: &Settings,
+    mask: Option<&EnvMask>,
+) -> io::Result<()> {
+    let titles: Vec<String> = fields.iter().map(|f| f.title().to_string()).collect();
+    let rule = vec![String::from("---"); fields.len()];
+
+    writeln!(w, "{}", markdown_row(&titles))?;
+    writeln!(w, "{}", markdown_row(&rule))?;
+
+    for h in h.iter().rev() {
+        let cells: Vec<String> = fields
+            .iter()
+            .map(|f| f.format(h, true, settings, mask))
+            .collect();
+        writeln!(w, "{}", markdown_row(&cells))?;
     }
 
-    writer.flush().expect("failed to flush tab writer");
+    w.flush()
+}
+
+/// A row of a Markdown table. Pipes would end a cell early, and newlines the
+/// row, so both are escaped
+fn markdown_row(cells: &[String]) -> String {
+    let cells: Vec<String> = cells
+        .iter()
+        .map(|c| c.replace('|', "\\|").replace('\n', "<br>"))
+        .collect();
+
+    format!("| {} |", cells.join(" | "))
 }
 
 impl Cmd {
@@ -186,17 +506,91 @@ impl Cmd {
                     }
                 };
 
-                print_list(&history, *human, *cmd_only);
+                print_list(&history, *human, *cmd_only, settings);
 
                 Ok(())
             }
 
             Self::Last { human, cmd_only } => {
                 let last = db.last().await?;
-                print_list(&[last], *human, *cmd_only);
+                print_list(&[last], *human, *cmd_only, settings);
 
                 Ok(())
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{fit, markdown_row, render_template, truncate_display, Field, FieldWidth};
+
+    #[test]
+    fn markdown_escapes_cells() {
+        let cells = vec![
+            String::from("ps aux | grep atuin"),
+            String::from("cat <<EOF\nhi\nEOF"),
+        ];
+
+        assert_eq!(
+            markdown_row(&cells),
+            "| ps aux \\| grep atuin | cat <<EOF<br>hi<br>EOF |"
+        );
+    }
+
+    #[test]
+    fn renders_template() {
+        let name = |f: Field| format!("<{}>", f.title());
+
+        assert_eq!(
+            render_template("# {time} in {cwd}\n{command}", name).as_deref(),
+            Ok("# <Time> in <Directory>\n<Command>")
+        );
+        assert_eq!(
+            render_template("no fields", name).as_deref(),
+            Ok("no fields")
+        );
+
+        assert!(render_template("{nope}", name).is_err());
+        assert!(render_template("{time", name).is_err());
+    }
+
+    #[test]
+    fn truncates_for_display() {
+        assert_eq!(truncate_display("git status", 0), "git status");
+        assert_eq!(truncate_display("git status", 10), "git status");
+        assert_eq!(truncate_display("git status", 3), "git… [truncated, 10B]");
+
+        let blob = format!("echo {}", "x".repeat(42 * 1024));
+        assert_eq!(truncate_display(&blob, 4), "echo… [truncated, 42KB]");
+
+        // Never cut a character in half
+        assert_eq!(truncate_display("é", 1), "… [truncated, 2B]");
+    }
+
+    #[test]
+    fn fits_to_width() {
+        assert_eq!(fit(String::from("git status"), 6, false), "git s…");
+        assert_eq!(fit(String::from("git"), 6, true), "git   ");
+        assert_eq!(fit(String::from("git"), 6, false), "git");
+        assert_eq!(fit(String::from("git st"), 6, true), "git st");
+
+        // Wide characters take up two columns, and are never split
+        assert_eq!(fit(String::from("echo 👍🏽👍🏽"), 7, true), "echo … ");
+    }
+
+    #[test]
+    fn parses_field_widths() {
+        assert_eq!(
+            "command=60".parse(),
+            Ok(FieldWidth {
+                field: Field::Command,
+                width: 60
+            })
+        );
+
+        for width in ["command", "command=", "command=0", "command=-1", "dir=10"] {
+            assert!(width.parse::<FieldWidth>().is_err(), "{}", width);
+        }
+    }
+}
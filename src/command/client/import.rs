@@ -1,6 +1,6 @@
 use std::{env, path::PathBuf};
 
-use atuin_client::import::fish::Fish;
+use atuin_client::import::{atuin::Atuin, fish::Fish};
 use clap::Parser;
 use eyre::{eyre, Result};
 
@@ -26,6 +26,12 @@ pub enum Cmd {
 
     /// Import history from the fish history file
     Fish,
+
+    /// Import history written by `atuin search --export-atuin`
+    Atuin {
+        /// The exported file
+        path: PathBuf,
+    },
 }
 
 const BATCH_SIZE: usize = 100;
@@ -60,6 +66,13 @@ impl Cmd {
             Self::Bash => import::<Bash<_>, _>(db, BATCH_SIZE).await,
             Self::Resh => import::<Resh, _>(db, BATCH_SIZE).await,
             Self::Fish => import::<Fish<_>, _>(db, BATCH_SIZE).await,
+            Self::Atuin { path } => {
+                if !path.is_file() {
+                    return Err(eyre!("Could not find exported history {:?}", path));
+                }
+
+                import_path::<Atuin<_>, _>(db, path.clone(), BATCH_SIZE).await
+            }
         }
     }
 }
@@ -68,12 +81,23 @@ async fn import<I: Importer + Send, DB: Database + Send + Sync>(
     db: &mut DB,
     buf_size: usize,
 ) -> Result<()>
+where
+    I::IntoIter: Send,
+{
+    let histpath = get_histpath::<I>()?;
+    import_path::<I, _>(db, histpath, buf_size).await
+}
+
+async fn import_path<I: Importer + Send, DB: Database + Send + Sync>(
+    db: &mut DB,
+    histpath: PathBuf,
+    buf_size: usize,
+) -> Result<()>
 where
     I::IntoIter: Send,
 {
     println!("Importing history from {}", I::NAME);
 
-    let histpath = get_histpath::<I>()?;
     let contents = I::parse(histpath)?;
 
     let iter = contents.into_iter();
@@ -0,0 +1,263 @@
+//! Counts and durations written the way the user's language writes them, for
+//! the search UI. Only the languages below are known, anything else is shown
+//! as English
+
+use std::env;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    // Between each group of three digits
+    separator: &'static str,
+
+    // The short names of the longer units. Seconds, minutes and hours are
+    // left as s, m and h, which are widely understood
+    day: &'static str,
+    week: &'static str,
+    month: &'static str,
+    year: &'static str,
+
+    // How long ago something was, with {} standing for the duration
+    ago: &'static str,
+}
+
+const ENGLISH: Locale = Locale {
+    separator: ",",
+    day: "d",
+    week: "w",
+    month: "mo",
+    year: "y",
+    ago: "{} ago",
+};
+
+// The narrow no-break space French puts between groups of digits
+const FRENCH: Locale = Locale {
+    separator: "\u{202f}",
+    day: "j",
+    week: "sem",
+    month: "mois",
+    year: "a",
+    ago: "il y a {}",
+};
+
+const LOCALES: [(&str, Locale); 10] = [
+    ("en", ENGLISH),
+    (
+        "de",
+        Locale {
+            separator: ".",
+            day: "T",
+            week: "W",
+            month: "Mon",
+            year: "J",
+            ago: "vor {}",
+        },
+    ),
+    (
+        "es",
+        Locale {
+            separator: ".",
+            day: "d",
+            week: "sem",
+            month: "mes",
+            year: "a",
+            ago: "hace {}",
+        },
+    ),
+    ("fr", FRENCH),
+    (
+        "it",
+        Locale {
+            separator: ".",
+            day: "g",
+            week: "sett",
+            month: "mes",
+            year: "a",
+            ago: "{} fa",
+        },
+    ),
+    (
+        "nl",
+        Locale {
+            separator: ".",
+            day: "d",
+            week: "w",
+            month: "mnd",
+            year: "j",
+            ago: "{} geleden",
+        },
+    ),
+    (
+        "pl",
+        Locale {
+            separator: "\u{a0}",
+            day: "d",
+            week: "tydz",
+            month: "mies",
+            year: "r",
+            ago: "{} temu",
+        },
+    ),
+    (
+        "pt",
+        Locale {
+            separator: ".",
+            day: "d",
+            week: "sem",
+            month: "mês",
+            year: "a",
+            ago: "há {}",
+        },
+    ),
+    (
+        "ru",
+        Locale {
+            separator: "\u{a0}",
+            day: "д",
+            week: "нед",
+            month: "мес",
+            year: "г",
+            ago: "{} назад",
+        },
+    ),
+    (
+        "sv",
+        Locale {
+            separator: "\u{a0}",
+            day: "d",
+            week: "v",
+            month: "mån",
+            year: "år",
+            ago: "för {} sedan",
+        },
+    ),
+];
+
+impl Default for Locale {
+    fn default() -> Self {
+        ENGLISH
+    }
+}
+
+impl Locale {
+    /// The locale with a name such as `de` or `de_DE.UTF-8`. With no name,
+    /// the system's is used, from `$LC_ALL`, `$LC_NUMERIC` or `$LANG`
+    pub fn new(name: &str) -> Self {
+        if name.is_empty() {
+            let name = ["LC_ALL", "LC_NUMERIC", "LANG"]
+                .iter()
+                .filter_map(|var| env::var(var).ok())
+                .find(|name| !name.is_empty())
+                .unwrap_or_default();
+
+            return Self::named(&name);
+        }
+
+        Self::named(name)
+    }
+
+    fn named(name: &str) -> Self {
+        let language = name
+            .split(|c| matches!(c, '_' | '-' | '.' | '@'))
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+
+        LOCALES
+            .iter()
+            .find(|(l, _)| *l == language)
+            .map_or(ENGLISH, |(_, locale)| *locale)
+    }
+
+    /// A count with its digits grouped in threes
+    pub fn format_count(&self, n: impl Into<i64>) -> String {
+        let n = n.into();
+        let digits = n.unsigned_abs().to_string();
+
+        let mut grouped = String::new();
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i) % 3 == 0 {
+                grouped.push_str(self.separator);
+            }
+            grouped.push(digit);
+        }
+
+        if n < 0 {
+            format!("-{}", grouped)
+        } else {
+            grouped
+        }
+    }
+
+    /// A duration in its largest unit only, such as 3d, as it's only meant to
+    /// give an idea of how long something took
+    pub fn format_duration(&self, duration: Duration) -> String {
+        let formatted = humantime::format_duration(duration).to_string();
+        let largest = formatted.split(' ').next().unwrap_or_default();
+
+        let unit_at = largest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(largest.len());
+        let (number, unit) = largest.split_at(unit_at);
+
+        let unit = match unit.trim_end_matches('s') {
+            "day" => self.day,
+            "week" => self.week,
+            "month" => self.month,
+            "year" => self.year,
+            _ => unit,
+        };
+
+        format!("{}{}", number, unit)
+    }
+
+    /// How long ago something was, from `format_duration`
+    pub fn format_ago(&self, duration: Duration) -> String {
+        self.ago.replace("{}", &self.format_duration(duration))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Locale;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[test]
+    fn formats_counts() {
+        let en = Locale::new("en_US.UTF-8");
+        assert_eq!(en.format_count(0), "0");
+        assert_eq!(en.format_count(999), "999");
+        assert_eq!(en.format_count(1000), "1,000");
+        assert_eq!(en.format_count(1_234_567), "1,234,567");
+        assert_eq!(en.format_count(-12_345), "-12,345");
+
+        assert_eq!(Locale::new("de_DE").format_count(1_234_567), "1.234.567");
+        assert_eq!(Locale::new("fr").format_count(12_345), "12\u{202f}345");
+    }
+
+    #[test]
+    fn formats_durations() {
+        let en = Locale::default();
+        assert_eq!(en.format_duration(Duration::from_millis(1500)), "1s");
+        assert_eq!(en.format_duration(Duration::from_millis(12)), "12ms");
+        assert_eq!(en.format_duration(Duration::from_secs(3 * DAY)), "3d");
+        assert_eq!(en.format_duration(Duration::from_secs(DAY)), "1d");
+        assert_eq!(en.format_ago(Duration::from_secs(90)), "1m ago");
+
+        let de = Locale::new("de");
+        assert_eq!(de.format_duration(Duration::from_secs(3 * DAY)), "3T");
+        assert_eq!(de.format_duration(Duration::from_secs(400 * DAY)), "1J");
+        assert_eq!(de.format_ago(Duration::from_secs(90)), "vor 1m");
+        assert_eq!(de.format_duration(Duration::from_secs(5)), "5s");
+    }
+
+    #[test]
+    fn unknown_languages_are_english() {
+        assert_eq!(Locale::new("C"), Locale::default());
+        assert_eq!(Locale::new("xx_YY.UTF-8"), Locale::default());
+        assert_eq!(Locale::new("PT-br"), Locale::new("pt"));
+    }
+}
@@ -1,38 +1,125 @@
-use chrono::Utc;
+use chrono::{DateTime, Local, TimeZone, Utc};
 use clap::Parser;
-use eyre::Result;
-use std::{io::stdout, ops::Sub, time::Duration};
+use clap_complete::Shell;
+use eyre::{eyre, Result, WrapErr};
+use fs_err as fs;
+use indicatif::ProgressBar;
+use itertools::Itertools;
+use regex::Regex;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    convert::TryFrom,
+    future::Future,
+    io::{stdout, BufWriter, Write},
+    ops::Sub,
+    panic::{self, PanicInfo},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+use tabwriter::TabWriter;
 use termion::{event::Key, input::MouseTerminal, raw::IntoRawMode, screen::AlternateScreen};
 use tui::{
     backend::{Backend, TermionBackend},
-    layout::{Alignment, Constraint, Corner, Direction, Layout},
+    layout::{Alignment, Constraint, Corner, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans, Text},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
     Frame, Terminal,
 };
+use unicode_segmentation::UnicodeSegmentation;
 use unicode_width::UnicodeWidthStr;
 
 use atuin_client::{
-    database::Database,
+    alias::Aliases,
+    database::{Database, Sqlite},
     history::History,
-    settings::{SearchMode, Settings},
+    import::atuin,
+    ordering::{self, filter_fuzzy, reorder_boundaries},
+    query::Filters,
+    redact::{EnvMask, Redactor},
+    settings::{
+        Column, EdgeBehavior, EmptyQuery, JumpMode, RequeryMode, SearchMode, Settings,
+        Style as UiLayout, Tiebreak,
+    },
 };
 
 use super::event::{Event, Events};
+use super::history::{render_template, truncate_display, Field, FieldWidth, Format};
+use super::locale::Locale;
+use super::signal::{self, Signal};
+use super::substitution::Substitution;
+
+mod ansi;
+mod report;
+
+use report::{
+    aggregate_durations, bucket_times, diff_commands, group_by_cwd, print_time_spent,
+    render_cwd_commands, render_duplicates, render_groups, render_heatmap, split_runs,
+    summarize_duplicates, summarize_groups, Group, GroupBy,
+};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-#[derive(Parser)]
+/// How many results the UI loads for each query, unless --max-entries says
+/// otherwise
+const MAX_ENTRIES: usize = 200;
+
+/// The fewest results Alt-- will shrink the UI's limit to
+const MIN_ENTRIES: usize = 10;
+
+/// How many results --preview shows
+const PREVIEW_COUNT: usize = 5;
+
+/// How often --watch checks for new commands
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How far back --watch looks each time, so commands saved a little after they
+/// started aren't missed
+const WATCH_LOOKBACK: Duration = Duration::from_secs(10);
+
+/// How many directories the recent directories list offers
+const RECENT_DIRS: i64 = 10;
+
+/// The line before a command picked in scratch mode, so the shell integration
+/// knows to run it without recording it
+const SCRATCH_MARKER: &str = "# atuin scratch";
+
+/// How close together runs have to be for --find-duplicates to count them
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// How often the "ago" column is brought up to date while the UI is open
+const DURATIONS_REFRESH: Duration = Duration::from_secs(60);
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Parser, Debug)]
 pub struct Cmd {
     /// Filter search result by directory
     #[clap(long, short)]
     cwd: Option<String>,
 
+    /// Only match --cwd exactly, even when cwd_include_subdirs is set
+    #[clap(long)]
+    no_subdirs: bool,
+
     /// Exclude directory from results
     #[clap(long = "exclude-cwd")]
     exclude_cwd: Option<String>,
 
+    /// Only include commands ran on this host, either just its name or
+    /// host:user as recorded. With --interactive, this is where the host
+    /// filter starts
+    #[clap(long)]
+    host: Option<String>,
+
+    /// Exclude commands ran on this host, either just its name or host:user
+    /// as recorded
+    #[clap(long, conflicts_with = "interactive")]
+    exclude_host: Option<String>,
+
     /// Filter search result by exit code
     #[clap(long, short)]
     exit: Option<i64>,
@@ -41,6 +128,10 @@ pub struct Cmd {
     #[clap(long = "exclude-exit")]
     exclude_exit: Option<i64>,
 
+    /// Only include results killed by this signal, such as SIGINT
+    #[clap(long)]
+    signal: Option<Signal>,
+
     /// Only include results added before this date
     #[clap(long, short)]
     before: Option<String>,
@@ -49,6 +140,10 @@ pub struct Cmd {
     #[clap(long)]
     after: Option<String>,
 
+    /// Only include results ran since the machine last booted
+    #[clap(long)]
+    since_boot: bool,
+
     /// Open interactive search UI
     #[clap(long, short)]
     interactive: bool,
@@ -57,567 +152,5937 @@ pub struct Cmd {
     #[clap(long)]
     human: bool,
 
+    /// Match commands containing the query exactly as given, like grep -F.
+    /// Wildcards, tags and the search mode's operators don't apply
+    #[clap(long, short = 'F', conflicts_with_all = &["interactive", "watch"])]
+    fixed_string: bool,
+
+    /// The search mode to use instead of search_mode, one of prefix,
+    /// fulltext, fuzzy, fulltext-indexed and regex
+    #[clap(long, conflicts_with = "fixed-string")]
+    search_mode: Option<SearchMode>,
+
     query: Vec<String>,
 
     /// Show only the text of the command
     #[clap(long)]
     cmd_only: bool,
-}
 
-impl Cmd {
-    pub async fn run(
-        self,
-        db: &mut (impl Database + Send + Sync),
-        settings: &Settings,
-    ) -> Result<()> {
-        run(
-            settings,
-            self.cwd,
-            self.exit,
-            self.interactive,
-            self.human,
-            self.exclude_exit,
-            self.exclude_cwd,
-            self.before,
-            self.after,
-            self.cmd_only,
-            &self.query,
-            db,
-        )
-        .await
-    }
-}
+    /// Only show the oldest N results, in chronological order
+    #[clap(long)]
+    head: Option<usize>,
 
-struct State {
-    input: String,
+    /// Write results, or the report asked for, to this file, rather than stdout
+    #[clap(long, short)]
+    output: Option<PathBuf>,
 
-    results: Vec<History>,
+    /// Write every matching run to this file, keeping its time, duration,
+    /// exit code, directory, session and host, for `atuin import atuin` to
+    /// read back in
+    #[clap(long)]
+    export_atuin: Option<PathBuf>,
 
-    results_state: ListState,
-}
+    /// Only include commands ran with sudo/doas, or as root
+    #[clap(long)]
+    privileged: bool,
 
-impl State {
-    #[allow(clippy::cast_sign_loss)]
-    fn durations(&self) -> Vec<(String, String)> {
-        self.results
-            .iter()
-            .map(|h| {
-                let duration =
-                    Duration::from_millis(std::cmp::max(h.duration, 0) as u64 / 1_000_000);
-                let duration = humantime::format_duration(duration).to_string();
-                let duration: Vec<&str> = duration.split(' ').collect();
+    /// Comma separated list of fields to print. Any of time, command,
+    /// duration, exit, cwd, session, host and id
+    #[clap(long, use_value_delimiter = true)]
+    fields: Option<Vec<Field>>,
 
-                let ago = chrono::Utc::now().sub(h.timestamp);
+    /// Comma separated widths to pin fields' columns to, like command=60,cwd=30.
+    /// Longer values are cut short
+    #[clap(long, use_value_delimiter = true)]
+    field_widths: Vec<FieldWidth>,
 
-                // Account for the chance that h.timestamp is "in the future"
-                // This would mean that "ago" is negative, and the unwrap here
-                // would fail.
-                // If the timestamp would otherwise be in the future, display
-                // the time ago as 0.
-                let ago = humantime::format_duration(
-                    ago.to_std().unwrap_or_else(|_| Duration::new(0, 0)),
-                )
-                .to_string();
-                let ago: Vec<&str> = ago.split(' ').collect();
+    /// How to print the results, as text or a markdown table
+    #[clap(long, default_value = "text")]
+    format: Format,
 
-                (
-                    duration[0]
-                        .to_string()
-                        .replace("days", "d")
-                        .replace("day", "d")
-                        .replace("weeks", "w")
-                        .replace("week", "w")
-                        .replace("months", "mo")
-                        .replace("month", "mo")
-                        .replace("years", "y")
-                        .replace("year", "y"),
-                    ago[0]
-                        .to_string()
-                        .replace("days", "d")
-                        .replace("day", "d")
-                        .replace("weeks", "w")
-                        .replace("week", "w")
-                        .replace("months", "mo")
-                        .replace("month", "mo")
-                        .replace("years", "y")
-                        .replace("year", "y")
-                        + " ago",
-                )
-            })
-            .collect()
-    }
+    /// Expand shell aliases in the printed commands
+    #[clap(long)]
+    resolve_aliases: bool,
 
-    fn render_results<T: tui::backend::Backend>(
-        &mut self,
-        f: &mut tui::Frame<T>,
-        r: tui::layout::Rect,
-        b: tui::widgets::Block,
-    ) {
-        let durations = self.durations();
-        let max_length = durations.iter().fold(0, |largest, i| {
-            std::cmp::max(largest, i.0.len() + i.1.len())
-        });
+    /// Read aliases to expand from this file, as printed by `alias`
+    #[clap(long, requires = "resolve-aliases")]
+    alias_file: Option<PathBuf>,
 
-        let results: Vec<ListItem> = self
-            .results
-            .iter()
-            .enumerate()
-            .map(|(i, m)| {
-                let command = m.command.to_string().replace('\n', " ").replace('\t', " ");
+    /// Sort results by time, duration, exit or command, optionally followed by
+    /// a direction. For example, duration:desc
+    #[clap(long)]
+    sort: Option<Sort>,
 
-                let mut command = Span::raw(command);
+    /// Search this history database, rather than the configured one
+    #[clap(long)]
+    db: Option<PathBuf>,
 
-                let (duration, mut ago) = durations[i].clone();
+    /// Exclude commands shorter than this many characters
+    #[clap(long)]
+    min_length: Option<usize>,
 
-                while (duration.len() + ago.len()) < max_length {
-                    ago = format!(" {}", ago);
-                }
+    /// Print the matching commands most used first, formatted as completion
+    /// candidates for this shell
+    #[clap(long)]
+    generate_completions: Option<Shell>,
 
-                let selected_index = match self.results_state.selected() {
-                    None => Span::raw("   "),
-                    Some(selected) => match i.checked_sub(selected) {
-                        None => Span::raw("   "),
-                        Some(diff) => {
-                            if 0 < diff && diff < 10 {
-                                Span::raw(format!(" {} ", diff))
-                            } else {
-                                Span::raw("   ")
-                            }
-                        }
-                    },
-                };
-
-                let duration = Span::styled(
-                    duration,
-                    Style::default().fg(if m.exit == 0 || m.duration == -1 {
-                        Color::Green
-                    } else {
-                        Color::Red
-                    }),
-                );
+    /// Show only results that do not pass the other filters. The query itself
+    /// must still match
+    #[clap(long, short = 'v')]
+    invert: bool,
 
-                let ago = Span::styled(ago, Style::default().fg(Color::Blue));
+    /// Print the commands in this collection, in the order they were added.
+    /// With --interactive, Alt-c adds to or removes from this collection
+    #[clap(long)]
+    collection: Option<String>,
 
-                if let Some(selected) = self.results_state.selected() {
-                    if selected == i {
-                        command.style =
-                            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD);
-                    }
-                }
+    /// List every collection, with how many commands are in each
+    #[clap(long)]
+    list_collections: bool,
 
-                let spans = Spans::from(vec![
-                    selected_index,
-                    duration,
-                    Span::raw(" "),
-                    ago,
-                    Span::raw(" "),
-                    command,
-                ]);
+    /// Rebuild the indexes and other tables searches rely on from the
+    /// history, such as after an import or an upgrade, then exit
+    #[clap(long)]
+    reindex: bool,
 
-                ListItem::new(spans)
-            })
-            .collect();
+    /// Only include commands from the current session
+    #[clap(long)]
+    session: bool,
 
-        let results = List::new(results)
-            .block(b)
-            .start_corner(Corner::BottomLeft)
-            .highlight_symbol(">> ");
+    /// With --session, only print the command ran this many commands ago,
+    /// such as -3, or counting from the start of the session if positive
+    #[clap(long, requires = "session", allow_hyphen_values = true)]
+    nth: Option<i64>,
 
-        f.render_stateful_widget(results, r, &mut self.results_state);
-    }
-}
+    /// Print every command from a session, oldest first. Defaults to the
+    /// current session
+    #[allow(clippy::option_option)]
+    #[clap(long, value_name = "SESSION")]
+    context_session: Option<Option<String>>,
 
-async fn query_results(
-    app: &mut State,
-    search_mode: SearchMode,
-    db: &mut (impl Database + Send + Sync),
-) -> Result<()> {
-    let results = match app.input.as_str() {
-        "" => db.list(Some(200), true).await?,
-        i => db.search(Some(200), search_mode, i).await?,
-    };
+    /// With --context-session, print each run of the same command once, with
+    /// how many times it was ran and how long that took
+    #[clap(long, requires = "context-session")]
+    collapse_repeats: bool,
 
-    app.results = results;
+    /// Only include commands that change directory, such as cd or pushd
+    #[clap(long)]
+    only_cd: bool,
 
-    if app.results.is_empty() {
-        app.results_state.select(None);
-    } else {
-        app.results_state.select(Some(0));
-    }
+    /// Only include commands with a pipe or redirect outside of quotes
+    #[clap(long)]
+    pipelines: bool,
 
-    Ok(())
-}
+    /// Only include commands whose arguments, everything after the first
+    /// word, match this regex, whatever the program. Give it more than once
+    /// to require several
+    #[clap(long, allow_hyphen_values = true, number_of_values = 1)]
+    arg: Vec<Regex>,
 
-async fn key_handler(
-    input: Key,
-    search_mode: SearchMode,
-    db: &mut (impl Database + Send + Sync),
-    app: &mut State,
-) -> Option<String> {
-    match input {
-        Key::Esc | Key::Ctrl('c' | 'd' | 'g') => return Some(String::from("")),
-        Key::Char('\n') => {
-            let i = app.results_state.selected().unwrap_or(0);
+    /// The most results to show. In the UI this is how many are loaded for
+    /// each query, otherwise it caps the printed results
+    #[clap(long, short = 'n')]
+    max_entries: Option<usize>,
 
-            return Some(
-                app.results
-                    .get(i)
-                    .map_or(app.input.clone(), |h| h.command.clone()),
-            );
-        }
-        Key::Alt(c) if ('1'..='9').contains(&c) => {
-            let c = c.to_digit(10)? as usize;
-            let i = app.results_state.selected()? + c;
+    /// Print the total time spent running each command, or each command's
+    /// first word with "token", longest first
+    #[clap(long)]
+    aggregate_time: Option<Group>,
 
-            return Some(
-                app.results
-                    .get(i)
-                    .map_or(app.input.clone(), |h| h.command.clone()),
-            );
-        }
-        Key::Char(c) => {
-            app.input.push(c);
-            query_results(app, search_mode, db).await.unwrap();
-        }
-        Key::Backspace => {
-            app.input.pop();
-            query_results(app, search_mode, db).await.unwrap();
-        }
-        // \u{7f} is escape sequence for backspace
-        Key::Alt('\u{7f}') => {
-            let words: Vec<&str> = app.input.split(' ').collect();
-            if words.is_empty() {
-                return None;
-            }
-            if words.len() == 1 {
-                app.input = String::from("");
-            } else {
-                app.input = words[0..(words.len() - 1)].join(" ");
-            }
-            query_results(app, search_mode, db).await.unwrap();
-        }
-        Key::Ctrl('u') => {
-            app.input = String::from("");
-            query_results(app, search_mode, db).await.unwrap();
-        }
-        Key::Down | Key::Ctrl('n') => {
-            let i = match app.results_state.selected() {
-                Some(i) => {
-                    if i == 0 {
-                        0
-                    } else {
-                        i - 1
-                    }
-                }
-                None => 0,
-            };
-            app.results_state.select(Some(i));
-        }
-        Key::Up | Key::Ctrl('p') => {
-            let i = match app.results_state.selected() {
-                Some(i) => {
-                    if i >= app.results.len() - 1 {
-                        app.results.len() - 1
-                    } else {
-                        i + 1
-                    }
-                }
-                None => 0,
-            };
-            app.results_state.select(Some(i));
-        }
-        _ => {}
-    };
+    /// Match the query against each line of multi-line commands, and print
+    /// the first line that matched along with its line number
+    #[clap(long)]
+    lines: bool,
 
-    None
-}
+    /// Replace anything that looks like a secret, a home directory or an IP
+    /// address in the printed commands and directories
+    #[clap(long)]
+    redact: bool,
 
-#[allow(clippy::cast_possible_truncation)]
-fn draw<T: Backend>(f: &mut Frame<'_, T>, history_count: i64, app: &mut State) {
-    let chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .margin(1)
-        .constraints(
-            [
-                Constraint::Length(2),
-                Constraint::Min(1),
-                Constraint::Length(3),
-            ]
-            .as_ref(),
-        )
-        .split(f.size());
+    /// Compare the commands ran before this time with those ran since, both
+    /// within --after and --before, and print which are new, which stopped
+    /// being used, and which are common to both
+    #[clap(long)]
+    diff: Option<String>,
 
-    let top_chunks = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-        .split(chunks[0]);
+    /// Only print the first command ran on each day, oldest day first, for
+    /// an overview of what was worked on when
+    #[clap(long, conflicts_with = "last-of-day")]
+    first_of_day: bool,
 
-    let top_left_chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([Constraint::Length(1), Constraint::Length(1)].as_ref())
-        .split(top_chunks[0]);
+    /// Only print the last command ran on each day, oldest day first
+    #[clap(long)]
+    last_of_day: bool,
 
-    let top_right_chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([Constraint::Length(1), Constraint::Length(1)].as_ref())
-        .split(top_chunks[1]);
+    /// Print the most ran commands within --after and --before, with how many
+    /// times each was ran
+    #[clap(long)]
+    top: bool,
 
-    let title = Paragraph::new(Text::from(Span::styled(
-        format!("Atuin v{}", VERSION),
-        Style::default().add_modifier(Modifier::BOLD),
-    )));
+    /// For auditing, print each directory with every distinct command ever
+    /// ran in it, within --after and --before. Directories and their commands
+    /// are sorted
+    #[clap(long)]
+    distinct_cwd_commands: bool,
 
-    let help = vec![
-        Span::raw("Press "),
-        Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
-        Span::raw(" to exit."),
-    ];
+    /// Print how many commands were ran on each host, or in each directory
+    /// with "cwd", how many of them were distinct and when the last was ran,
+    /// within --after and --before. Busiest first
+    #[clap(long)]
+    group_by: Option<GroupBy>,
 
-    let help = Text::from(Spans::from(help));
-    let help = Paragraph::new(help);
+    /// Report commands recorded several times in quick succession in the same
+    /// session, which a misbehaving shell hook can leave behind. With
+    /// --fields or --cmd-only, print the extra copies themselves instead
+    #[clap(long)]
+    find_duplicates: bool,
 
-    let input = Paragraph::new(app.input.clone())
-        .block(Block::default().borders(Borders::ALL).title("Query"));
+    /// How soon after the run before it a run has to start for
+    /// --find-duplicates to count it as a copy. Defaults to 1s
+    #[clap(long, requires = "find-duplicates")]
+    duplicate_window: Option<String>,
 
-    let stats = Paragraph::new(Text::from(Span::raw(format!(
-        "history count: {}",
-        history_count,
-    ))))
-    .alignment(Alignment::Right);
+    /// Print a grid of how many matching commands were ran in each hour of
+    /// each day of the week, within --after and --before
+    #[clap(long)]
+    explain_time: bool,
 
-    f.render_widget(title, top_left_chunks[0]);
-    f.render_widget(help, top_left_chunks[1]);
-    f.render_widget(stats, top_right_chunks[0]);
+    /// Report how many results each filter removed, on stderr
+    #[clap(long)]
+    explain_filters: bool,
 
-    app.render_results(
-        f,
-        chunks[1],
-        Block::default().borders(Borders::ALL).title("History"),
-    );
-    f.render_widget(input, chunks[2]);
+    /// Only print how many results there are, and the first few of them
+    #[clap(long)]
+    preview: bool,
 
-    f.set_cursor(
-        // Put cursor past the end of the input text
-        chunks[2].x + app.input.width() as u16 + 1,
-        // Move one line down, from the border to the input line
-        chunks[2].y + 1,
-    );
-}
+    /// Exclude commands with an unknown duration, such as imported ones and
+    /// those still running
+    #[clap(long)]
+    has_duration: bool,
 
-#[allow(clippy::cast_possible_truncation)]
-fn draw_compact<T: Backend>(f: &mut Frame<'_, T>, history_count: i64, app: &mut State) {
-    let chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .margin(0)
-        .horizontal_margin(1)
-        .constraints(
-            [
-                Constraint::Length(1),
-                Constraint::Min(1),
-                Constraint::Length(1),
-            ]
-            .as_ref(),
-        )
-        .split(f.size());
+    /// The shell the selected command is for, so it can be encoded to survive
+    /// being put on the prompt. Defaults to the one in $SHELL
+    #[clap(long)]
+    shell: Option<Shell>,
 
-    let header_chunks = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints(
-            [
-                Constraint::Ratio(1, 3),
-                Constraint::Ratio(1, 3),
-                Constraint::Ratio(1, 3),
-            ]
-            .as_ref(),
-        )
-        .split(chunks[0]);
+    /// When several commands are marked with Alt-m, print each after a
+    /// "# atuin replay <n>/<total>" line, so a wrapper can run them one at a
+    /// time. Otherwise they are printed as one multi-line command
+    #[clap(long, requires = "interactive")]
+    replay: bool,
 
-    let title = Paragraph::new(Text::from(Span::styled(
-        format!("Atuin v{}", VERSION),
-        Style::default().fg(Color::DarkGray),
-    )));
+    /// Open the search UI in scratch mode, which Alt-z toggles. The selected
+    /// command is then printed after a "# atuin scratch" line, telling the
+    /// shell integration not to record it when it is ran
+    #[clap(long, requires = "interactive")]
+    scratch: bool,
 
-    let help = Paragraph::new(Text::from(Spans::from(vec![
-        Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
-        Span::raw(" to exit"),
-    ])))
-    .style(Style::default().fg(Color::DarkGray))
-    .alignment(Alignment::Center);
+    /// Print the selected command to stdout, rather than handing it to the
+    /// shell, so nothing from history can be ran by accident
+    #[clap(long)]
+    print_only: bool,
 
-    let stats = Paragraph::new(Text::from(Span::raw(format!(
-        "history count: {}",
-        history_count,
-    ))))
-    .style(Style::default().fg(Color::DarkGray))
-    .alignment(Alignment::Right);
+    /// Keep running, and print matching commands as they are added, like
+    /// `tail -f`
+    #[clap(long, conflicts_with = "interactive")]
+    watch: bool,
 
-    let input = Paragraph::new(format!("] {}", app.input.clone())).block(Block::default());
+    // Resolved from the environment and settings by `run`
+    #[clap(skip)]
+    session_id: Option<String>,
 
-    f.render_widget(title, header_chunks[0]);
-    f.render_widget(help, header_chunks[1]);
-    f.render_widget(stats, header_chunks[2]);
+    #[clap(skip)]
+    cd_commands: Vec<String>,
 
-    app.render_results(f, chunks[1], Block::default());
-    f.render_widget(input, chunks[2]);
+    #[clap(skip)]
+    subdirs: bool,
 
-    f.set_cursor(
-        // Put cursor past the end of the input text
-        chunks[2].x + app.input.width() as u16 + 2,
-        // Move one line down, from the border to the input line
-        chunks[2].y + 1,
-    );
+    #[clap(skip)]
+    boot_time: Option<DateTime<Utc>>,
 }
 
-// this is a big blob of horrible! clean it up!
-// for now, it works. But it'd be great if it were more easily readable, and
-// modular. I'd like to add some more stats and stuff at some point
-#[allow(clippy::cast_possible_truncation)]
-async fn select_history(
-    query: &[String],
-    search_mode: SearchMode,
-    style: atuin_client::settings::Style,
-    db: &mut (impl Database + Send + Sync),
-) -> Result<String> {
-    let stdout = stdout().into_raw_mode()?;
-    let stdout = MouseTerminal::from(stdout);
-    let stdout = AlternateScreen::from(stdout);
-    let backend = TermionBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+/// The runs --watch hasn't been through yet, from those it fetched since
+/// `from`. Only runs from `start` on count, and `seen` remembers the rest until
+/// they're older than `from`, as each fetch overlaps the last
+fn unseen(
+    history: Vec<History>,
+    start: DateTime<Utc>,
+    from: DateTime<Utc>,
+    seen: &mut HashMap<String, DateTime<Utc>>,
+) -> Vec<History> {
+    seen.retain(|_, timestamp| *timestamp >= from);
 
-    // Setup event handlers
-    let events = Events::new();
+    history
+        .into_iter()
+        .filter(|h| h.timestamp >= start && seen.insert(h.id.clone(), h.timestamp).is_none())
+        .collect()
+}
 
-    let mut app = State {
-        input: query.join(" "),
-        results: Vec::new(),
-        results_state: ListState::default(),
-    };
+/// Pick the first, or last, entry of each local day from entries sorted
+/// oldest first, keeping them in that order
+fn sample_days(history: Vec<History>, last: bool) -> Vec<History> {
+    let mut days = BTreeMap::new();
 
-    query_results(&mut app, search_mode, db).await?;
+    for h in history {
+        let day = h.timestamp.with_timezone(&Local).date().naive_local();
 
-    loop {
-        let history_count = db.history_count().await?;
-        // Handle input
-        if let Event::Input(input) = events.next()? {
-            if let Some(output) = key_handler(input, search_mode, db, &mut app).await {
-                return Ok(output);
+        if last {
+            days.insert(day, h);
+        } else {
+            days.entry(day).or_insert(h);
+        }
+    }
+
+    days.into_values().collect()
+}
+
+/// Merge each run of the same command, oldest first, into its first entry.
+/// The command notes how many times it was ran, and how long from the start
+/// of the first to the end of the last, while the exit code is the last one's
+fn collapse_repeats(history: Vec<History>) -> Vec<History> {
+    let mut collapsed: Vec<(History, usize, DateTime<Utc>)> = Vec::new();
+
+    for h in history {
+        let end = h.timestamp + chrono::Duration::nanoseconds(std::cmp::max(h.duration, 0));
+
+        match collapsed.last_mut() {
+            Some((first, count, last_end)) if first.command.trim() == h.command.trim() => {
+                *count += 1;
+                *last_end = end;
+                first.exit = h.exit;
             }
+            _ => collapsed.push((h, 1, end)),
         }
+    }
+
+    collapsed
+        .into_iter()
+        .map(|(mut h, count, end)| {
+            if count > 1 {
+                let span = (end - h.timestamp).to_std().unwrap_or_default();
+                let span = humantime::format_duration(Duration::from_secs(span.as_secs()));
+
+                h.command = format!(
+                    "{}  # ran {} times over {}",
+                    h.command.trim_end(),
+                    count,
+                    span
+                );
+            }
+
+            h
+        })
+        .collect()
+}
+
+/// The `n`th of a session's commands, oldest first, counting back from the
+/// most recent when negative. Commands still running, such as the search
+/// looking for it, are skipped
+fn nth_command(history: Vec<History>, n: i64) -> Result<History> {
+    let mut history: Vec<History> = history.into_iter().filter(|h| h.duration != -1).collect();
+    let len = history.len();
+
+    let i = match n {
+        0 => return Err(eyre!("--nth counts from 1, or back from -1")),
+        n if n > 0 => usize::try_from(n - 1).ok(),
+        n => usize::try_from(n.unsigned_abs())
+            .ok()
+            .and_then(|back| len.checked_sub(back)),
+    };
+
+    match i.filter(|i| *i < len) {
+        Some(i) => Ok(history.swap_remove(i)),
+        None => Err(eyre!(
+            "--nth {} is out of range, as the session only has {} commands",
+            n,
+            len
+        )),
+    }
+}
+
+fn parse_time(time: &str) -> Result<DateTime<Utc>> {
+    chrono_english::parse_date_string(time, Utc::now(), chrono_english::Dialect::Uk)
+        .wrap_err_with(|| format!("could not parse time {:?}", time))
+}
+
+/// When the machine booted, from the btime line of /proc/stat
+#[cfg(target_os = "linux")]
+fn boot_time() -> Result<DateTime<Utc>> {
+    let stat = fs::read_to_string("/proc/stat")?;
+    let btime = parse_btime(&stat).ok_or_else(|| eyre!("could not find btime in /proc/stat"))?;
+
+    Ok(Utc.timestamp(btime, 0))
+}
+
+#[cfg(target_os = "linux")]
+fn parse_btime(stat: &str) -> Option<i64> {
+    stat.lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .and_then(|btime| btime.trim().parse().ok())
+}
 
-        let compact = match style {
-            atuin_client::settings::Style::Auto => {
-                terminal.size().map(|size| size.height < 14).unwrap_or(true)
+/// When the machine booted, from `sysctl kern.boottime`
+#[cfg(target_os = "macos")]
+fn boot_time() -> Result<DateTime<Utc>> {
+    let output = std::process::Command::new("sysctl")
+        .args(["-n", "kern.boottime"])
+        .output()
+        .wrap_err("could not run sysctl")?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let sec = parse_boottime(&output)
+        .ok_or_else(|| eyre!("could not parse kern.boottime {:?}", output.trim()))?;
+
+    Ok(Utc.timestamp(sec, 0))
+}
+
+/// Pull the seconds out of a boottime like `{ sec = 1665000000, usec = 0 }`
+#[cfg(target_os = "macos")]
+fn parse_boottime(boottime: &str) -> Option<i64> {
+    let sec = boottime.split("sec = ").nth(1)?;
+
+    sec.split(',').next()?.trim().parse().ok()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn boot_time() -> Result<DateTime<Utc>> {
+    Err(eyre!("--since-boot is not supported on this platform"))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SortKey {
+    Time,
+    Duration,
+    Exit,
+    Command,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sort {
+    key: SortKey,
+    descending: bool,
+}
+
+impl FromStr for Sort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, direction) = s.split_once(':').unwrap_or((s, "asc"));
+
+        let key = match key {
+            "time" => SortKey::Time,
+            "duration" => SortKey::Duration,
+            "exit" => SortKey::Exit,
+            "command" => SortKey::Command,
+            _ => {
+                return Err(format!(
+                    "invalid sort key {:?}, expected one of time, duration, exit, command",
+                    key
+                ))
             }
-            atuin_client::settings::Style::Compact => true,
-            atuin_client::settings::Style::Full => false,
         };
-        if compact {
-            terminal.draw(|f| draw_compact(f, history_count, &mut app))?;
-        } else {
-            terminal.draw(|f| draw(f, history_count, &mut app))?;
-        }
+
+        let descending = match direction {
+            "asc" => false,
+            "desc" => true,
+            _ => {
+                return Err(format!(
+                    "invalid sort direction {:?}, expected asc or desc",
+                    direction
+                ))
+            }
+        };
+
+        Ok(Self { key, descending })
     }
 }
 
-// This is supposed to more-or-less mirror the command line version, so ofc
-// it is going to have a lot of args
-#[allow(clippy::too_many_arguments)]
-pub async fn run(
-    settings: &Settings,
-    cwd: Option<String>,
-    exit: Option<i64>,
-    interactive: bool,
-    human: bool,
-    exclude_exit: Option<i64>,
-    exclude_cwd: Option<String>,
-    before: Option<String>,
-    after: Option<String>,
-    cmd_only: bool,
-    query: &[String],
-    db: &mut (impl Database + Send + Sync),
-) -> Result<()> {
-    let dir = if let Some(cwd) = cwd {
-        if cwd == "." {
+impl Sort {
+    /// Sort the results, in the order they should be read
+    fn apply(self, results: &mut [History]) {
+        results.sort_by(|a, b| {
+            let ord = match self.key {
+                SortKey::Time => a.timestamp.cmp(&b.timestamp),
+                SortKey::Duration => a.duration.cmp(&b.duration),
+                SortKey::Exit => a.exit.cmp(&b.exit),
+                SortKey::Command => a.command.cmp(&b.command),
+            };
+
+            if self.descending {
+                ord.reverse()
+            } else {
+                ord
+            }
+        });
+    }
+}
+
+impl Cmd {
+    pub async fn run(
+        mut self,
+        db: &mut (impl Database + Send + Sync),
+        settings: &Settings,
+    ) -> Result<()> {
+        // Shell integrations may want a different search mode to the one in the
+        // config file, eg for interactive shells vs scripts
+        let mut settings = settings.clone();
+        if let Some(mode) = std::env::var("ATUIN_SEARCH_MODE")
+            .ok()
+            .and_then(|m| m.parse::<SearchMode>().ok())
+        {
+            settings.search_mode = mode;
+        }
+
+        if let Some(mode) = self.search_mode {
+            settings.search_mode = mode;
+        }
+
+        if let Some(min_length) = self.min_length {
+            settings.min_command_length = min_length;
+        }
+
+        if self.has_duration {
+            settings.hide_unknown_duration = true;
+        }
+
+        if self.print_only {
+            settings.never_execute = true;
+        }
+
+        if self.session {
+            self.session_id = Some(std::env::var("ATUIN_SESSION").wrap_err(
+                "--session needs ATUIN_SESSION to be set, by the atuin shell integration",
+            )?);
+        }
+
+        if self.since_boot {
+            self.boot_time = Some(boot_time()?);
+        }
+
+        self.cd_commands = settings.cd_commands.clone();
+        self.subdirs = settings.cwd_include_subdirs && !self.no_subdirs;
+
+        if self.cwd.as_deref() == Some(".") {
             let current = std::env::current_dir()?;
             let current = current.as_os_str();
             let current = current.to_str().unwrap();
 
-            Some(current.to_owned())
+            self.cwd = Some(current.to_owned());
+        }
+
+        debug!(
+            "searching in {:?} mode with {:?}",
+            settings.search_mode, self
+        );
+
+        if let Some(path) = self.db.take() {
+            if !path.is_file() {
+                return Err(eyre!("could not find database {:?}", path));
+            }
+
+            let mut db = Sqlite::open_read_only(&path)
+                .await
+                .wrap_err_with(|| format!("{:?} is not an atuin database", path))?;
+
+            self.search(&mut db, &settings).await
         } else {
-            Some(cwd)
+            self.search(db, &settings).await
         }
-    } else {
-        None
-    };
+    }
 
-    if interactive {
-        let item = select_history(query, settings.search_mode, settings.style, db).await?;
-        eprintln!("{}", item);
-    } else {
-        let results = db
-            .search(None, settings.search_mode, query.join(" ").as_str())
+    async fn search(
+        &self,
+        db: &mut (impl Database + Send + Sync),
+        settings: &Settings,
+    ) -> Result<()> {
+        if self.list_collections {
+            for (name, count) in db.collections().await? {
+                println!("{}\t{}", name, count);
+            }
+
+            Ok(())
+        } else if self.reindex {
+            reindex(db).await
+        } else if self.interactive {
+            let collection = self.collection.as_deref().unwrap_or("default");
+            let max_entries = self.max_entries.unwrap_or(MAX_ENTRIES);
+            render_template(&settings.share_format, |_| String::new())
+                .map_err(|e| eyre!("invalid share_format: {}", e))?;
+
+            let (selected, scratch) = select_history(
+                &self.query,
+                settings,
+                db,
+                collection,
+                max_entries,
+                self.scratch,
+                self.host.as_deref(),
+            )
             .await?;
+            let shell = self.shell.or_else(shell_from_env);
+            let commands = selected.commands();
 
-        // TODO: This filtering would be better done in the SQL query, I just
-        // need a nice way of building queries.
-        let results: Vec<History> = results
-            .iter()
-            .filter(|h| {
-                if let Some(exit) = exit {
-                    if h.exit != exit {
-                        return false;
-                    }
-                }
+            // The shell integrations only pick up what is written to stderr
+            let picked = commands.iter().any(|c| !c.is_empty());
+            if scratch && picked && !settings.never_execute {
+                eprintln!("{}", SCRATCH_MARKER);
+            }
 
-                if let Some(exit) = exclude_exit {
-                    if h.exit == exit {
-                        return false;
-                    }
+            if settings.never_execute {
+                for command in commands.iter().filter(|c| !c.is_empty()) {
+                    println!("{}", command);
                 }
-
-                if let Some(cwd) = &exclude_cwd {
-                    if h.cwd.as_str() == cwd.as_str() {
-                        return false;
-                    }
+            } else if self.replay && commands.len() > 1 {
+                for (i, command) in commands.iter().enumerate() {
+                    eprintln!("# atuin replay {}/{}", i + 1, commands.len());
+                    eprintln!("{}", fill_prompt(command, shell));
                 }
+            } else {
+                eprintln!("{}", fill_prompt(&commands.join("\n"), shell));
+            }
 
-                if let Some(cwd) = &dir {
-                    if h.cwd.as_str() != cwd.as_str() {
-                        return false;
-                    }
-                }
+            Ok(())
+        } else if let Some(collection) = &self.collection {
+            let mut results = db.collection(collection).await?;
 
-                if let Some(before) = &before {
-                    let before = chrono_english::parse_date_string(
-                        before.as_str(),
-                        Utc::now(),
-                        chrono_english::Dialect::Uk,
-                    );
+            // print_list prints the last entry first
+            results.reverse();
 
-                    if before.is_err() || h.timestamp.gt(&before.unwrap()) {
-                        return false;
-                    }
-                }
+            self.print(settings, &mut self.output()?, results)
+        } else if let Some(session) = &self.context_session {
+            let session = match session {
+                Some(session) => session.clone(),
+                None => std::env::var("ATUIN_SESSION").wrap_err(
+                    "--context-session needs a session id, or ATUIN_SESSION to be set by the atuin shell integration",
+                )?,
+            };
 
-                if let Some(after) = &after {
-                    let after = chrono_english::parse_date_string(
-                        after.as_str(),
-                        Utc::now(),
-                        chrono_english::Dialect::Uk,
-                    );
+            let mut results = db.session_history(&session).await?;
+            if self.collapse_repeats {
+                results = collapse_repeats(results);
+            }
 
-                    if after.is_err() || h.timestamp.lt(&after.unwrap()) {
-                        return false;
-                    }
-                }
+            // print_list prints the last entry first
+            results.reverse();
 
-                true
-            })
-            .map(std::borrow::ToOwned::to_owned)
-            .collect();
+            self.print(settings, &mut self.output()?, results)
+        } else if let (Some(n), Some(session)) = (self.nth, &self.session_id) {
+            let h = nth_command(db.session_history(session).await?, n)?;
+            self.print(settings, &mut self.output()?, vec![h])
+        } else if self.find_duplicates {
+            self.print_duplicates(settings, db, &mut self.output()?)
+                .await
+        } else if self.watch {
+            self.watch(settings, db).await
+        } else if let Some(path) = &self.export_atuin {
+            let mut history = self.every_run(settings, db).await?;
+            self.redact(&settings.redact_patterns, &mut history)?;
+            if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                fs::create_dir_all(dir)?;
+            }
+
+            let file = fs::File::create(path)?;
+            atuin::export(BufWriter::new(file), &history)?;
+            eprintln!("exported {} commands to {}", history.len(), path.display());
 
-        super::history::print_list(&results, human, cmd_only);
+            Ok(())
+        } else {
+            self.run_non_interactive(settings, db).await
+        }
     }
 
-    Ok(())
+    async fn watch(
+        &self,
+        settings: &Settings,
+        db: &mut (impl Database + Send + Sync),
+    ) -> Result<()> {
+        let query = self.query.join(" ");
+        let lookback = chrono::Duration::from_std(WATCH_LOOKBACK)?;
+
+        // Only commands added from now on are printed. Those seen recently are
+        // remembered, as each check overlaps the last
+        let start = Utc::now();
+        let mut since = start;
+        let mut seen = HashMap::<String, DateTime<Utc>>::new();
+        let mut out = self.output()?;
+
+        loop {
+            tokio::time::sleep(WATCH_INTERVAL).await;
+
+            let now = Utc::now();
+            let from = since - lookback;
+
+            let mut results: Vec<History> =
+                unseen(db.range(from, now).await?, start, from, &mut seen)
+                    .into_iter()
+                    .filter(|h| {
+                        self.keep(h)
+                            && long_enough(h, settings.min_command_length)
+                            && (h.duration != -1 || !settings.hide_unknown_duration)
+                            && (query.is_empty()
+                                || matching_line(&h.command, &query, settings.search_mode)
+                                    .is_some())
+                    })
+                    .collect();
+
+            if !results.is_empty() {
+                // print_list prints the last entry first
+                results.reverse();
+                self.print(settings, &mut out, results)?;
+            }
+
+            since = now;
+        }
+    }
+
+    /// Search for the query, without the weakest fuzzy matches. Also returns
+    /// how many results there were before those were dropped
+    async fn find(
+        &self,
+        settings: &Settings,
+        db: &mut (impl Database + Send + Sync),
+        mode: SearchMode,
+        query: &str,
+    ) -> Result<(Vec<History>, usize)> {
+        check_regex(mode, query)?;
+        let filters = self.sql_filters();
+
+        if self.fixed_string {
+            let results = db.search_fixed(None, query, &filters).await?;
+            let found = results.len();
+            return Ok((results, found));
+        }
+
+        let aliases = settings.fuzzy_aliases();
+        let results =
+            search_with_aliases(db, None, mode, query, &filters, aliases.as_ref()).await?;
+        let found = results.len();
+
+        let results = match mode {
+            SearchMode::Fuzzy => filter_fuzzy(
+                query,
+                settings.fuzzy_min_score,
+                aliases.as_ref(),
+                reorder_boundaries(query, &settings.fuzzy_boundaries, results),
+            ),
+            _ => results,
+        };
+
+        if mode == SearchMode::Fuzzy {
+            debug!("fuzzy_min_score kept {} of {}", results.len(), found);
+        }
+        Ok((results, found))
+    }
+
+    async fn run_non_interactive(
+        &self,
+        settings: &Settings,
+        db: &mut (impl Database + Send + Sync),
+    ) -> Result<()> {
+        // Every report, as well as the results, is written here
+        let mut out = self.output()?;
+
+        if let Some(split) = &self.diff {
+            return self.print_diff(settings, db, split, &mut out).await;
+        }
+
+        if self.first_of_day || self.last_of_day {
+            return self.print_days(settings, db, &mut out).await;
+        }
+
+        if self.top {
+            return self.print_top(settings, db, &mut out).await;
+        }
+
+        // Every run counts towards the time spent, not just the latest
+        if let Some(group) = self.aggregate_time {
+            let mut history = self.every_run(settings, db).await?;
+            self.redact(&settings.redact_patterns, &mut history)?;
+            return print_time_spent(&mut out, &aggregate_durations(group, &history));
+        }
+
+        if self.explain_time || self.distinct_cwd_commands || self.group_by.is_some() {
+            let mut history = self.every_run(settings, db).await?;
+            self.redact(&settings.redact_patterns, &mut history)?;
+            let report = if let Some(by) = self.group_by {
+                render_groups(&summarize_groups(&history, by), by, &settings.time_format)?
+            } else if self.explain_time {
+                render_heatmap(&bucket_times(&history))
+            } else {
+                render_cwd_commands(&group_by_cwd(&history), settings.trim_commands)
+            };
+
+            write!(out, "{}", report)?;
+            return Ok(());
+        }
+
+        let query = self.query.join(" ");
+        let (query, tags) = self.split_tags(&query);
+
+        // A line can match anywhere within a multi-line command, not just at
+        // the start
+        let mode = match settings.search_mode {
+            SearchMode::Prefix if self.lines => SearchMode::FullText,
+            mode => mode,
+        };
+        let (results, found) = self.find(settings, db, mode, &query).await?;
+        let weak = found - results.len();
+
+        // Whatever the database couldn't filter by
+        let tagged = tagged_commands(db, &tags).await?;
+        let (mut results, mut removed) = self.apply_filters(
+            results,
+            settings.min_command_length,
+            settings.hide_unknown_duration,
+            tagged.as_ref(),
+        );
+        debug!("filters removed {:?}, leaving {}", removed, results.len());
+
+        if self.explain_filters {
+            if weak > 0 {
+                removed.push(("fuzzy_min_score", weak));
+            }
+            explain_filters(found, &removed, results.len());
+        }
+
+        if self.lines {
+            results = results
+                .into_iter()
+                .filter_map(|mut h| {
+                    let (n, line) = matching_line(&h.command, &query, settings.search_mode)?;
+                    h.command = format!("{}: {}", n, line);
+                    Some(h)
+                })
+                .collect();
+        }
+
+        if let Some(shell) = self.generate_completions {
+            // Results are unique by command, and hold when each was last ran
+            let last_ran: HashMap<String, DateTime<Utc>> = results
+                .into_iter()
+                .map(|h| (h.command, h.timestamp))
+                .collect();
+            let commands: Vec<String> = last_ran.keys().cloned().collect();
+            let counts = db.command_counts(&commands).await?;
+
+            let ranked = rank_by_frequency(&counts, &last_ran, settings.frequency_tiebreak);
+            let redactor = self.redactor(&settings.redact_patterns)?;
+            print_completions(&mut out, shell, &ranked, redactor.as_ref())?;
+
+            return Ok(());
+        }
+
+        if let Some(head) = self.head {
+            results.sort_by_key(|h| h.timestamp);
+            results.truncate(head);
+
+            // print_list expects newest first
+            results.reverse();
+        }
+
+        if let Some(sort) = self.sort {
+            sort.apply(&mut results);
+
+            // print_list prints the last entry first
+            results.reverse();
+        }
+
+        if let Some(max) = self.max_entries {
+            if self.sort.is_some() {
+                // print_list prints the last entry first, so keep the end
+                results.drain(..results.len().saturating_sub(max));
+            } else {
+                // Otherwise results are newest first, so keep the most recent
+                results.truncate(max);
+            }
+        }
+
+        if self.preview {
+            let shown = std::cmp::min(results.len(), PREVIEW_COUNT);
+            writeln!(out, "{} matches, showing first {}", results.len(), shown)?;
+
+            // print_list prints the last entry first, so keep the end
+            results.drain(..results.len() - shown);
+        }
+
+        self.print(settings, &mut out, results)
+    }
+
+    async fn print_diff(
+        &self,
+        settings: &Settings,
+        db: &mut (impl Database + Send + Sync),
+        split: &str,
+        out: &mut (dyn Write + Send),
+    ) -> Result<()> {
+        let split = parse_time(split)?;
+        let (start, end) = self.window()?;
+
+        // Searches only return the latest run of each command, so they can
+        // only tell which commands match the query. Every run in each window
+        // comes from the ranges instead
+        let query = self.query.join(" ");
+        let matching = self.matching_commands(settings, db, &query).await?;
+
+        let commands = |history: Vec<History>| -> BTreeSet<String> {
+            history
+                .into_iter()
+                .filter(|h| {
+                    self.keep(h)
+                        && long_enough(h, settings.min_command_length)
+                        && matching.as_ref().map_or(true, |m| m.contains(&h.command))
+                })
+                .map(|h| h.command)
+                .collect()
+        };
+
+        let (old, new) = split_runs(db.range(start, end).await?, split);
+        let (old, new) = (commands(old), commands(new));
+
+        let diff = diff_commands(&old, &new);
+        writeln!(
+            out,
+            "{} added, {} removed, {} in common",
+            diff.added.len(),
+            diff.removed.len(),
+            diff.common.len()
+        )?;
+
+        let redactor = self.redactor(&settings.redact_patterns)?;
+        let lines = diff
+            .added
+            .iter()
+            .map(|c| ('+', c))
+            .chain(diff.removed.iter().map(|c| ('-', c)))
+            .chain(diff.common.iter().map(|c| (' ', c)));
+        for (marker, command) in lines {
+            let command = redacted(redactor.as_ref(), command);
+            writeln!(out, "{} {}", marker, display_command(&command, true))?;
+        }
+
+        Ok(())
+    }
+
+    /// Print how many copies of each command were recorded within
+    /// --duplicate-window of each other, or the copies themselves when asked
+    /// for particular fields
+    async fn print_duplicates(
+        &self,
+        settings: &Settings,
+        db: &mut (impl Database + Send + Sync),
+        out: &mut (dyn Write + Send),
+    ) -> Result<()> {
+        let window = match &self.duplicate_window {
+            Some(window) => humantime::parse_duration(window)
+                .wrap_err_with(|| format!("invalid --duplicate-window {:?}", window))?,
+            None => DUPLICATE_WINDOW,
+        };
+
+        let mut duplicates = db.duplicates(chrono::Duration::from_std(window)?).await?;
+        duplicates.retain(|h| self.keep(h));
+
+        if self.fields.is_some() || self.cmd_only {
+            // print_list prints the last entry first
+            duplicates.reverse();
+            return self.print(settings, out, duplicates);
+        }
+
+        if duplicates.is_empty() {
+            eprintln!("no duplicates found");
+        } else {
+            self.redact(&settings.redact_patterns, &mut duplicates)?;
+            let summary = summarize_duplicates(&duplicates);
+            write!(
+                out,
+                "{}",
+                render_duplicates(&summary, &settings.time_format)?
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// The times given with --after and --before, or the start of time and now
+    fn window(&self) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+        let start = match &self.after {
+            Some(after) => parse_time(after)?,
+            None => Utc.timestamp(0, 0),
+        };
+        let end = match &self.before {
+            Some(before) => parse_time(before)?,
+            None => Utc::now(),
+        };
+
+        Ok((start, end))
+    }
+
+    /// Print the matching commands ran most often within --after and
+    /// --before, ranked. Counting happens in the database, so only the query
+    /// and the time window apply
+    async fn print_top(
+        &self,
+        settings: &Settings,
+        db: &mut (impl Database + Send + Sync),
+        out: &mut (dyn Write + Send),
+    ) -> Result<()> {
+        let (start, end) = self.window()?;
+
+        let query = self.query.join(" ");
+        let matching = self.matching_commands(settings, db, &query).await?;
+
+        let top = db
+            .top_commands(start, end)
+            .await?
+            .into_iter()
+            .filter(|(command, _)| matching.as_ref().map_or(true, |m| m.contains(command)))
+            .take(self.max_entries.unwrap_or(usize::MAX));
+
+        let redactor = self.redactor(&settings.redact_patterns)?;
+        let mut writer = TabWriter::new(out).padding(2);
+        for (i, (command, count)) in top.enumerate() {
+            let command = redacted(redactor.as_ref(), &command);
+            writeln!(
+                writer,
+                "{}\t{}\t{}",
+                i + 1,
+                count,
+                display_command(&command, settings.trim_commands)
+            )?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// The commands matching the query, or None if there's no query to match
+    async fn matching_commands(
+        &self,
+        settings: &Settings,
+        db: &mut (impl Database + Send + Sync),
+        query: &str,
+    ) -> Result<Option<HashSet<String>>> {
+        if query.is_empty() {
+            return Ok(None);
+        }
+        check_regex(settings.search_mode, query)?;
+
+        let results = if self.fixed_string {
+            db.search_fixed(None, query, &Filters::default()).await?
+        } else {
+            db.search(None, settings.search_mode, query).await?
+        };
+
+        Ok(Some(results.into_iter().map(|h| h.command).collect()))
+    }
+
+    /// Take the tags out of the query, unless it's to be matched as is
+    fn split_tags<'a>(&self, query: &'a str) -> (String, Vec<&'a str>) {
+        if self.fixed_string {
+            (query.to_string(), Vec::new())
+        } else {
+            split_tags(query)
+        }
+    }
+
+    /// Every matching run within --after and --before, oldest first. As with
+    /// --diff, searches only return the latest run of each command, so every
+    /// run comes from the range instead
+    async fn every_run(
+        &self,
+        settings: &Settings,
+        db: &mut (impl Database + Send + Sync),
+    ) -> Result<Vec<History>> {
+        let (start, end) = self.window()?;
+
+        let query = self.query.join(" ");
+        let (query, tags) = self.split_tags(&query);
+        let matching = self.matching_commands(settings, db, &query).await?;
+        let tagged = tagged_commands(db, &tags).await?;
+
+        Ok(db
+            .range(start, end)
+            .await?
+            .into_iter()
+            .filter(|h| {
+                matching.as_ref().map_or(true, |m| m.contains(&h.command))
+                    && self
+                        .rejected_by(
+                            h,
+                            settings.min_command_length,
+                            settings.hide_unknown_duration,
+                            tagged.as_ref(),
+                        )
+                        .is_none()
+            })
+            .collect())
+    }
+
+    /// Print the first or last matching command of each day, within --after
+    /// and --before
+    async fn print_days(
+        &self,
+        settings: &Settings,
+        db: &mut (impl Database + Send + Sync),
+        out: &mut (dyn Write + Send),
+    ) -> Result<()> {
+        let history = self.every_run(settings, db).await?;
+        let mut days = sample_days(history, self.last_of_day);
+
+        // print_list prints the last entry first
+        days.reverse();
+        self.print(settings, out, days)
+    }
+
+    /// The filters the database can apply itself. Everything else is left to
+    /// `apply_filters`, as is everything with --invert, and with
+    /// --explain-filters so each filter is still counted
+    fn sql_filters(&self) -> Filters {
+        if self.invert || self.explain_filters {
+            return Filters::default();
+        }
+
+        Filters {
+            exit: self.exit,
+            exclude_exit: self.exclude_exit,
+            cwd: self.cwd.clone(),
+            subdirs: self.subdirs,
+            exclude_cwd: self.exclude_cwd.clone(),
+            host: self.host.clone(),
+            ..Filters::default()
+        }
+    }
+
+    /// Whether a history entry should be shown, taking --invert into account
+    fn keep(&self, h: &History) -> bool {
+        self.matches(h) != self.invert
+    }
+
+    /// Apply every filter to the search results, counting how many entries
+    /// each removed. An entry only counts against the first filter it fails
+    fn apply_filters(
+        &self,
+        results: Vec<History>,
+        min_length: usize,
+        hide_unknown_duration: bool,
+        tagged: Option<&HashSet<String>>,
+    ) -> (Vec<History>, Vec<(&'static str, usize)>) {
+        let mut kept = Vec::with_capacity(results.len());
+        let mut removed = Vec::<(&'static str, usize)>::new();
+
+        for h in results {
+            match self.rejected_by(&h, min_length, hide_unknown_duration, tagged) {
+                Some(filter) => match removed.iter_mut().find(|(f, _)| *f == filter) {
+                    Some((_, n)) => *n += 1,
+                    None => removed.push((filter, 1)),
+                },
+                None => kept.push(h),
+            }
+        }
+
+        (kept, removed)
+    }
+
+    /// Which filter, if any, stops a history entry from being shown
+    fn rejected_by(
+        &self,
+        h: &History,
+        min_length: usize,
+        hide_unknown_duration: bool,
+        tagged: Option<&HashSet<String>>,
+    ) -> Option<&'static str> {
+        match (self.failed_filter(h), self.invert) {
+            (Some(filter), false) => return Some(filter),
+            (None, true) => return Some("invert"),
+            _ => {}
+        }
+
+        if !long_enough(h, min_length) {
+            return Some("min-length");
+        }
+
+        if h.duration == -1 && hide_unknown_duration {
+            return Some("has-duration");
+        }
+
+        if tagged.map_or(false, |t| !t.contains(&h.command)) {
+            return Some("tag");
+        }
+
+        None
+    }
+
+    /// Whether a history entry passes every filter given on the command line
+    fn matches(&self, h: &History) -> bool {
+        self.failed_filter(h).is_none()
+    }
+
+    /// The first filter given on the command line that a history entry fails
+    fn failed_filter(&self, h: &History) -> Option<&'static str> {
+        if let Some(exit) = self.exit {
+            if h.exit != exit {
+                return Some("exit");
+            }
+        }
+
+        if let Some(exit) = self.exclude_exit {
+            if h.exit == exit {
+                return Some("exclude-exit");
+            }
+        }
+
+        if let Some(signal) = self.signal {
+            if h.exit != signal.exit_code() {
+                return Some("signal");
+            }
+        }
+
+        if let Some(cwd) = &self.exclude_cwd {
+            if h.cwd.as_str() == cwd.as_str() {
+                return Some("exclude-cwd");
+            }
+        }
+
+        if let Some(cwd) = &self.cwd {
+            if !in_dir(&h.cwd, cwd, self.subdirs) {
+                return Some("cwd");
+            }
+        }
+
+        if let Some(host) = &self.host {
+            if !on_host(h, host) {
+                return Some("host");
+            }
+        }
+
+        if let Some(host) = &self.exclude_host {
+            if on_host(h, host) {
+                return Some("exclude-host");
+            }
+        }
+
+        if self.privileged && !h.is_privileged() {
+            return Some("privileged");
+        }
+
+        if let Some(session) = &self.session_id {
+            if &h.session != session {
+                return Some("session");
+            }
+        }
+
+        if self.only_cd && !changes_dir(h, &self.cd_commands) {
+            return Some("only-cd");
+        }
+
+        if self.pipelines && !is_pipeline(&h.command) {
+            return Some("pipelines");
+        }
+
+        if !self.arg.is_empty() {
+            let args = arguments(&h.command);
+            if !self.arg.iter().all(|r| r.is_match(args)) {
+                return Some("arg");
+            }
+        }
+
+        if let Some(before) = &self.before {
+            let before = chrono_english::parse_date_string(
+                before.as_str(),
+                Utc::now(),
+                chrono_english::Dialect::Uk,
+            );
+
+            if before.is_err() || h.timestamp.gt(&before.unwrap()) {
+                return Some("before");
+            }
+        }
+
+        if let Some(boot_time) = self.boot_time {
+            if h.timestamp < boot_time {
+                return Some("since-boot");
+            }
+        }
+
+        if let Some(after) = &self.after {
+            let after = chrono_english::parse_date_string(
+                after.as_str(),
+                Utc::now(),
+                chrono_english::Dialect::Uk,
+            );
+
+            if after.is_err() || h.timestamp.lt(&after.unwrap()) {
+                return Some("after");
+            }
+        }
+
+        None
+    }
+
+    /// The redactor for --redact, or None without it
+    fn redactor(&self, patterns: &[String]) -> Result<Option<Redactor>> {
+        if self.redact {
+            Redactor::new(patterns).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// With --redact, hide secrets in the commands and directories about to
+    /// be printed
+    fn redact(&self, patterns: &[String], history: &mut [History]) -> Result<()> {
+        if let Some(redactor) = self.redactor(patterns)? {
+            for h in history {
+                h.command = redactor.redact(&h.command).into_owned();
+                h.cwd = redactor.redact(&h.cwd).into_owned();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Where results are written, the file given with --output or stdout
+    fn output(&self) -> Result<Box<dyn Write + Send>> {
+        match &self.output {
+            Some(output) => {
+                if let Some(dir) = output.parent().filter(|d| !d.as_os_str().is_empty()) {
+                    fs::create_dir_all(dir)?;
+                }
+
+                Ok(Box::new(fs::File::create(output)?))
+            }
+            None => Ok(Box::new(stdout())),
+        }
+    }
+
+    fn print(
+        &self,
+        settings: &Settings,
+        out: &mut dyn Write,
+        mut results: Vec<History>,
+    ) -> Result<()> {
+        if self.resolve_aliases {
+            let mut aliases = Aliases::new(settings.aliases.clone());
+            if let Some(alias_file) = &self.alias_file {
+                aliases.load(alias_file)?;
+            }
+
+            for h in &mut results {
+                h.command = aliases.resolve(&h.command).into_owned();
+            }
+        }
+
+        self.redact(&settings.redact_patterns, &mut results)?;
+
+        let fields = self
+            .fields
+            .clone()
+            .unwrap_or_else(|| Field::defaults(self.human, self.cmd_only, self.format));
+
+        super::history::write_list(
+            out,
+            &results,
+            self.human,
+            &fields,
+            &self.field_widths,
+            self.format,
+            settings,
+        )
+        .wrap_err("could not write results")
+    }
+}
+
+/// How the last query went, for the debug overlay and logs
+#[derive(Debug, Default, Clone, Copy)]
+struct QueryStats {
+    latency: Duration,
+
+    // Rows the database returned, and how many were left after filtering
+    fetched: usize,
+    shown: usize,
+
+    // Whether the rows came from narrowing those already loaded, rather
+    // than the database
+    narrowed: bool,
+}
+
+/// The results of the last database query, kept so that typing more of the
+/// query can narrow them down in memory with `incremental_search`
+#[derive(Debug)]
+struct Loaded {
+    mode: SearchMode,
+    query: String,
+    filters: Filters,
+
+    // Whether the query found fewer results than the limit, so nothing
+    // that matches a longer query can be missing
+    complete: bool,
+
+    results: Vec<History>,
+    tags: HashMap<String, Vec<String>>,
+    counts: HashMap<String, i64>,
+}
+
+/// What the UI returns once it's closed
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Selected {
+    /// The UI was closed without picking anything
+    Nothing,
+
+    /// A single command
+    One(String),
+
+    /// The commands marked with Alt-m, in the order they were ran
+    Marked(Vec<String>),
+}
+
+impl Selected {
+    fn commands(&self) -> &[String] {
+        match self {
+            Self::Nothing => &[],
+            Self::One(command) => std::slice::from_ref(command),
+            Self::Marked(commands) => commands,
+        }
+    }
+}
+
+/// The actions offered by the menu for the selected entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    CopyCommand,
+    CopyCwd,
+    CopyTime,
+    CopyShare,
+    Cd,
+}
+
+impl Action {
+    const ALL: [Self; 5] = [
+        Self::CopyCommand,
+        Self::CopyCwd,
+        Self::CopyTime,
+        Self::CopyShare,
+        Self::Cd,
+    ];
+
+    const fn label(self) -> &'static str {
+        match self {
+            Self::CopyCommand => "Copy command",
+            Self::CopyCwd => "Copy directory",
+            Self::CopyTime => "Copy time",
+            Self::CopyShare => "Copy to share",
+            Self::Cd => "cd to directory",
+        }
+    }
+}
+
+/// The fields of the filter form opened with Alt-f
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FormField {
+    Cwd,
+    Exit,
+    Host,
+    Before,
+    After,
+}
+
+impl FormField {
+    /// In the order shown
+    const ALL: [Self; 5] = [Self::Cwd, Self::Exit, Self::Host, Self::Before, Self::After];
+
+    const fn label(self) -> &'static str {
+        match self {
+            Self::Cwd => "cwd",
+            Self::Exit => "exit",
+            Self::Host => "host",
+            Self::Before => "before",
+            Self::After => "after",
+        }
+    }
+}
+
+/// What has been typed into each field of the filter form, in the order of
+/// `FormField::ALL`, and which field is being typed into
+#[derive(Debug, Default, Clone, PartialEq)]
+struct FilterForm {
+    values: [String; 5],
+    focus: usize,
+}
+
+impl FilterForm {
+    fn value(&self, field: FormField) -> &str {
+        &self.values[field as usize]
+    }
+
+    fn value_mut(&mut self, field: FormField) -> &mut String {
+        &mut self.values[field as usize]
+    }
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Default)]
+struct State {
+    input: String,
+
+    results: Vec<History>,
+
+    results_state: ListState,
+
+    // The (start, end) range of results currently on screen
+    results_window: (usize, usize),
+
+    // Every host in the results, before filtering by host
+    hosts: Vec<String>,
+
+    host_filter: Option<String>,
+
+    // Only show commands ran in this directory
+    cwd_filter: Option<String>,
+
+    // The directory the search UI was opened in
+    current_dir: Option<String>,
+
+    // Only show commands that failed
+    failed_only: bool,
+
+    // Only show commands with this exit code
+    exit_filter: Option<i64>,
+
+    // Only show commands ran before or after these times, along with the
+    // text they were parsed from
+    before_filter: Option<(String, DateTime<Utc>)>,
+    after_filter: Option<(String, DateTime<Utc>)>,
+
+    // The filter form, while it is open
+    form: Option<FilterForm>,
+
+    // Hide commands shorter than this
+    min_length: usize,
+
+    // Hide commands with an unknown duration
+    hide_unknown_duration: bool,
+
+    // Show how far each row is from the selection, for Alt-<num>
+    show_index: bool,
+
+    // Commands matching any of these need confirming before they are returned
+    dangerous: Vec<Regex>,
+
+    // A dangerous command, or marked commands with one that is, waiting to
+    // be confirmed
+    confirm: Option<Selected>,
+
+    // How many times each command in the results has been ran, if shown
+    show_counts: bool,
+    counts: HashMap<String, i64>,
+
+    // How many of the query's words each command in the results has, and how
+    // many words there are, if shown
+    show_terms: bool,
+    terms: HashMap<String, (usize, usize)>,
+
+    // Entries marked with Alt-m, to be returned together
+    marked: Vec<History>,
+
+    // The tags on each command in the results, and the tag being typed for
+    // the selected one after Alt-t
+    tags: HashMap<String, Vec<String>>,
+    tagging: Option<String>,
+
+    // The substitution being typed for the selected command after Alt-s
+    substituting: Option<String>,
+
+    // Show every line of the selected command, until the next key press
+    expanded: bool,
+
+    // Keep the selected command from being recorded when it is ran
+    scratch: bool,
+
+    // Group fuzzy results by their first word, each collapsed down to its
+    // first result unless it has been opened. Along with how many results
+    // are in each group of more than one
+    clusters: bool,
+    open_clusters: HashSet<String>,
+    cluster_sizes: HashMap<String, usize>,
+
+    // Draw a line between results last ran on different days, weeks and so on
+    group_separators: bool,
+
+    // The action selected in the action menu, while it is open
+    menu: Option<usize>,
+
+    // How counts and durations are written
+    locale: Locale,
+
+    // Recently used directories, and which is selected while the list is open
+    dirs: Vec<String>,
+    dirs_selected: Option<usize>,
+
+    // Text waiting to be copied to the clipboard
+    clipboard: Option<String>,
+
+    // The collection Alt-c adds to, and the ids of the entries already in it
+    collection: String,
+    collected: HashSet<String>,
+
+    // Show the debug overlay
+    debug: bool,
+
+    query_stats: QueryStats,
+
+    // Narrow the loaded results in memory as more of the query is typed,
+    // rather than querying the database again
+    incremental: bool,
+    loaded: Option<Loaded>,
+
+    // The formatted duration and time since each result ran, and when they
+    // were formatted
+    durations: Vec<(String, String)>,
+    durations_at: Option<Instant>,
+
+    // The most results to load for a query
+    max_entries: usize,
+
+    // Queries from earlier on, oldest first, and which of them is being shown
+    past_queries: Vec<String>,
+    recalled_query: Option<usize>,
+
+    // Give up on queries that take longer than this
+    search_timeout: Option<Duration>,
+
+    // When the results last came from the database, and how often to query
+    // it again while the UI is left open
+    loaded_at: Option<Instant>,
+    auto_refresh: Option<Duration>,
+
+    // Fuzzy matches starting on one of these rank higher
+    fuzzy_boundaries: String,
+
+    // Fuzzy matches scoring less than this are hidden
+    fuzzy_min_score: f64,
+
+    // Aliases that fuzzy search also matches commands with, from
+    // fuzzy_aliases
+    aliases: Option<Aliases>,
+
+    // Why the last query failed, if it did
+    query_error: Option<String>,
+
+    // What to show before anything is typed
+    empty_query: EmptyQuery,
+
+    // What the numbers for Alt-<num> count from
+    jump_mode: JumpMode,
+
+    // Whether to draw the full or compact UI, starting from the style setting
+    // and changed with Alt-l
+    layout: UiLayout,
+
+    // Hides secret environment variables in the shown commands
+    env_mask: Option<EnvMask>,
+
+    // The template for copying a command to share, and the format of its time
+    share_format: String,
+    time_format: String,
+
+    // Commands longer than this are cut short when shown, and only this much
+    // of them is searched for the matching line
+    max_display_length: usize,
+
+    // The inner line each multi-line result matched the query on, if shown
+    show_matching_line: bool,
+    matching_lines: HashMap<String, (usize, String)>,
+}
+
+impl State {
+    /// Remove the last grapheme cluster from the input, so that multi-codepoint
+    /// characters (emoji with modifiers, combining accents) are removed whole
+    fn pop_grapheme(&mut self) {
+        if let Some((i, _)) = self.input.grapheme_indices(true).next_back() {
+            self.input.truncate(i);
+        }
+    }
+
+    /// Remove the last space separated word from the input, along with the
+    /// space preceding it
+    fn pop_word(&mut self) {
+        let i = self
+            .input
+            .grapheme_indices(true)
+            .rev()
+            .find(|(_, g)| *g == " ")
+            .map_or(0, |(i, _)| i);
+
+        self.input.truncate(i);
+    }
+
+    /// Keep hold of the query, before it's replaced or cleared, so it can be
+    /// recalled later
+    fn remember_query(&mut self) {
+        if !self.input.is_empty() && self.past_queries.last() != Some(&self.input) {
+            self.past_queries.push(self.input.clone());
+        }
+    }
+
+    /// Swap the query for the one before it, going back around to the latest
+    /// once the oldest is reached
+    fn recall_query(&mut self) {
+        if self.recalled_query.is_none() {
+            self.remember_query();
+        }
+
+        let len = self.past_queries.len();
+        if len == 0 {
+            return;
+        }
+
+        let i = match self.recalled_query {
+            // The current query is the latest, so start from the one before
+            None if len > 1 && self.past_queries.last() == Some(&self.input) => len - 2,
+            Some(0) | None => len - 1,
+            Some(i) => i - 1,
+        };
+
+        self.recalled_query = Some(i);
+        self.input = self.past_queries[i].clone();
+    }
+
+    /// The on-screen width of the input. Each grapheme cluster occupies at most
+    /// two cells, no matter how many codepoints make it up
+    fn input_width(&self) -> usize {
+        self.input
+            .graphemes(true)
+            .map(|g| std::cmp::min(g.width(), 2))
+            .sum()
+    }
+
+    /// Work out which results are on screen, given the height of the list.
+    /// tui keeps the scroll offset of a `ListState` private, so this mirrors
+    /// how it scrolls just enough to keep the selection visible
+    fn scroll(&mut self, height: usize) -> (usize, usize) {
+        let len = self.results.len();
+        if len == 0 || height == 0 {
+            self.results_window = (0, 0);
+            return self.results_window;
+        }
+
+        let selected = self.results_state.selected().unwrap_or(0).min(len - 1);
+        let mut start = self.results_window.0.min(len - 1);
+
+        if selected >= start + height {
+            start = selected + 1 - height;
+        }
+        if selected < start {
+            start = selected;
+        }
+
+        self.results_window = (start, std::cmp::min(start + height, len));
+        self.results_window
+    }
+
+    /// Formatting every duration each frame adds up, so they're kept until
+    /// the results change. "ago" still needs to move on now and then
+    fn refresh_durations(&mut self) {
+        let outdated = self
+            .durations_at
+            .map_or(true, |at| at.elapsed() >= DURATIONS_REFRESH);
+
+        if outdated || self.durations.len() != self.results.len() {
+            self.durations = self.format_durations();
+            self.durations_at = Some(Instant::now());
+        }
+    }
+
+    /// How many lines the selected result takes up in a list `height` lines
+    /// tall. Only an expanded multi-line command takes more than one, and
+    /// never more than fit
+    fn expanded_height(&self, height: usize) -> usize {
+        let lines = self
+            .results_state
+            .selected()
+            .filter(|_| self.expanded)
+            .and_then(|i| self.results.get(i))
+            .map_or(1, |h| self.shown(&h.command).lines().count());
+
+        lines.clamp(1, std::cmp::max(height, 1))
+    }
+
+    /// How many results fit in a list `height` lines tall, as an expanded
+    /// selection and the lines between groups take up room of their own
+    fn rows(&self, height: usize) -> usize {
+        let rows = height + 1 - self.expanded_height(height);
+        if !self.group_separators {
+            return rows;
+        }
+
+        let start = self.results_window.0;
+        let rules = (start + 1..start + rows)
+            .filter(|i| self.starts_group(*i))
+            .count();
+
+        std::cmp::max(rows.saturating_sub(rules), 1)
+    }
+
+    /// Whether a result was last ran in an earlier group than the one after
+    /// it, such as yesterday rather than today
+    fn starts_group(&self, i: usize) -> bool {
+        let now = Local::now();
+        match (
+            self.results.get(i),
+            i.checked_sub(1).and_then(|i| self.results.get(i)),
+        ) {
+            (Some(h), Some(newer)) => {
+                age_group(now, h.timestamp) != age_group(now, newer.timestamp)
+            }
+            _ => false,
+        }
+    }
+
+    /// The line beneath a result starting a new group, if they are drawn. With
+    /// the first result at the bottom, this sits between it and the newer ones
+    fn group_rule(&self, i: usize, width: u16) -> Option<Spans<'static>> {
+        if !self.group_separators || !self.starts_group(i) {
+            return None;
+        }
+
+        // Leaving room for the highlight symbol
+        let rule = "─".repeat((width as usize).saturating_sub(3));
+        Some(Spans::from(Span::styled(
+            rule,
+            Style::default().fg(Color::DarkGray),
+        )))
+    }
+
+    /// How long ago the results came from the database, such as "loaded 2m
+    /// ago", as they don't show commands ran since
+    fn loaded_ago(&self) -> Option<String> {
+        let elapsed = self.loaded_at?.elapsed();
+
+        // Only whole seconds, so the counter doesn't flicker
+        let elapsed = Duration::from_secs(elapsed.as_secs());
+        Some(format!("loaded {}", self.locale.format_ago(elapsed)))
+    }
+
+    /// Whether `auto_refresh` says it's time to query the database again.
+    /// Not while a prompt, form or list is open, as that would change the
+    /// results from under it
+    fn refresh_due(&self) -> bool {
+        let open = self.prompting()
+            || self.form.is_some()
+            || self.menu.is_some()
+            || self.dirs_selected.is_some();
+
+        match (self.auto_refresh, self.loaded_at) {
+            (Some(every), Some(at)) => !open && at.elapsed() >= every,
+            _ => false,
+        }
+    }
+
+    /// Show no results, as for an empty query with `empty_query_behavior` set
+    /// to blank
+    fn clear_results(&mut self) {
+        self.results.clear();
+        self.results_state.select(None);
+        self.query_error = None;
+        self.durations_at = None;
+        self.query_stats.fetched = 0;
+        self.query_stats.shown = 0;
+        self.loaded_at = None;
+    }
+
+    /// Work out how many terms each result matched, and which line of it
+    /// matched, when they're to be shown
+    fn annotate_results(&mut self, query: &str, search_mode: SearchMode) {
+        if self.show_terms {
+            self.terms = self
+                .results
+                .iter()
+                .filter_map(|h| Some((h.command.clone(), matched_terms(query, &h.command)?)))
+                .collect();
+        }
+
+        if self.show_matching_line {
+            self.matching_lines = self
+                .results
+                .iter()
+                .filter_map(|h| {
+                    Some((
+                        h.command.clone(),
+                        inner_match(
+                            &truncate_display(&h.command, self.max_display_length),
+                            query,
+                            search_mode,
+                        )?,
+                    ))
+                })
+                .collect();
+        }
+    }
+
+    /// Put results with the same first word together, after the first of
+    /// them, and leave only that first one of each group that isn't open
+    fn cluster_results(&mut self) {
+        self.cluster_sizes.clear();
+        if !self.clusters {
+            return;
+        }
+
+        let mut order = Vec::new();
+        let mut groups: HashMap<String, Vec<History>> = HashMap::new();
+        for h in std::mem::take(&mut self.results) {
+            let word = first_word(&h.command).to_string();
+            groups
+                .entry(word.clone())
+                .or_insert_with(|| {
+                    order.push(word);
+                    Vec::new()
+                })
+                .push(h);
+        }
+
+        for word in order {
+            let mut group = groups.remove(&word).unwrap_or_default();
+            if group.len() > 1 {
+                self.cluster_sizes.insert(word.clone(), group.len());
+                if !self.open_clusters.contains(&word) {
+                    group.truncate(1);
+                }
+            }
+            self.results.extend(group);
+        }
+    }
+
+    /// The group of more than one result that the `i`th result starts, if any
+    fn cluster_at(&self, i: usize) -> Option<(&str, usize)> {
+        let word = first_word(&self.results.get(i)?.command);
+        let before = i.checked_sub(1).and_then(|i| self.results.get(i));
+        if before.map_or(false, |h| first_word(&h.command) == word) {
+            return None;
+        }
+
+        self.cluster_sizes.get(word).map(|n| (word, *n))
+    }
+
+    /// Open the selected result's group with Enter or Right, if it's closed,
+    /// or close the group it is in with Left. Whether anything changed
+    fn toggle_cluster(&mut self, key: Key) -> bool {
+        if !self.clusters {
+            return false;
+        }
+
+        let selected = match self.results_state.selected() {
+            Some(i) => i,
+            None => return false,
+        };
+
+        match key {
+            Key::Char('\n') | Key::Right if self.marked.is_empty() => {
+                match self.cluster_at(selected) {
+                    Some((word, _)) if !self.open_clusters.contains(word) => {
+                        self.open_clusters.insert(word.to_string());
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            Key::Left => {
+                let word = first_word(&self.results[selected].command).to_string();
+
+                // Select what's left of the group once it's closed
+                let first = self
+                    .results
+                    .iter()
+                    .position(|h| first_word(&h.command) == word);
+                self.results_state.select(first);
+
+                self.open_clusters.remove(&word)
+            }
+            _ => false,
+        }
+    }
+
+    /// The marker in front of a result when results are grouped, and the
+    /// count after the first of a closed group
+    fn cluster_spans(&self, i: usize) -> (Option<Span<'static>>, Option<Span<'static>>) {
+        if !self.clusters {
+            return (None, None);
+        }
+
+        let style = Style::default().fg(Color::DarkGray);
+        match self.cluster_at(i) {
+            Some((word, n)) if !self.open_clusters.contains(word) => (
+                Some(Span::styled("▸ ", style)),
+                Some(Span::styled(format!(" (+{})", n - 1), style)),
+            ),
+            Some(_) => (Some(Span::styled("▾ ", style)), None),
+            None => (Some(Span::raw("  ")), None),
+        }
+    }
+
+    /// Move on to the next layout, from auto to full to compact and back
+    fn cycle_layout(&mut self) {
+        self.layout = match self.layout {
+            UiLayout::Auto => UiLayout::Full,
+            UiLayout::Full => UiLayout::Compact,
+            UiLayout::Compact => UiLayout::Auto,
+        };
+    }
+
+    /// Whether to draw the compact UI, given the size of the terminal if it
+    /// is known. Left to itself, it is used on short terminals
+    fn compact(&self, size: Option<Rect>) -> bool {
+        match self.layout {
+            UiLayout::Auto => size.map_or(true, |size| size.height < 14),
+            UiLayout::Compact => true,
+            UiLayout::Full => false,
+        }
+    }
+
+    /// The title at the top of the UI, naming the layout if it has been
+    /// fixed, rather than left to the height of the terminal
+    fn title(&self) -> String {
+        let title = match self.layout {
+            UiLayout::Auto => format!("Atuin v{}", VERSION),
+            UiLayout::Full => format!("Atuin v{} (full)", VERSION),
+            UiLayout::Compact => format!("Atuin v{} (compact)", VERSION),
+        };
+
+        if self.scratch {
+            title + " (scratch)"
+        } else {
+            title
+        }
+    }
+
+    /// The number Alt-<num> uses to pick the `i`th result, if it has one
+    fn jump_number(&self, i: usize) -> Option<usize> {
+        let from = match self.jump_mode {
+            JumpMode::Relative => self.results_state.selected()? + 1,
+            JumpMode::Absolute => self.results_window.0,
+        };
+
+        (i >= from && i - from < 9).then(|| i - from + 1)
+    }
+
+    /// The result Alt-<num> picks
+    fn jump_target(&self, n: usize) -> Option<usize> {
+        match self.jump_mode {
+            JumpMode::Relative => Some(self.results_state.selected()? + n),
+            JumpMode::Absolute => Some(self.results_window.0 + n - 1),
+        }
+    }
+
+    /// Move the selection down the screen, to a newer entry. `edge` decides
+    /// what happens when moving on from the newest entry
+    fn select_down(&mut self, edge: EdgeBehavior) {
+        let i = match (self.results_state.selected(), edge) {
+            (Some(0), EdgeBehavior::Wrap) => Some(self.results.len().saturating_sub(1)),
+            (Some(0), EdgeBehavior::Deselect) => None,
+            (Some(i), _) => Some(i.saturating_sub(1)),
+            (None, _) => Some(0),
+        };
+        self.results_state.select(i);
+    }
+
+    /// Move the selection up the screen, to an older entry. `edge` decides
+    /// what happens when moving on from the oldest entry
+    fn select_up(&mut self, edge: EdgeBehavior) {
+        let last = self.results.len().saturating_sub(1);
+        let i = match (self.results_state.selected(), edge) {
+            (Some(i), EdgeBehavior::Stop) if i >= last => Some(last),
+            (Some(i), EdgeBehavior::Wrap) if i >= last => Some(0),
+            (Some(i), EdgeBehavior::Deselect) if i >= last => None,
+            (Some(i), _) => Some(i + 1),
+            (None, _) => Some(0),
+        };
+        self.results_state.select(i);
+    }
+
+    /// Return the given command, unless it is dangerous. Then it needs
+    /// confirming first
+    fn accept(&mut self, command: String) -> Option<Selected> {
+        if self.is_dangerous(&command) {
+            self.confirm = Some(Selected::One(command));
+            None
+        } else {
+            Some(Selected::One(command))
+        }
+    }
+
+    /// Return the marked commands. They need confirming if any of them is
+    /// dangerous
+    fn accept_marked(&mut self) -> Option<Selected> {
+        let commands = self.marked_commands();
+
+        if commands.iter().any(|c| self.is_dangerous(c)) {
+            self.confirm = Some(Selected::Marked(commands));
+            None
+        } else {
+            Some(Selected::Marked(commands))
+        }
+    }
+
+    fn is_dangerous(&self, command: &str) -> bool {
+        self.dangerous.iter().any(|r| r.is_match(command))
+    }
+
+    /// Mark the selected entry, or unmark it if it already is
+    fn toggle_marked(&mut self) {
+        let h = match self
+            .results_state
+            .selected()
+            .and_then(|i| self.results.get(i))
+        {
+            Some(h) => h,
+            None => return,
+        };
+
+        match self.marked.iter().position(|m| m.id == h.id) {
+            Some(i) => {
+                self.marked.remove(i);
+            }
+            None => self.marked.push(h.clone()),
+        }
+    }
+
+    /// The marked commands, in the order they were originally ran
+    fn marked_commands(&self) -> Vec<String> {
+        self.marked
+            .iter()
+            .sorted_by_key(|h| h.timestamp)
+            .map(|h| h.command.clone())
+            .collect()
+    }
+
+    /// Carry out an action from the menu on the selected entry. Copying
+    /// leaves the UI open, while cd returns the command to run
+    fn run_action(&mut self, action: Action) -> Option<String> {
+        let h = self.results.get(self.results_state.selected()?)?;
+
+        match action {
+            Action::CopyCommand => self.clipboard = Some(h.command.clone()),
+            Action::CopyCwd => self.clipboard = Some(h.cwd.clone()),
+            Action::CopyTime => {
+                self.clipboard = Some(h.timestamp.with_timezone(&Local).to_rfc3339());
+            }
+            Action::CopyShare => self.clipboard = Some(self.share_text(h)),
+            Action::Cd => return Some(format!("cd {}", shell_quote(&h.cwd))),
+        }
+
+        None
+    }
+
+    /// The text shown in the input box, along with its style
+    fn input_text(&self) -> (String, Style) {
+        match (&self.confirm, &self.tagging, &self.substituting) {
+            (Some(selected), _, _) => (
+                format!(
+                    "Really run `{}`? [y/N]",
+                    selected.commands().join(" ").replace('\n', " ")
+                ),
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+            (None, Some(tag), _) => (
+                format!("Add or remove tag: {}", tag),
+                Style::default().fg(Color::Yellow),
+            ),
+            (None, None, Some(expression)) => (
+                format!("Substitute: {}", expression),
+                Style::default().fg(Color::Yellow),
+            ),
+            (None, None, None) => (self.input.clone(), Style::default()),
+        }
+    }
+
+    /// Whether the input box shows a prompt, rather than the query
+    const fn prompting(&self) -> bool {
+        self.confirm.is_some() || self.tagging.is_some() || self.substituting.is_some()
+    }
+
+    /// Move the host filter on to the next host in the results, or clear it
+    /// once every host has been visited
+    fn cycle_host_filter(&mut self) {
+        let next = match &self.host_filter {
+            None => 0,
+            Some(host) => self
+                .hosts
+                .iter()
+                .position(|h| h == host)
+                .map_or(0, |i| i + 1),
+        };
+
+        self.host_filter = self.hosts.get(next).cloned();
+    }
+
+    /// Whether a result passes the filters set from within the UI
+    fn matches(&self, h: &History) -> bool {
+        if !long_enough(h, self.min_length) {
+            return false;
+        }
+
+        if self.hide_unknown_duration && h.duration == -1 {
+            return false;
+        }
+
+        true
+    }
+
+    /// The filters the database applies before the results are limited to
+    /// `max_entries`, so none of the results they keep are cut off. The
+    /// rest are left to `matches`
+    fn sql_filters(&self, tags: &[&str]) -> Filters {
+        Filters {
+            exit: self.exit_filter,
+            // Commands with an unknown duration are still running, so haven't
+            // failed yet
+            exclude_exit: self.failed_only.then(|| 0),
+            cwd: self.cwd_filter.clone(),
+            host: self.host_filter.clone(),
+            before: self.before_filter.as_ref().map(|(_, t)| *t),
+            after: self.after_filter.as_ref().map(|(_, t)| *t),
+            finished: self.failed_only,
+            tags: tags.iter().map(ToString::to_string).collect(),
+            ..Filters::default()
+        }
+    }
+
+    /// Turn on or off, or cycle through, the filter a key is bound to
+    fn toggle_filter(&mut self, key: Key) {
+        match key {
+            Key::Alt('h') => self.cycle_host_filter(),
+            Key::Alt('e') => self.failed_only = !self.failed_only,
+            Key::Ctrl('l') => self.toggle_cwd_filter(),
+            Key::Alt('w') => self.toggle_current_dir_filter(),
+            _ => {}
+        }
+    }
+
+    /// Only show commands from the selected result's directory, or show every
+    /// directory again if that filter is already on
+    fn toggle_cwd_filter(&mut self) {
+        self.cwd_filter = match self.cwd_filter {
+            Some(_) => None,
+            None => self
+                .results_state
+                .selected()
+                .and_then(|i| self.results.get(i))
+                .map(|h| h.cwd.clone()),
+        };
+    }
+
+    /// Only show commands from the directory the search UI was opened in, or
+    /// show every directory again if that filter is already on. Any other
+    /// directory filter is replaced
+    fn toggle_current_dir_filter(&mut self) {
+        self.cwd_filter = if self.cwd_filter == self.current_dir {
+            None
+        } else {
+            self.current_dir.clone()
+        };
+    }
+
+    /// Open the filter form, filled in with the filters currently applied
+    fn open_form(&mut self) {
+        let text = |filter: &Option<(String, DateTime<Utc>)>| {
+            filter.as_ref().map(|(t, _)| t.clone()).unwrap_or_default()
+        };
+
+        let mut form = FilterForm::default();
+        *form.value_mut(FormField::Cwd) = self.cwd_filter.clone().unwrap_or_default();
+        *form.value_mut(FormField::Exit) =
+            self.exit_filter.map(|e| e.to_string()).unwrap_or_default();
+        *form.value_mut(FormField::Host) = self.host_filter.clone().unwrap_or_default();
+        *form.value_mut(FormField::Before) = text(&self.before_filter);
+        *form.value_mut(FormField::After) = text(&self.after_filter);
+        self.form = Some(form);
+    }
+
+    /// Replace the filters with those from the form. An empty field clears
+    /// its filter. Nothing changes if any field can't be parsed
+    fn apply_form(&mut self, form: &FilterForm) -> Result<()> {
+        let non_empty = |field| Some(form.value(field).trim()).filter(|v| !v.is_empty());
+        let time = |field| -> Result<_> {
+            non_empty(field)
+                .map(|t| Ok((t.to_string(), parse_time(t)?)))
+                .transpose()
+        };
+
+        let exit = non_empty(FormField::Exit)
+            .map(|e| {
+                e.parse::<i64>()
+                    .wrap_err_with(|| format!("invalid exit code {:?}", e))
+            })
+            .transpose()?;
+        let before = time(FormField::Before)?;
+        let after = time(FormField::After)?;
+
+        self.cwd_filter = non_empty(FormField::Cwd).map(String::from);
+        self.exit_filter = exit;
+        self.host_filter = non_empty(FormField::Host).map(String::from);
+        self.before_filter = before;
+        self.after_filter = after;
+
+        Ok(())
+    }
+
+    /// Human readable descriptions of the filters currently applied
+    fn active_filters(&self) -> Vec<String> {
+        let mut filters = Vec::new();
+
+        if let Some(host) = &self.host_filter {
+            filters.push(format!("host: {}", host));
+        }
+
+        if let Some(cwd) = &self.cwd_filter {
+            filters.push(format!("cwd: {}", cwd));
+        }
+
+        if let Some(exit) = self.exit_filter {
+            filters.push(format!("exit: {}", exit));
+        }
+
+        if let Some((before, _)) = &self.before_filter {
+            filters.push(format!("before: {}", before));
+        }
+
+        if let Some((after, _)) = &self.after_filter {
+            filters.push(format!("after: {}", after));
+        }
+
+        if self.failed_only {
+            filters.push(String::from("failed"));
+        }
+
+        filters
+    }
+
+    /// Double how many results are loaded for each query, or halve it
+    fn change_limit(&mut self, grow: bool) {
+        self.max_entries = if grow {
+            self.max_entries.saturating_mul(2)
+        } else {
+            std::cmp::max(self.max_entries / 2, MIN_ENTRIES)
+        };
+    }
+
+    /// Describe which part of the results are on screen, eg "1-50 of 200"
+    fn results_range(&self) -> String {
+        let (start, end) = self.results_window;
+
+        if self.results.is_empty() {
+            String::from("0 of 0")
+        } else {
+            format!("{}-{} of {}", start + 1, end, self.results.len())
+        }
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    fn format_durations(&self) -> Vec<(String, String)> {
+        self.results
+            .iter()
+            .map(|h| {
+                let duration =
+                    Duration::from_millis(std::cmp::max(h.duration, 0) as u64 / 1_000_000);
+
+                let ago = chrono::Utc::now().sub(h.timestamp);
+
+                // Account for the chance that h.timestamp is "in the future"
+                // This would mean that "ago" is negative, and the unwrap here
+                // would fail.
+                // If the timestamp would otherwise be in the future, display
+                // the time ago as 0.
+                let ago = ago.to_std().unwrap_or_else(|_| Duration::new(0, 0));
+
+                (
+                    self.locale.format_duration(duration),
+                    self.locale.format_ago(ago),
+                )
+            })
+            .collect()
+    }
+
+    /// A command with any secret environment variables hidden
+    fn masked<'a>(&self, command: &'a str) -> Cow<'a, str> {
+        match &self.env_mask {
+            Some(mask) => mask.mask(command),
+            None => Cow::Borrowed(command),
+        }
+    }
+
+    /// A command as the results list shows it, cut down to
+    /// `max_display_length` before secrets are hidden, so huge ones aren't
+    /// scanned in full every frame
+    fn shown<'a>(&self, command: &'a str) -> Cow<'a, str> {
+        match truncate_display(command, self.max_display_length) {
+            Cow::Borrowed(command) => self.masked(command),
+            Cow::Owned(command) => Cow::Owned(self.masked(&command).into_owned()),
+        }
+    }
+
+    /// A command along with where and when it was ran, from `share_format`,
+    /// ready to paste into a chat or ticket
+    fn share_text(&self, h: &History) -> String {
+        render_template(&self.share_format, |field| {
+            field.format_human(h, &self.time_format, self.env_mask.as_ref())
+        })
+        .unwrap_or_else(|_| h.command.clone())
+    }
+
+    /// The plain text of a column for the `i`th result. Styling and alignment
+    /// happen once every cell is known, so columns line up
+    fn cell(&self, column: Column, i: usize, h: &History, trim: bool) -> String {
+        match column {
+            Column::Index => match self.jump_number(i) {
+                Some(n) => format!(" {} ", n),
+                None => String::from("   "),
+            },
+            Column::Duration => self.durations[i].0.clone(),
+            Column::Ago => self.durations[i].1.clone(),
+            Column::Exit => signal::name(h.exit).map_or_else(|| h.exit.to_string(), String::from),
+            Column::Cwd => h.cwd.clone(),
+            Column::Host => h.hostname.clone(),
+            Column::Count => self.counts.get(&h.command).map_or_else(String::new, |n| {
+                format!("(x{})", self.locale.format_count(*n))
+            }),
+            Column::Terms => self
+                .terms
+                .get(&h.command)
+                .map_or_else(String::new, |(matched, total)| {
+                    format!("{}/{}", matched, total)
+                }),
+            Column::Command => ansi::parse(&display_command(&self.shown(&h.command), trim))
+                .into_iter()
+                .map(|(text, _)| text)
+                .collect(),
+        }
+    }
+
+    /// What's shown after a command: the line it matched on, and its tags
+    fn annotation_spans(&self, h: &History) -> Vec<Span<'static>> {
+        let mut spans = Vec::new();
+
+        if let Some((n, line)) = self.matching_lines.get(&h.command) {
+            spans.push(Span::styled(
+                format!("  ↳ {}: {}", n, line.trim()),
+                Style::default()
+                    .fg(Color::Cyan)
+                    .add_modifier(Modifier::ITALIC),
+            ));
+        }
+
+        for tag in self.tags.get(&h.command).into_iter().flatten() {
+            spans.push(Span::raw(" "));
+            spans.push(Span::styled(
+                format!(" {} ", tag),
+                Style::default().fg(Color::Black).bg(host_color(tag)),
+            ));
+        }
+
+        spans
+    }
+
+    fn render_results<T: tui::backend::Backend>(
+        &mut self,
+        f: &mut tui::Frame<T>,
+        r: tui::layout::Rect,
+        b: tui::widgets::Block,
+        settings: &Settings,
+    ) {
+        let inner = b.inner(r);
+
+        // An expanded result leaves room for fewer of the others
+        let expanded = self.expanded_height(inner.height as usize);
+        let (start, _) = self.scroll(self.rows(inner.height as usize));
+
+        self.refresh_durations();
+
+        let columns: Vec<Column> = settings
+            .ui_columns
+            .iter()
+            .copied()
+            .filter(|c| *c != Column::Index || self.show_index)
+            .collect();
+
+        let cells: Vec<Vec<String>> = self
+            .results
+            .iter()
+            .enumerate()
+            .map(|(i, m)| {
+                columns
+                    .iter()
+                    .map(|c| self.cell(*c, i, m, settings.trim_commands))
+                    .collect()
+            })
+            .collect();
+
+        let widths: Vec<usize> = (0..columns.len())
+            .map(|c| cells.iter().map(|row| row[c].width()).max().unwrap_or(0))
+            .collect();
+
+        let results: Vec<ListItem> = self
+            .results
+            .iter()
+            .enumerate()
+            .map(|(i, m)| {
+                let selected = self.results_state.selected() == Some(i);
+
+                let mut spans = Vec::new();
+                let mut more = Vec::new();
+                for (c, column) in columns.iter().enumerate() {
+                    let last = c + 1 == columns.len();
+
+                    if *column == Column::Command {
+                        if self.marked.iter().any(|h| h.id == m.id) {
+                            spans.push(Span::styled("+ ", Style::default().fg(Color::Magenta)));
+                        }
+                        if settings.show_host_marker {
+                            spans.push(Span::styled(
+                                "● ",
+                                Style::default().fg(host_color(&m.hostname)),
+                            ));
+                        }
+                        let (marker, count) = self.cluster_spans(i);
+                        spans.extend(marker);
+                        let command = self.shown(&m.command);
+                        let mut command_width = cells[i][c].width();
+                        if selected && expanded > 1 {
+                            let indent: usize = spans.iter().map(Span::width).sum();
+                            let first = command.lines().next().unwrap_or_default();
+                            let first = display_command(first, settings.trim_commands);
+                            command_width = first.width();
+
+                            spans.extend(line_spans(m, &first, selected, settings));
+                            more = expanded_lines(m, &command, indent, expanded, settings);
+                        } else {
+                            spans.extend(command_spans(m, &command, selected, settings));
+                        }
+                        spans.extend(count);
+                        spans.extend(self.annotation_spans(m));
+                        if !last {
+                            let padding = widths[c].saturating_sub(command_width) + 1;
+                            spans.push(Span::raw(" ".repeat(padding)));
+                        }
+                        continue;
+                    }
+
+                    let text = align(&cells[i][c], widths[c], column.right_aligned());
+                    spans.push(Span::styled(text, column_style(*column, m)));
+
+                    // The index brings its own spacing
+                    if *column != Column::Index && !last {
+                        spans.push(Span::raw(" "));
+                    }
+                }
+
+                let mut lines = vec![Spans::from(spans)];
+                lines.extend(more);
+                lines.extend(self.group_rule(i, inner.width));
+                ListItem::new(lines)
+            })
+            .collect();
+
+        let results = List::new(results)
+            .block(b)
+            .start_corner(Corner::BottomLeft)
+            .highlight_symbol(">> ");
+
+        f.render_stateful_widget(results, r, &mut self.results_state);
+
+        // Draw the scrollbar over the right hand border, if there is one, and
+        // there's room for it
+        if r.width > 0 && self.results.len() > inner.height as usize {
+            let track = Rect::new(r.x + r.width - 1, inner.y, 1, inner.height);
+            render_scrollbar(f, track, start, self.results.len());
+        }
+    }
+}
+
+/// The program a command runs, or rather its first word
+fn first_word(command: &str) -> &str {
+    command.split_whitespace().next().unwrap_or_default()
+}
+
+/// Which of today, yesterday, the last week, month or year, or earlier, a
+/// time falls in, for the lines between groups of results
+fn age_group(now: DateTime<Local>, timestamp: DateTime<Utc>) -> usize {
+    let days = (now.date() - timestamp.with_timezone(&Local).date()).num_days();
+
+    match days {
+        i64::MIN..=0 => 0,
+        1 => 1,
+        2..=6 => 2,
+        7..=30 => 3,
+        31..=364 => 4,
+        _ => 5,
+    }
+}
+
+/// A colour for a host, that stays the same across runs
+fn host_color(hostname: &str) -> Color {
+    const COLORS: [Color; 6] = [
+        Color::Cyan,
+        Color::Magenta,
+        Color::Yellow,
+        Color::Green,
+        Color::Blue,
+        Color::LightRed,
+    ];
+
+    // FNV-1a, as the standard library's hashers are free to change between
+    // releases
+    let hash = hostname.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    });
+
+    #[allow(clippy::cast_possible_truncation)]
+    COLORS[(hash % COLORS.len() as u64) as usize]
+}
+
+/// Pad `text` out to `width` columns, on whichever side keeps it aligned
+fn align(text: &str, width: usize, right: bool) -> String {
+    let padding = " ".repeat(width.saturating_sub(text.width()));
+
+    if right {
+        padding + text
+    } else {
+        String::from(text) + &padding
+    }
+}
+
+fn column_style(column: Column, h: &History) -> Style {
+    let failed = h.exit != 0 && h.duration != -1;
+
+    match column {
+        Column::Duration | Column::Exit => {
+            Style::default().fg(if failed { Color::Red } else { Color::Green })
+        }
+        Column::Ago => Style::default().fg(Color::Blue),
+        Column::Cwd => Style::default().fg(Color::DarkGray),
+        Column::Host => Style::default().fg(host_color(&h.hostname)),
+        Column::Count => Style::default().fg(Color::Cyan),
+        Column::Terms => Style::default().fg(Color::Yellow),
+        Column::Index | Column::Command => Style::default(),
+    }
+}
+
+/// The command of a result, styled for the results list
+fn command_spans<'a>(
+    h: &History,
+    command: &str,
+    selected: bool,
+    settings: &Settings,
+) -> Vec<Span<'a>> {
+    line_spans(
+        h,
+        &display_command(command, settings.trim_commands),
+        selected,
+        settings,
+    )
+}
+
+/// The lines after the first of an expanded command, lined up beneath it.
+/// If they don't all fit in `height`, the last says how many were left out
+fn expanded_lines<'a>(
+    h: &History,
+    command: &str,
+    indent: usize,
+    height: usize,
+    settings: &Settings,
+) -> Vec<Spans<'a>> {
+    let rest: Vec<&str> = command.lines().skip(1).collect();
+    let room = height - 1;
+
+    rest.iter()
+        .take(room)
+        .enumerate()
+        .map(|(n, line)| {
+            let mut spans = vec![Span::raw(" ".repeat(indent))];
+
+            if n + 1 == room && rest.len() > room {
+                spans.push(Span::styled(
+                    format!("… {} more lines", rest.len() - n),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            } else {
+                let line = line.replace('\t', "    ");
+                spans.extend(line_spans(h, line.trim_end(), true, settings));
+            }
+
+            Spans::from(spans)
+        })
+        .collect()
+}
+
+/// One line of a command, styled for the results list
+fn line_spans<'a>(
+    h: &History,
+    command: &str,
+    selected: bool,
+    settings: &Settings,
+) -> Vec<Span<'a>> {
+    let style = if selected {
+        Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+    } else if settings.highlight_privileged && h.is_privileged() {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default()
+    };
+
+    // Escape sequences are always dropped, and only used for styling if asked.
+    // The selection highlight always wins, so it stays obvious
+    ansi::parse(command)
+        .into_iter()
+        .map(|(text, ansi)| {
+            if settings.render_ansi && !selected {
+                Span::styled(text, style.patch(ansi))
+            } else {
+                Span::styled(text, style)
+            }
+        })
+        .collect()
+}
+
+/// Render a vertical scrollbar, where `start` is the index of the first of
+/// `total` items on screen. As results are listed from the bottom up, so is the
+/// scrollbar
+#[allow(clippy::cast_possible_truncation)]
+fn render_scrollbar<T: Backend>(f: &mut Frame<'_, T>, track: Rect, start: usize, total: usize) {
+    let height = track.height as usize;
+    if height == 0 {
+        return;
+    }
+
+    let thumb = (height * height / total).clamp(1, height);
+    let thumb_start = std::cmp::min(start * height / total, height - thumb);
+
+    let lines: Vec<Spans> = (0..height)
+        .map(|row| {
+            let from_bottom = height - 1 - row;
+
+            if from_bottom >= thumb_start && from_bottom < thumb_start + thumb {
+                Spans::from(Span::raw("█"))
+            } else {
+                Spans::from(Span::styled("│", Style::default().fg(Color::DarkGray)))
+            }
+        })
+        .collect();
+
+    f.render_widget(Paragraph::new(lines), track);
+}
+
+/// Take `tag:<name>` words out of a query, returning the rest of it along with
+/// the tags
+fn split_tags(query: &str) -> (String, Vec<&str>) {
+    let tags: Vec<&str> = query
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix("tag:"))
+        .filter(|tag| !tag.is_empty())
+        .collect();
+
+    if tags.is_empty() {
+        return (query.to_string(), tags);
+    }
+
+    let rest = query
+        .split_whitespace()
+        .filter(|word| !word.starts_with("tag:"))
+        .join(" ");
+
+    (rest, tags)
+}
+
+/// The commands with every one of the tags, or None if there are no tags to
+/// filter by
+async fn tagged_commands(
+    db: &mut (impl Database + Send + Sync),
+    tags: &[&str],
+) -> Result<Option<HashSet<String>>> {
+    let mut tagged: Option<HashSet<String>> = None;
+
+    for tag in tags {
+        let commands: HashSet<String> = db.tagged_commands(tag).await?.into_iter().collect();
+        tagged = Some(match tagged {
+            Some(t) => t.intersection(&commands).cloned().collect(),
+            None => commands,
+        });
+    }
+
+    Ok(tagged)
+}
+
+/// Add the typed tag to the selected entry, or remove it if the command
+/// already has it
+async fn toggle_tag(
+    app: &mut State,
+    tag: &str,
+    db: &mut (impl Database + Send + Sync),
+) -> Result<()> {
+    let h = match app
+        .results_state
+        .selected()
+        .and_then(|i| app.results.get(i))
+    {
+        Some(h) => h,
+        None => return Ok(()),
+    };
+
+    let tagged = app
+        .tags
+        .get(&h.command)
+        .map_or(false, |tags| tags.iter().any(|t| t == tag));
+
+    if tagged {
+        db.remove_tag(h, tag).await?;
+    } else {
+        db.add_tag(h, tag).await?;
+    }
+
+    // Reload the command's tags, as a removed tag may have been on another run
+    let command = h.command.clone();
+    let mut tags = db.command_tags(&[command.clone()]).await?;
+    match tags.remove(&command) {
+        Some(tags) => app.tags.insert(command, tags),
+        None => app.tags.remove(&command),
+    };
+
+    Ok(())
+}
+
+/// Handle a key press while the filter form is open
+async fn form_key_handler(
+    input: Key,
+    mut form: FilterForm,
+    search_mode: SearchMode,
+    db: &mut (impl Database + Send + Sync),
+    app: &mut State,
+) {
+    match input {
+        Key::Esc | Key::Ctrl('c' | 'g') | Key::Alt('f') => return,
+        Key::Char('\n') => match app.apply_form(&form) {
+            Ok(()) => {
+                query_results(app, search_mode, db).await.unwrap();
+                return;
+            }
+            // Leave the form open, so the mistake can be fixed
+            Err(e) => app.query_error = Some(e.to_string()),
+        },
+        Key::Char('\t') | Key::Down | Key::Ctrl('n') => {
+            form.focus = (form.focus + 1) % FormField::ALL.len();
+        }
+        Key::BackTab | Key::Up | Key::Ctrl('p') => {
+            form.focus = (form.focus + FormField::ALL.len() - 1) % FormField::ALL.len();
+        }
+        Key::Backspace => {
+            form.values[form.focus].pop();
+        }
+        Key::Char(c) => form.values[form.focus].push(c),
+        _ => {}
+    }
+
+    app.form = Some(form);
+}
+
+/// Handle a key press while a substitution is being typed after Alt-s. Enter
+/// returns the selected command with the substitution made
+fn substitute_key_handler(input: Key, mut expression: String, app: &mut State) -> Option<Selected> {
+    match input {
+        Key::Esc | Key::Ctrl('c' | 'g') => return None,
+        Key::Char('\n') => {
+            let command = app
+                .results_state
+                .selected()
+                .and_then(|i| app.results.get(i))
+                .map(|h| h.command.clone())?;
+
+            // Leave the prompt open after a mistake, so it can be fixed
+            match expression.parse::<Substitution>() {
+                Ok(substitution) => match substitution.apply(&command) {
+                    Some(command) => return app.accept(command),
+                    None => app.query_error = Some(String::from("nothing to substitute")),
+                },
+                Err(e) => app.query_error = Some(e),
+            }
+        }
+        Key::Backspace => {
+            expression.pop();
+        }
+        Key::Char(c) => expression.push(c),
+        _ => {}
+    }
+
+    app.substituting = Some(expression);
+    None
+}
+
+/// Handle a key press while a tag is being typed after Alt-t
+async fn tag_key_handler(
+    input: Key,
+    mut tag: String,
+    db: &mut (impl Database + Send + Sync),
+    app: &mut State,
+) {
+    match input {
+        Key::Esc | Key::Ctrl('c' | 'g') => return,
+        Key::Char('\n') => {
+            let tag = tag.trim().trim_start_matches('#');
+            if !tag.is_empty() {
+                if let Err(e) = toggle_tag(app, tag, db).await {
+                    app.query_error = Some(e.to_string());
+                }
+            }
+            return;
+        }
+        Key::Backspace => {
+            tag.pop();
+        }
+        // Tags are single words, so they can be searched for with tag:
+        Key::Char(c) if !c.is_whitespace() => tag.push(c),
+        _ => {}
+    }
+
+    app.tagging = Some(tag);
+}
+
+async fn query_results(
+    app: &mut State,
+    search_mode: SearchMode,
+    db: &mut (impl Database + Send + Sync),
+) -> Result<()> {
+    let selected = app
+        .results_state
+        .selected()
+        .and_then(|i| app.results.get(i))
+        .map(|h| h.command.clone());
+
+    if app.empty_query == EmptyQuery::Blank && app.input.trim().is_empty() {
+        app.clear_results();
+        return Ok(());
+    }
+
+    // Narrowing the results needs the state, while the tags borrow the input
+    let input = app.input.clone();
+    let (query, tags) = split_tags(&input);
+
+    if let Err(e) = check_regex(search_mode, &query) {
+        app.query_error = Some(e.to_string());
+        return Ok(());
+    }
+
+    let filters = app.sql_filters(&tags);
+    let start = Instant::now();
+    let results = fetch(app, search_mode, &query, &filters, db).await;
+    app.query_stats.latency = start.elapsed();
+
+    // Leave the previous results in place, so there's still something to look
+    // at while the query is refined
+    let results = match results {
+        Some(results) => {
+            app.query_error = None;
+            results?
+        }
+        None => {
+            app.query_error = Some(String::from("query timed out, refine your search"));
+            return Ok(());
+        }
+    };
+
+    let results = match search_mode {
+        SearchMode::Fuzzy => filter_fuzzy(
+            &query,
+            app.fuzzy_min_score,
+            app.aliases.as_ref(),
+            reorder_boundaries(&query, &app.fuzzy_boundaries, results),
+        ),
+        _ => results,
+    };
+    app.query_stats.fetched = results.len();
+
+    // With a host filter, only that host's results are fetched, so the hosts
+    // from before it was set are kept to cycle through
+    if app.host_filter.is_none() {
+        app.hosts = results
+            .iter()
+            .map(|h| h.hostname.clone())
+            .sorted()
+            .dedup()
+            .collect();
+    }
+
+    app.results = results.into_iter().filter(|h| app.matches(h)).collect();
+    app.cluster_results();
+    app.annotate_results(&query, search_mode);
+
+    // The loaded tags and counts cover every narrowed result
+    if let Some(loaded) = app.loaded.as_ref().filter(|_| app.query_stats.narrowed) {
+        app.tags = loaded.tags.clone();
+        app.counts = loaded.counts.clone();
+    } else {
+        let commands: Vec<String> = app.results.iter().map(|h| h.command.clone()).collect();
+        app.tags = db.command_tags(&commands).await?;
+        if app.show_counts {
+            app.counts = db.command_counts(&commands).await?;
+        }
+    }
+    app.durations_at = None;
+    app.query_stats.shown = app.results.len();
+
+    debug!(
+        "query {:?} in {:?} mode: {:?}",
+        app.input, search_mode, app.query_stats
+    );
+
+    if app.results.is_empty() {
+        app.results_state.select(None);
+    } else {
+        // Results are unique by command, so if the previously selected command
+        // is still there keep it selected, rather than jumping back to the start
+        let i = selected
+            .and_then(|selected| app.results.iter().position(|h| h.command == selected))
+            .unwrap_or(0);
+
+        app.results_state.select(Some(i));
+    }
+
+    Ok(())
+}
+
+/// The results for a query, narrowed down from those loaded if possible,
+/// otherwise from the database. None if the database took too long
+async fn fetch(
+    app: &mut State,
+    search_mode: SearchMode,
+    query: &str,
+    filters: &Filters,
+    db: &mut (impl Database + Send + Sync),
+) -> Option<Result<Vec<History>>> {
+    let narrowed = narrow(app, search_mode, query, filters);
+    app.query_stats.narrowed = narrowed.is_some();
+    if narrowed.is_some() {
+        return narrowed.map(Ok);
+    }
+
+    let limit = i64::try_from(app.max_entries).unwrap_or(i64::MAX);
+    let results = match query {
+        "" if *filters == Filters::default() => {
+            with_timeout(app.search_timeout, db.list(Some(app.max_entries), true))
+                .await
+                .map(|r| r.map_err(eyre::Report::from))
+        }
+        i => {
+            let search = search_with_aliases(
+                db,
+                Some(limit),
+                search_mode,
+                i,
+                filters,
+                app.aliases.as_ref(),
+            );
+            with_timeout(app.search_timeout, search).await
+        }
+    };
+    // Even when the query timed out or failed, so auto_refresh waits a whole
+    // interval before trying again rather than trying on every tick
+    app.loaded_at = Some(Instant::now());
+
+    if let Some(Ok(results)) = results.as_ref().filter(|_| app.incremental) {
+        let complete = results.len() < app.max_entries;
+        match load(db, search_mode, query, filters, complete, results).await {
+            Ok(loaded) => app.loaded = Some(loaded),
+            Err(e) => return Some(Err(e)),
+        }
+    }
+
+    results
+}
+
+/// Query the database again, even when the results loaded so far could be
+/// narrowed down, to pick up commands ran since
+async fn refresh(
+    app: &mut State,
+    search_mode: SearchMode,
+    db: &mut (impl Database + Send + Sync),
+) -> Result<()> {
+    app.loaded = None;
+    query_results(app, search_mode, db).await
+}
+
+/// Keep the results of a database query, along with the tags and counts of
+/// every command in them, for `narrow` to use
+async fn load(
+    db: &mut (impl Database + Send + Sync),
+    mode: SearchMode,
+    query: &str,
+    filters: &Filters,
+    complete: bool,
+    results: &[History],
+) -> Result<Loaded> {
+    let commands: Vec<String> = results.iter().map(|h| h.command.clone()).collect();
+
+    Ok(Loaded {
+        mode,
+        query: query.to_string(),
+        filters: filters.clone(),
+        complete,
+        results: results.to_vec(),
+        tags: db.command_tags(&commands).await?,
+        counts: db.command_counts(&commands).await?,
+    })
+}
+
+/// The results for `query` worked out from those already loaded, when it
+/// only adds to the loaded query. Those matching the longer query are
+/// then a subset of the loaded ones, as long as the loaded query found
+/// everything it matched. Otherwise, and for queries that can't be matched
+/// correctly in memory, the database has to be asked
+fn narrow(app: &State, mode: SearchMode, query: &str, filters: &Filters) -> Option<Vec<History>> {
+    let loaded = app.loaded.as_ref().filter(|l| {
+        app.incremental
+            && app.aliases.is_none()
+            && l.complete
+            && l.mode == mode
+            && l.filters == *filters
+            && query.len() > l.query.len()
+            && query.starts_with(&l.query)
+    })?;
+
+    if !can_match_loaded(mode, query) {
+        return None;
+    }
+
+    let mut results: Vec<History> = loaded
+        .results
+        .iter()
+        .filter(|h| matches_loaded(mode, query, &h.command))
+        .cloned()
+        .collect();
+
+    // Put them back in the order the database would have returned them
+    results.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    results.truncate(app.max_entries);
+
+    Some(ordering::reorder_fuzzy(mode, query, results))
+}
+
+/// Whether `matches_loaded` matches a query the same way the database does.
+/// Wildcards, fuzzy search operators, the smart case fuzzy search does with
+/// upper case letters, and regexes are left to the database
+fn can_match_loaded(mode: SearchMode, query: &str) -> bool {
+    if query.contains(|c| matches!(c, '*' | '%' | '_')) {
+        return false;
+    }
+
+    match mode {
+        SearchMode::Prefix | SearchMode::FullText => true,
+        SearchMode::Fuzzy => {
+            !query.contains(|c: char| c.is_uppercase() || matches!(c, '!' | '^' | '$' | '\'' | '|'))
+        }
+        SearchMode::FullTextIndexed | SearchMode::Regex => false,
+    }
+}
+
+/// Match a command against a query that `can_match_loaded`, ignoring case
+/// like the database's `like` does. Fuzzy search matches each word of the
+/// query, with its letters in order but not necessarily side by side
+fn matches_loaded(mode: SearchMode, query: &str, command: &str) -> bool {
+    let query = query.to_ascii_lowercase();
+    let command = command.to_ascii_lowercase();
+
+    match mode {
+        SearchMode::Prefix => command.starts_with(&query),
+        SearchMode::FullText | SearchMode::FullTextIndexed | SearchMode::Regex => {
+            command.contains(&query)
+        }
+        SearchMode::Fuzzy => query.split(' ').all(|word| {
+            let mut rest = command.chars();
+            word.chars().all(|c| rest.any(|r| r == c))
+        }),
+    }
+}
+
+/// Order commands most used first, breaking ties as configured. Recency falls
+/// back to the command itself, so the order is always the same
+fn rank_by_frequency<'a>(
+    counts: &'a HashMap<String, i64>,
+    last_ran: &HashMap<String, DateTime<Utc>>,
+    tiebreak: Tiebreak,
+) -> Vec<(&'a String, i64)> {
+    counts
+        .iter()
+        .sorted_by(|a, b| {
+            let tie = match tiebreak {
+                Tiebreak::Recency => last_ran.get(b.0).cmp(&last_ran.get(a.0)),
+                Tiebreak::Alphabetical => std::cmp::Ordering::Equal,
+            };
+
+            b.1.cmp(a.1).then(tie).then_with(|| a.0.cmp(b.0))
+        })
+        .map(|(command, count)| (command, *count))
+        .collect()
+}
+
+/// Report how many entries each filter removed, most first
+fn explain_filters(found: usize, removed: &[(&str, usize)], left: usize) {
+    eprintln!("{} matched the query", found);
+    for (filter, n) in removed.iter().sorted_by(|a, b| b.1.cmp(&a.1)) {
+        eprintln!("{} removed {}", filter, n);
+    }
+    eprintln!("{} left", left);
+}
+
+/// Print commands as completion candidates, in the order given. zsh and fish
+/// can show a description alongside each candidate, so include the count there
+fn print_completions(
+    out: &mut dyn Write,
+    shell: Shell,
+    commands: &[(&String, i64)],
+    redactor: Option<&Redactor>,
+) -> Result<()> {
+    for (command, count) in commands {
+        let command = redacted(redactor, command);
+
+        // Candidates are line based, so multi-line commands can't be completed
+        if command.contains('\n') {
+            continue;
+        }
+
+        match shell {
+            Shell::Zsh => writeln!(out, "{}:ran {} times", command.replace(':', "\\:"), count)?,
+            Shell::Fish => writeln!(out, "{}\tran {} times", command, count)?,
+            _ => writeln!(out, "{}", command)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// The text with whatever the redactor matches replaced, or as it is without
+/// a redactor
+fn redacted<'a>(redactor: Option<&Redactor>, s: &'a str) -> Cow<'a, str> {
+    redactor.map_or(Cow::Borrowed(s), |r| r.redact(s))
+}
+
+/// Rebuild what searches rely on, with a spinner naming the current step, as
+/// this can take a while with a lot of history
+async fn reindex(db: &mut (impl Database + Send + Sync)) -> Result<()> {
+    let spinner = ProgressBar::new_spinner();
+    spinner.enable_steady_tick(100);
+
+    let start = Instant::now();
+    let reindexed = db.reindex(&|step| spinner.set_message(step)).await?;
+    spinner.finish_and_clear();
+
+    eprintln!(
+        "reindexed in {:.1?}, dropped {} orphaned tags and {} orphaned collection entries",
+        start.elapsed(),
+        reindexed.orphaned_tags,
+        reindexed.orphaned_collection_entries
+    );
+
+    Ok(())
+}
+
+/// Work out the user's shell from $SHELL, eg /usr/bin/fish
+fn shell_from_env() -> Option<Shell> {
+    let shell = PathBuf::from(std::env::var_os("SHELL")?);
+
+    shell.file_name()?.to_str()?.parse().ok()
+}
+
+/// Encode a selected command so the shell integration can put it on the
+/// prompt intact. bash and zsh capture the output whole, but fish splits
+/// command substitutions on newlines, so for fish everything but a few safe
+/// characters is percent encoded, for `string unescape --style=url` to undo
+fn fill_prompt(command: &str, shell: Option<Shell>) -> String {
+    match shell {
+        Some(Shell::Fish) => command
+            .bytes()
+            .map(|b| match b {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'.' | b'_' | b'~' | b'-' => {
+                    char::from(b).to_string()
+                }
+                b => format!("%{:02X}", b),
+            })
+            .collect(),
+        _ => command.to_string(),
+    }
+}
+
+/// Quote a string so a POSIX shell treats it as a single word
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Flatten a command onto one line for the results list
+fn display_command(command: &str, trim: bool) -> String {
+    let command = if trim { command.trim() } else { command };
+
+    command.replace('\n', " ").replace('\t', " ")
+}
+
+/// How many of the words in a query a command contains, ignoring case, and
+/// how many words there are. Fuzzy search operators such as `^` and `'` are
+/// left out, and a `!word` counts when the command doesn't contain the word.
+/// None for a query without any words
+fn matched_terms(query: &str, command: &str) -> Option<(usize, usize)> {
+    let command = command.to_lowercase();
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .filter(|term| *term != "|")
+        .map(str::to_lowercase)
+        .collect();
+
+    if terms.is_empty() {
+        return None;
+    }
+
+    let matched = terms
+        .iter()
+        .filter(|term| {
+            let (inverse, term) = match term.strip_prefix('!') {
+                Some(term) => (true, term),
+                None => (false, term.as_str()),
+            };
+            let term = term.trim_start_matches(|c| c == '^' || c == '\'');
+            let term = term.strip_suffix('$').unwrap_or(term);
+
+            command.contains(term) != inverse
+        })
+        .count();
+
+    Some((matched, terms.len()))
+}
+
+/// Find the first line of a command matching the query, numbered from 1.
+/// Matching ignores case like the database does, except for regexes. For
+/// fuzzy search, a line matches if it contains every word in the query
+fn matching_line(command: &str, query: &str, mode: SearchMode) -> Option<(usize, String)> {
+    let regex = match mode {
+        SearchMode::Regex => Some(Regex::new(query).ok()?),
+        _ => None,
+    };
+    let query = query.to_lowercase();
+
+    command
+        .lines()
+        .enumerate()
+        .find(|(_, line)| {
+            let lower = line.to_lowercase();
+
+            match mode {
+                SearchMode::Prefix => lower.trim_start().starts_with(&query),
+                SearchMode::FullText => lower.contains(&query),
+                SearchMode::Fuzzy | SearchMode::FullTextIndexed => {
+                    query.split_whitespace().all(|word| lower.contains(word))
+                }
+                SearchMode::Regex => regex.as_ref().map_or(false, |r| r.is_match(line)),
+            }
+        })
+        .map(|(i, line)| (i + 1, line.to_string()))
+}
+
+/// The line a multi-line command matched the query on, unless it is the first,
+/// which is seen anyway
+fn inner_match(command: &str, query: &str, mode: SearchMode) -> Option<(usize, String)> {
+    if query.is_empty() || !command.contains('\n') {
+        return None;
+    }
+
+    matching_line(command, query, mode).filter(|(n, _)| *n > 1)
+}
+
+/// In regex mode, make sure the query is a valid regex before searching, so
+/// it's reported as one rather than as a database error
+fn check_regex(mode: SearchMode, query: &str) -> Result<()> {
+    if mode != SearchMode::Regex {
+        return Ok(());
+    }
+
+    Regex::new(query).map(|_| ()).map_err(|e| {
+        // Syntax errors point out where over several lines, ending with what
+        // went wrong
+        let e = e.to_string();
+        let reason = e.lines().last().unwrap_or_default();
+        eyre!("invalid regex: {}", reason.trim_start_matches("error: "))
+    })
+}
+
+/// Whether `cwd` is `dir`, or with `subdirs` anywhere beneath it
+fn in_dir(cwd: &str, dir: &str, subdirs: bool) -> bool {
+    if cwd == dir {
+        return true;
+    }
+
+    subdirs
+        && cwd
+            .strip_prefix(dir.trim_end_matches('/'))
+            .map_or(false, |rest| rest.starts_with('/'))
+}
+
+/// Whether a command starts with one of the given directory changing commands
+fn changes_dir(h: &History, cd_commands: &[String]) -> bool {
+    h.command
+        .split_whitespace()
+        .next()
+        .map_or(false, |word| cd_commands.iter().any(|cd| cd == word))
+}
+
+/// Everything after a command's first word, such as `push --force` for `git
+/// push --force`
+fn arguments(command: &str) -> &str {
+    let command = command.trim_start();
+    command
+        .find(char::is_whitespace)
+        .map_or("", |end| command[end..].trim_start())
+}
+
+/// Whether a command pipes into another, or redirects its input or output.
+/// Quoted and escaped characters, like the `|` in `echo "a|b"`, don't count,
+/// and neither does `||`
+fn is_pipeline(command: &str) -> bool {
+    let mut chars = command.chars().peekable();
+    let mut quote = None;
+    let mut word_start = true;
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some('"') | None, '\\') => {
+                chars.next();
+            }
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '#') if word_start => return false,
+            (None, '|') if chars.peek() == Some(&'|') => {
+                chars.next();
+            }
+            (None, '|' | '>' | '<') => return true,
+            _ => {}
+        }
+
+        word_start = quote.is_none() && (c.is_whitespace() || c == ';');
+    }
+
+    false
+}
+
+/// Whether a command was ran on `host`, which can leave off the user that
+/// hostnames are recorded with
+fn on_host(h: &History, host: &str) -> bool {
+    h.hostname == host || h.hostname.split(':').next() == Some(host)
+}
+
+/// Whether a command is at least `min_length` characters long, ignoring any
+/// surrounding whitespace
+fn long_enough(h: &History, min_length: usize) -> bool {
+    min_length == 0 || h.command.trim().chars().count() >= min_length
+}
+
+/// Search the database, and in fuzzy mode with aliases to match, search again
+/// with the alias at the start of the query expanded, and put back in place
+/// of what it expands to. `gco` then finds `git checkout main`, and `git
+/// checkout` finds `gco main`. Results stay newest first
+async fn search_with_aliases(
+    db: &mut (impl Database + Send + Sync),
+    limit: Option<i64>,
+    mode: SearchMode,
+    query: &str,
+    filters: &Filters,
+    aliases: Option<&Aliases>,
+) -> Result<Vec<History>> {
+    let mut results = db.search_filtered(limit, mode, query, filters).await?;
+
+    let aliases = match (mode, aliases) {
+        (SearchMode::Fuzzy, Some(aliases)) => aliases,
+        _ => return Ok(results),
+    };
+
+    for variant in [aliases.resolve(query), aliases.contract(query)] {
+        if variant != query {
+            let seen: HashSet<String> = results.iter().map(|h| h.command.clone()).collect();
+            let found = db.search_filtered(limit, mode, &variant, filters).await?;
+
+            results.extend(found.into_iter().filter(|h| !seen.contains(&h.command)));
+        }
+    }
+
+    results.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    if let Some(limit) = limit.and_then(|l| usize::try_from(l).ok()) {
+        results.truncate(limit);
+    }
+
+    Ok(results)
+}
+
+/// Await the future, or return None if it takes longer than the timeout
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    f: impl Future<Output = T> + Send,
+) -> Option<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, f).await.ok(),
+        None => Some(f.await),
+    }
+}
+
+/// Add the selected entry to the collection, or remove it if it is already
+/// there
+async fn toggle_collected(app: &mut State, db: &mut (impl Database + Send + Sync)) -> Result<()> {
+    let h = match app
+        .results_state
+        .selected()
+        .and_then(|i| app.results.get(i))
+    {
+        Some(h) => h,
+        None => return Ok(()),
+    };
+
+    let id = h.id.clone();
+
+    if app.collected.contains(&id) {
+        db.remove_from_collection(&app.collection, h).await?;
+        app.collected.remove(&id);
+    } else {
+        db.add_to_collection(&app.collection, h).await?;
+        app.collected.insert(id);
+    }
+
+    Ok(())
+}
+
+/// Open the list of recently used directories, if there are any
+async fn open_dirs(app: &mut State, db: &mut (impl Database + Send + Sync)) {
+    match db.recent_dirs(RECENT_DIRS).await {
+        Ok(dirs) if !dirs.is_empty() => {
+            app.dirs = dirs;
+            app.dirs_selected = Some(0);
+        }
+        Ok(_) => {}
+        Err(e) => app.query_error = Some(e.to_string()),
+    }
+}
+
+/// Handle a key press while the recent directories list is open. Enter cds to
+/// the selected directory, while Tab filters the results by it
+async fn dirs_key_handler(
+    input: Key,
+    selected: usize,
+    search_mode: SearchMode,
+    db: &mut (impl Database + Send + Sync),
+    app: &mut State,
+) -> Option<String> {
+    match input {
+        Key::Esc | Key::Ctrl('c' | 'g') | Key::Alt('j') => app.dirs_selected = None,
+        Key::Up | Key::Ctrl('p') => app.dirs_selected = Some(selected.saturating_sub(1)),
+        Key::Down | Key::Ctrl('n') => {
+            app.dirs_selected = Some(std::cmp::min(selected + 1, app.dirs.len() - 1));
+        }
+        Key::Char('\n') => {
+            app.dirs_selected = None;
+            return Some(format!("cd {}", shell_quote(&app.dirs[selected])));
+        }
+        Key::Char('\t') => {
+            app.dirs_selected = None;
+            app.cwd_filter = Some(app.dirs[selected].clone());
+            query_results(app, search_mode, db).await.unwrap();
+        }
+        _ => {}
+    }
+
+    None
+}
+
+/// Handle a key press while the action menu is open
+fn menu_key_handler(input: Key, selected: usize, app: &mut State) -> Option<String> {
+    match input {
+        Key::Esc | Key::Ctrl('c' | 'g') | Key::Alt('a') => app.menu = None,
+        Key::Up | Key::Ctrl('p') => app.menu = Some(selected.saturating_sub(1)),
+        Key::Down | Key::Ctrl('n') => {
+            app.menu = Some(std::cmp::min(selected + 1, Action::ALL.len() - 1));
+        }
+        Key::Char('\n') => {
+            app.menu = None;
+            return app.run_action(Action::ALL[selected]);
+        }
+        _ => {}
+    }
+
+    None
+}
+
+/// Add pasted text to the query, all at once so there's a single search. A
+/// query is one line, so line breaks become spaces rather than selecting
+/// whatever is highlighted
+async fn paste(
+    text: &str,
+    search_mode: SearchMode,
+    db: &mut (impl Database + Send + Sync),
+    app: &mut State,
+) {
+    if let Some(form) = &mut app.form {
+        form.values[form.focus].push_str(&pasted_query(text));
+        return;
+    }
+
+    if app.prompting() || app.menu.is_some() || app.dirs_selected.is_some() {
+        return;
+    }
+
+    app.input.push_str(&pasted_query(text));
+    query_results(app, search_mode, db).await.unwrap();
+}
+
+fn pasted_query(text: &str) -> String {
+    text.trim_end_matches('\n')
+        .replace(|c| c == '\n' || c == '\t', " ")
+}
+
+async fn key_handler(
+    input: Key,
+    settings: &Settings,
+    db: &mut (impl Database + Send + Sync),
+    app: &mut State,
+) -> Option<Selected> {
+    let search_mode = settings.search_mode;
+
+    if let Some(selected) = app.confirm.take() {
+        return match input {
+            Key::Char('y' | 'Y') => Some(selected),
+            _ => None,
+        };
+    }
+
+    if let Some(selected) = app.menu {
+        return menu_key_handler(input, selected, app).map(Selected::One);
+    }
+
+    if let Some(selected) = app.dirs_selected {
+        return dirs_key_handler(input, selected, search_mode, db, app)
+            .await
+            .map(Selected::One);
+    }
+
+    if let Some(tag) = app.tagging.take() {
+        tag_key_handler(input, tag, db, app).await;
+        return None;
+    }
+
+    if let Some(expression) = app.substituting.take() {
+        return substitute_key_handler(input, expression, app);
+    }
+
+    if let Some(form) = app.form.take() {
+        form_key_handler(input, form, search_mode, db, app).await;
+        return None;
+    }
+
+    // Any other key collapses the expanded command again
+    let expanded = std::mem::take(&mut app.expanded);
+    if input == Key::Alt('x') {
+        app.expanded = !expanded;
+        return None;
+    }
+
+    if app.toggle_cluster(input) {
+        query_results(app, search_mode, db).await.unwrap();
+        return None;
+    }
+
+    results_key_handler(input, settings, db, app).await
+}
+
+/// Handle a key press while nothing is open over the results
+async fn results_key_handler(
+    input: Key,
+    settings: &Settings,
+    db: &mut (impl Database + Send + Sync),
+    app: &mut State,
+) -> Option<Selected> {
+    let search_mode = settings.search_mode;
+
+    if input != Key::Ctrl('r') {
+        app.recalled_query = None;
+    }
+
+    match input {
+        Key::Esc | Key::Ctrl('c' | 'd' | 'g') => return Some(Selected::Nothing),
+        Key::Char('\n') if !app.marked.is_empty() => return app.accept_marked(),
+        Key::Char('\n') => {
+            let i = app.results_state.selected().unwrap_or(0);
+            let command = app
+                .results
+                .get(i)
+                .map_or(app.input.clone(), |h| h.command.clone());
+
+            return app.accept(command);
+        }
+        Key::Alt(c) if ('1'..='9').contains(&c) => {
+            let c = c.to_digit(10)? as usize;
+            let i = app.jump_target(c)?;
+            let command = app
+                .results
+                .get(i)
+                .map_or(app.input.clone(), |h| h.command.clone());
+
+            return app.accept(command);
+        }
+        Key::Char('\t') => {
+            app.remember_query();
+            let i = app.results_state.selected()?;
+            let command = app.results.get(i)?.command.trim();
+
+            app.input = match settings.requery_mode {
+                RequeryMode::Token => command.split_whitespace().next().unwrap_or("").to_string(),
+                RequeryMode::Command => command.to_string(),
+            };
+            query_results(app, search_mode, db).await.unwrap();
+        }
+        Key::Char(c) => {
+            app.input.push(c);
+            query_results(app, search_mode, db).await.unwrap();
+        }
+        Key::Backspace => {
+            app.pop_grapheme();
+            query_results(app, search_mode, db).await.unwrap();
+        }
+        // \u{7f} is escape sequence for backspace
+        Key::Alt('\u{7f}') => {
+            app.remember_query();
+            app.pop_word();
+            query_results(app, search_mode, db).await.unwrap();
+        }
+        Key::Alt('h' | 'e' | 'w') | Key::Ctrl('l') => {
+            app.toggle_filter(input);
+            query_results(app, search_mode, db).await.unwrap();
+        }
+        Key::Alt('a') if app.results_state.selected().is_some() => app.menu = Some(0),
+        Key::Alt('y') => {
+            app.run_action(Action::CopyShare);
+        }
+        Key::Alt('j') => open_dirs(app, db).await,
+        Key::Alt('f') => app.open_form(),
+        Key::Alt(c @ ('+' | '=' | '-')) => {
+            app.change_limit(c != '-');
+            query_results(app, search_mode, db).await.unwrap();
+        }
+        Key::Alt('m') => app.toggle_marked(),
+        Key::Alt('z') => app.scratch = !app.scratch,
+        Key::Alt('r') => refresh(app, search_mode, db).await.unwrap(),
+        Key::Alt('t') if app.results_state.selected().is_some() => {
+            app.tagging = Some(String::new());
+        }
+        Key::Alt('s') if app.results_state.selected().is_some() => {
+            app.substituting = Some(String::from("s/"));
+        }
+        Key::Alt('d') => app.debug = !app.debug,
+        Key::Alt('i') => app.show_index = !app.show_index,
+        Key::Alt('l') => app.cycle_layout(),
+        Key::Alt('c') => {
+            if let Err(e) = toggle_collected(app, db).await {
+                app.query_error = Some(e.to_string());
+            }
+        }
+        Key::Ctrl('u') => {
+            app.remember_query();
+            app.input = String::from("");
+            query_results(app, search_mode, db).await.unwrap();
+        }
+        Key::Ctrl('r') => {
+            app.recall_query();
+            query_results(app, search_mode, db).await.unwrap();
+        }
+        Key::Down | Key::Ctrl('n') => app.select_down(settings.edge_behavior),
+        Key::Up | Key::Ctrl('p') => app.select_up(settings.edge_behavior),
+        _ => {}
+    };
+
+    None
+}
+
+fn results_title(app: &State) -> String {
+    let filters = app.active_filters();
+
+    let title = if filters.is_empty() {
+        String::from("History")
+    } else {
+        format!("History ({})", filters.join(", "))
+    };
+
+    if app.collected.is_empty() {
+        title
+    } else {
+        format!("{} [{}: {}]", title, app.collection, app.collected.len())
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn draw<T: Backend>(
+    f: &mut Frame<'_, T>,
+    history_count: i64,
+    app: &mut State,
+    settings: &Settings,
+) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints(
+            [
+                Constraint::Length(2),
+                Constraint::Min(1),
+                Constraint::Length(3),
+            ]
+            .as_ref(),
+        )
+        .split(f.size());
+
+    let top_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .split(chunks[0]);
+
+    let top_left_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(1), Constraint::Length(1)].as_ref())
+        .split(top_chunks[0]);
+
+    let top_right_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(1), Constraint::Length(1)].as_ref())
+        .split(top_chunks[1]);
+
+    let title = Paragraph::new(Text::from(Span::styled(
+        app.title(),
+        Style::default().add_modifier(Modifier::BOLD),
+    )));
+
+    let help = vec![
+        Span::raw("Press "),
+        Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
+        Span::raw(" to exit."),
+    ];
+
+    let help = Text::from(Spans::from(help));
+    let help = Paragraph::new(help);
+
+    let (input_text, input_style) = app.input_text();
+    let input_width = input_text.width();
+    // Make it obvious when there's a problem with the query, so it isn't
+    // mistaken for there being no matches
+    let input_block = match &app.query_error {
+        Some(e) => Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Red))
+            .title(Span::styled(
+                format!("Query ({})", e),
+                Style::default().fg(Color::Red),
+            )),
+        None => Block::default().borders(Borders::ALL).title("Query"),
+    };
+    let input = Paragraph::new(input_text)
+        .style(input_style)
+        .block(input_block);
+
+    let stats = Paragraph::new(Text::from(Span::raw(format!(
+        "history count: {}",
+        app.locale.format_count(history_count),
+    ))))
+    .alignment(Alignment::Right);
+
+    app.render_results(
+        f,
+        chunks[1],
+        Block::default()
+            .borders(Borders::ALL)
+            .title(results_title(app)),
+        settings,
+    );
+
+    let mut range = format!(
+        "showing {} (limit {})",
+        app.results_range(),
+        app.max_entries
+    );
+    if let Some(loaded) = app.loaded_ago() {
+        range = format!("{}, {}", range, loaded);
+    }
+    let range = Paragraph::new(Text::from(Span::raw(range))).alignment(Alignment::Right);
+
+    f.render_widget(title, top_left_chunks[0]);
+    f.render_widget(help, top_left_chunks[1]);
+    f.render_widget(stats, top_right_chunks[0]);
+    f.render_widget(range, top_right_chunks[1]);
+    f.render_widget(input, chunks[2]);
+
+    f.set_cursor(
+        // Put cursor past the end of the input text
+        chunks[2].x
+            + if app.prompting() {
+                input_width
+            } else {
+                app.input_width()
+            } as u16
+            + 1,
+        // Move one line down, from the border to the input line
+        chunks[2].y + 1,
+    );
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn draw_compact<T: Backend>(
+    f: &mut Frame<'_, T>,
+    history_count: i64,
+    app: &mut State,
+    settings: &Settings,
+) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(0)
+        .horizontal_margin(1)
+        .constraints(
+            [
+                Constraint::Length(1),
+                Constraint::Min(1),
+                Constraint::Length(1),
+            ]
+            .as_ref(),
+        )
+        .split(f.size());
+
+    let header_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(
+            [
+                Constraint::Ratio(1, 3),
+                Constraint::Ratio(1, 3),
+                Constraint::Ratio(1, 3),
+            ]
+            .as_ref(),
+        )
+        .split(chunks[0]);
+
+    let title = Paragraph::new(Text::from(Span::styled(
+        app.title(),
+        Style::default().fg(Color::DarkGray),
+    )));
+
+    let filters = app.active_filters();
+    let help = if filters.is_empty() {
+        Spans::from(vec![
+            Span::styled("Esc", Style::default().add_modifier(Modifier::BOLD)),
+            Span::raw(" to exit"),
+        ])
+    } else {
+        Spans::from(Span::raw(filters.join(", ")))
+    };
+    let help = Paragraph::new(Text::from(help))
+        .style(Style::default().fg(Color::DarkGray))
+        .alignment(Alignment::Center);
+
+    app.render_results(f, chunks[1], Block::default(), settings);
+
+    let (start, end) = app.results_window;
+    let mut stats = format!(
+        "{}-{}/{}  history count: {}",
+        std::cmp::min(start + 1, end),
+        end,
+        app.locale.format_count(app.results.len() as i64),
+        app.locale.format_count(history_count),
+    );
+    if let Some(loaded) = app.loaded_ago() {
+        stats = format!("{}  {}", stats, loaded);
+    }
+    let stats = Paragraph::new(Text::from(Span::raw(stats)))
+        .style(Style::default().fg(Color::DarkGray))
+        .alignment(Alignment::Right);
+
+    let (input_text, input_style) = app.input_text();
+    let input_width = input_text.width();
+    let mut input = vec![Span::styled(format!("] {}", input_text), input_style)];
+    if let Some(e) = &app.query_error {
+        input.push(Span::styled(
+            format!("  ({})", e),
+            Style::default().fg(Color::Red),
+        ));
+    }
+    let input = Paragraph::new(Spans::from(input)).block(Block::default());
+
+    f.render_widget(title, header_chunks[0]);
+    f.render_widget(help, header_chunks[1]);
+    f.render_widget(stats, header_chunks[2]);
+    f.render_widget(input, chunks[2]);
+
+    f.set_cursor(
+        // Put cursor past the end of the input text
+        chunks[2].x
+            + if app.prompting() {
+                input_width
+            } else {
+                app.input_width()
+            } as u16
+            + 2,
+        // Move one line down, from the border to the input line
+        chunks[2].y + 1,
+    );
+}
+
+/// Draw the debug overlay in the top right corner, if it is enabled
+#[allow(clippy::cast_possible_truncation)]
+fn draw_debug<T: Backend>(f: &mut Frame<'_, T>, app: &State, settings: &Settings) {
+    if !app.debug {
+        return;
+    }
+
+    let stats = app.query_stats;
+    let lines = vec![
+        Spans::from(format!("mode: {:?}", settings.search_mode)),
+        Spans::from(format!("latency: {:.1?}", stats.latency)),
+        Spans::from(format!(
+            "rows: {} {}, {} shown",
+            stats.fetched,
+            if stats.narrowed {
+                "narrowed"
+            } else {
+                "fetched"
+            },
+            stats.shown
+        )),
+    ];
+
+    let size = f.size();
+    let width = std::cmp::min(36, size.width);
+    let height = std::cmp::min(lines.len() as u16 + 2, size.height);
+    let area = Rect::new(size.x + size.width - width, size.y, width, height);
+
+    let debug = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("Debug"));
+
+    f.render_widget(Clear, area);
+    f.render_widget(debug, area);
+}
+
+/// Draw the action menu over the middle of the screen, if it is open
+fn draw_menu<T: Backend>(f: &mut Frame<'_, T>, app: &State) {
+    if let Some(selected) = app.menu {
+        let items: Vec<ListItem> = Action::ALL
+            .iter()
+            .map(|a| ListItem::new(a.label()))
+            .collect();
+
+        draw_popup(f, "Actions", items, selected, 24);
+    }
+}
+
+/// Draw the recent directories list over the middle of the screen, if it is
+/// open
+fn draw_dirs<T: Backend>(f: &mut Frame<'_, T>, app: &State) {
+    if let Some(selected) = app.dirs_selected {
+        let width = app.dirs.iter().map(|d| d.width()).max().unwrap_or(0) + 5;
+        let items: Vec<ListItem> = app.dirs.iter().map(|d| ListItem::new(d.as_str())).collect();
+
+        draw_popup(f, "Recent directories", items, selected, width);
+    }
+}
+
+/// Draw the filter form over the middle of the screen, if it is open
+fn draw_form<T: Backend>(f: &mut Frame<'_, T>, app: &State) {
+    if let Some(form) = &app.form {
+        let items = FormField::ALL
+            .iter()
+            .map(|&field| ListItem::new(format!("{:<8}{}", field.label(), form.value(field))))
+            .collect();
+
+        draw_popup(f, "Filters", items, form.focus, 50);
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn draw_popup<T: Backend>(
+    f: &mut Frame<'_, T>,
+    title: &str,
+    items: Vec<ListItem>,
+    selected: usize,
+    width: usize,
+) {
+    let size = f.size();
+    let width = std::cmp::min(width.max(title.width() + 4) as u16, size.width);
+    let height = std::cmp::min(items.len() as u16 + 2, size.height);
+    let area = Rect::new(
+        size.x + (size.width - width) / 2,
+        size.y + (size.height - height) / 2,
+        width,
+        height,
+    );
+
+    let popup = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))
+        .highlight_symbol(" > ");
+
+    let mut state = ListState::default();
+    state.select(Some(selected));
+
+    f.render_widget(Clear, area);
+    f.render_stateful_widget(popup, area, &mut state);
+}
+
+/// Turns on bracketed paste while the UI is up, so pasted text can be told
+/// apart from typing. Like `MouseTerminal`, it's turned off again when dropped
+struct BracketedPaste<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> From<W> for BracketedPaste<W> {
+    fn from(mut inner: W) -> Self {
+        inner.write_all(b"\x1b[?2004h").ok();
+        Self { inner }
+    }
+}
+
+impl<W: Write> Drop for BracketedPaste<W> {
+    fn drop(&mut self) {
+        self.inner.write_all(b"\x1b[?2004l").ok();
+        self.inner.flush().ok();
+    }
+}
+
+impl<W: Write> Write for BracketedPaste<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+type PanicHook = Arc<dyn Fn(&PanicInfo<'_>) + Send + Sync + 'static>;
+
+/// Holds back panic messages from this thread while the UI is up. They would
+/// otherwise be printed to the alternate screen in raw mode, and lost. The
+/// terminal is restored as it's dropped while unwinding, and this guard is then
+/// dropped after it and prints the message
+struct PanicGuard {
+    thread: ThreadId,
+    message: Arc<Mutex<Option<String>>>,
+    previous: PanicHook,
+}
+
+impl PanicGuard {
+    fn new() -> Self {
+        let thread = thread::current().id();
+        let message = Arc::new(Mutex::new(None));
+        let previous: PanicHook = Arc::from(panic::take_hook());
+
+        {
+            let message = message.clone();
+            let previous = previous.clone();
+            panic::set_hook(Box::new(move |info| {
+                let current = thread::current();
+                if current.id() == thread {
+                    let name = current.name().unwrap_or("<unnamed>");
+                    *message.lock().unwrap() = Some(format!("thread '{}' {}", name, info));
+                } else {
+                    previous(info);
+                }
+            }));
+        }
+
+        Self {
+            thread,
+            message,
+            previous,
+        }
+    }
+}
+
+impl Drop for PanicGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            // The hook can't be changed while panicking, but the process is on
+            // its way out anyway
+            if let Some(message) = self.message.lock().ok().and_then(|mut m| m.take()) {
+                // Make sure the terminal has really left the alternate screen
+                stdout().flush().ok();
+                eprintln!("{}", message);
+            }
+        } else if thread::current().id() == self.thread {
+            let previous = self.previous.clone();
+            drop(panic::take_hook());
+            panic::set_hook(Box::new(move |info| previous(info)));
+        }
+    }
+}
+
+/// Commands that need confirming before the UI returns them
+fn dangerous_patterns(settings: &Settings) -> Result<Vec<Regex>> {
+    settings
+        .dangerous_patterns
+        .iter()
+        .map(|p| Regex::new(p))
+        .collect::<Result<_, _>>()
+        .wrap_err("invalid pattern in dangerous_patterns")
+}
+
+/// A duration setting such as `search_timeout`, which is off when set to 0
+fn duration_setting(name: &str, value: &str) -> Result<Option<Duration>> {
+    match value {
+        "" | "0" => Ok(None),
+        t => humantime::parse_duration(t)
+            .map(Some)
+            .wrap_err_with(|| format!("invalid {} {:?}", name, t)),
+    }
+}
+
+// this is a big blob of horrible! clean it up!
+// for now, it works. But it'd be great if it were more easily readable, and
+// modular. I'd like to add some more stats and stuff at some point
+#[allow(clippy::cast_possible_truncation)]
+async fn select_history(
+    query: &[String],
+    settings: &Settings,
+    db: &mut (impl Database + Send + Sync),
+    collection: &str,
+    max_entries: usize,
+    scratch: bool,
+    host: Option<&str>,
+) -> Result<(Selected, bool)> {
+    // Declared before the terminal so it's dropped after it, once the
+    // terminal has been put back to normal
+    let _panic_guard = PanicGuard::new();
+
+    let stdout = stdout().into_raw_mode()?;
+    let stdout = MouseTerminal::from(stdout);
+    let stdout = BracketedPaste::from(stdout);
+    let stdout = AlternateScreen::from(stdout);
+    let backend = TermionBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
+
+    // Setup event handlers
+    let events = Events::new();
+
+    let mut app = State {
+        input: query.join(" "),
+        current_dir: std::env::current_dir()
+            .ok()
+            .and_then(|d| d.to_str().map(String::from)),
+        dangerous: dangerous_patterns(settings)?,
+        search_timeout: duration_setting("search_timeout", &settings.search_timeout)?,
+        auto_refresh: duration_setting("auto_refresh", &settings.auto_refresh)?,
+        fuzzy_boundaries: settings.fuzzy_boundaries.clone(),
+        fuzzy_min_score: settings.fuzzy_min_score,
+        aliases: settings.fuzzy_aliases(),
+        empty_query: settings.empty_query_behavior,
+        jump_mode: settings.jump_mode,
+        layout: settings.style,
+        show_matching_line: settings.show_matching_line,
+        max_display_length: settings.max_display_length,
+        env_mask: settings.env_mask()?,
+        share_format: settings.share_format.clone(),
+        time_format: settings.time_format.clone(),
+        locale: Locale::new(&settings.locale),
+        incremental: settings.incremental_search,
+        show_counts: settings.ui_columns.contains(&Column::Count),
+        show_terms: settings.ui_columns.contains(&Column::Terms),
+        group_separators: settings.group_separators,
+        clusters: settings.cluster_results && settings.search_mode == SearchMode::Fuzzy,
+        min_length: settings.min_command_length,
+        hide_unknown_duration: settings.hide_unknown_duration,
+        show_index: settings.show_selection_index,
+        max_entries,
+        scratch,
+        host_filter: host.map(String::from),
+        collection: collection.to_string(),
+        collected: db
+            .collection(collection)
+            .await?
+            .into_iter()
+            .map(|h| h.id)
+            .collect(),
+        debug: std::env::var_os("ATUIN_DEBUG").is_some(),
+        ..State::default()
+    };
+
+    query_results(&mut app, settings.search_mode, db).await?;
+
+    loop {
+        let history_count = db.history_count().await?;
+        // Handle input. Ticks and resizes only need a redraw, which also
+        // re-checks the compact threshold against the new size
+        let input = match events.next()? {
+            Event::Input(input) => Some(input),
+            Event::Paste(text) => {
+                paste(&text, settings.search_mode, db, &mut app).await;
+                None
+            }
+            Event::Tick | Event::Resize => None,
+        };
+
+        if let Some(input) = input {
+            if let Some(selected) = key_handler(input, settings, db, &mut app).await {
+                return Ok((selected, app.scratch));
+            }
+        }
+
+        if app.refresh_due() {
+            refresh(&mut app, settings.search_mode, db).await?;
+        }
+
+        if app.compact(terminal.size().ok()) {
+            terminal.draw(|f| {
+                draw_compact(f, history_count, &mut app, settings);
+                draw_debug(f, &app, settings);
+                draw_menu(f, &app);
+                draw_dirs(f, &app);
+                draw_form(f, &app);
+            })?;
+        } else {
+            terminal.draw(|f| {
+                draw(f, history_count, &mut app, settings);
+                draw_debug(f, &app, settings);
+                draw_menu(f, &app);
+                draw_dirs(f, &app);
+                draw_form(f, &app);
+            })?;
+        }
+
+        // Copy with an OSC 52 sequence, which the terminal passes on to the
+        // system clipboard. This works over ssh too
+        if let Some(text) = app.clipboard.take() {
+            let backend = terminal.backend_mut();
+            write!(backend, "\x1b]52;c;{}\x07", base64::encode(text))?;
+            Backend::flush(backend)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use clap::Parser;
+    use clap_complete::Shell;
+    use regex::Regex;
+
+    use atuin_client::{
+        alias::Aliases,
+        database::{Database, Sqlite},
+        history::History,
+        query::Filters,
+        settings::{
+            Column, EdgeBehavior, EmptyQuery, JumpMode, SearchMode, Style as UiLayout, Tiebreak,
+        },
+    };
+
+    use super::{
+        aggregate_durations, align, display_command, fill_prompt, in_dir, matching_line,
+        pasted_query, query_results, rank_by_frequency, shell_quote, split_tags, Action, Cmd,
+        FilterForm, FormField, Group, Selected, Sort, SortKey, State,
+    };
+
+    fn state(input: &str) -> State {
+        State {
+            input: input.to_string(),
+            ..State::default()
+        }
+    }
+
+    /// A run of `command` in /, `secs` seconds from now, that succeeded after
+    /// a nanosecond
+    pub(super) fn history(command: &str, secs: i64, session: Option<&str>) -> History {
+        History::new(
+            chrono::Utc::now() + chrono::Duration::seconds(secs),
+            command.to_string(),
+            "/".to_string(),
+            0,
+            1,
+            session.map(String::from),
+            None,
+        )
+    }
+
+    #[test]
+    fn pop_grapheme_removes_whole_cluster() {
+        let mut app = state("ls 👍🏽");
+        app.pop_grapheme();
+        assert_eq!(app.input, "ls ");
+
+        let mut app = state("cafe\u{301}");
+        app.pop_grapheme();
+        assert_eq!(app.input, "caf");
+
+        let mut app = state("");
+        app.pop_grapheme();
+        assert_eq!(app.input, "");
+    }
+
+    #[test]
+    fn pop_word() {
+        let mut app = state("git commit -m 👨‍👩‍👧");
+        app.pop_word();
+        assert_eq!(app.input, "git commit -m");
+        app.pop_word();
+        assert_eq!(app.input, "git commit");
+
+        let mut app = state("git ");
+        app.pop_word();
+        assert_eq!(app.input, "git");
+        app.pop_word();
+        assert_eq!(app.input, "");
+    }
+
+    #[test]
+    fn scroll_follows_selection() {
+        let mut app = state("");
+        app.results = (0..10)
+            .map(|i| history(&format!("command {}", i), 0, None))
+            .collect();
+
+        app.results_state.select(Some(0));
+        assert_eq!(app.scroll(4), (0, 4));
+        assert_eq!(app.results_range(), "1-4 of 10");
+
+        // only scrolls once the selection leaves the window
+        app.results_state.select(Some(3));
+        assert_eq!(app.scroll(4), (0, 4));
+        app.results_state.select(Some(6));
+        assert_eq!(app.scroll(4), (3, 7));
+        app.results_state.select(Some(4));
+        assert_eq!(app.scroll(4), (3, 7));
+        app.results_state.select(Some(1));
+        assert_eq!(app.scroll(4), (1, 5));
+
+        assert_eq!(app.scroll(20), (1, 10));
+    }
+
+    #[test]
+    fn group_separators() {
+        let history = |days: i64| History {
+            timestamp: chrono::Utc::now() - chrono::Duration::days(days),
+            ..history(&format!("ran {} days ago", days), 0, None)
+        };
+
+        let mut app = state("");
+        app.results = vec![history(0), history(0), history(1), history(3), history(4)];
+
+        let starts: Vec<bool> = (0..6).map(|i| app.starts_group(i)).collect();
+        assert_eq!(starts, vec![false, false, true, true, false, false]);
+
+        app.results_state.select(Some(0));
+        assert_eq!(app.rows(5), 5);
+
+        app.group_separators = true;
+        assert_eq!(app.rows(5), 3);
+    }
+
+    #[test]
+    fn auto_refresh() {
+        let mut app = state("");
+        assert!(app.loaded_ago().is_none());
+        assert!(!app.refresh_due());
+
+        let minutes_ago = std::time::Instant::now() - std::time::Duration::from_secs(150);
+        app.loaded_at = Some(minutes_ago);
+        assert_eq!(app.loaded_ago().as_deref(), Some("loaded 2m ago"));
+
+        // Only when there's a refresh interval
+        assert!(!app.refresh_due());
+        app.auto_refresh = Some(std::time::Duration::from_secs(60));
+        assert!(app.refresh_due());
+
+        app.tagging = Some(String::new());
+        assert!(!app.refresh_due());
+        app.tagging = None;
+
+        app.loaded_at = Some(std::time::Instant::now());
+        assert!(!app.refresh_due());
+
+        app.loaded_at = Some(minutes_ago);
+        app.clear_results();
+        assert!(!app.refresh_due());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn auto_refresh_after_timeout() {
+        // Enough history for the query to take a moment
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        let history: Vec<History> = (0..5_000)
+            .map(|i| history(&format!("ls {}", i), 0, None))
+            .collect();
+        db.save_bulk(&history).await.unwrap();
+
+        let mut app = state("ls");
+        app.max_entries = 10;
+        app.auto_refresh = Some(std::time::Duration::from_secs(60));
+        app.loaded_at = Some(std::time::Instant::now() - std::time::Duration::from_secs(150));
+        assert!(app.refresh_due());
+
+        // A query that times out still counts as a refresh
+        app.search_timeout = Some(std::time::Duration::ZERO);
+        query_results(&mut app, SearchMode::Prefix, &mut db)
+            .await
+            .unwrap();
+        assert_eq!(
+            app.query_error.as_deref(),
+            Some("query timed out, refine your search")
+        );
+        assert!(!app.refresh_due());
+    }
+
+    #[test]
+    fn cluster_results() {
+        use termion::event::Key;
+
+        let commands = |app: &State| -> Vec<String> {
+            app.results.iter().map(|h| h.command.clone()).collect()
+        };
+        let results = vec![
+            history("git status", 0, None),
+            history("ls", 0, None),
+            history("git push", 0, None),
+            history("cargo test", 0, None),
+            history("git pull", 0, None),
+        ];
+
+        let mut app = state("");
+        app.results = results.clone();
+        app.cluster_results();
+        assert_eq!(commands(&app).len(), 5);
+
+        // Without grouping, the keys are left alone
+        app.results_state.select(Some(2));
+        assert!(!app.toggle_cluster(Key::Left));
+        assert!(!app.toggle_cluster(Key::Char('\n')));
+        assert_eq!(app.results_state.selected(), Some(2));
+
+        app.clusters = true;
+        app.cluster_results();
+        assert_eq!(commands(&app), vec!["git status", "ls", "cargo test"]);
+        assert_eq!(app.cluster_at(0), Some(("git", 3)));
+        assert_eq!(app.cluster_at(1), None);
+
+        // Enter opens the group rather than picking the command
+        app.results_state.select(Some(0));
+        assert!(app.toggle_cluster(Key::Char('\n')));
+        app.results = results;
+        app.cluster_results();
+        assert_eq!(
+            commands(&app),
+            vec!["git status", "git push", "git pull", "ls", "cargo test"]
+        );
+        assert!(!app.toggle_cluster(Key::Right));
+
+        // Left closes it from any of its results
+        app.results_state.select(Some(2));
+        assert!(app.toggle_cluster(Key::Left));
+        assert_eq!(app.results_state.selected(), Some(0));
+        assert!(!app.toggle_cluster(Key::Left));
+    }
+
+    #[test]
+    fn cycle_layout() {
+        let mut app = state("");
+        assert!(matches!(app.layout, UiLayout::Auto));
+        assert!(!app.title().contains('('));
+
+        let short = Some(tui::layout::Rect::new(0, 0, 80, 10));
+        assert!(app.compact(short));
+
+        app.cycle_layout();
+        assert!(matches!(app.layout, UiLayout::Full));
+        assert!(!app.compact(short));
+        assert!(app.title().ends_with("(full)"));
+
+        app.cycle_layout();
+        assert!(matches!(app.layout, UiLayout::Compact));
+        assert!(app.title().ends_with("(compact)"));
+
+        app.cycle_layout();
+        assert!(matches!(app.layout, UiLayout::Auto));
+
+        app.scratch = true;
+        assert!(app.title().ends_with(" (scratch)"));
+    }
+
+    #[test]
+    fn expanded_height() {
+        let mut app = state("");
+        app.results = vec![
+            history("ls", 0, None),
+            history("cat <<EOF\na\nb\nEOF", 0, None),
+        ];
+        app.results_state.select(Some(1));
+        assert_eq!(app.expanded_height(10), 1);
+
+        app.expanded = true;
+        assert_eq!(app.expanded_height(10), 4);
+        assert_eq!(app.expanded_height(3), 3);
+
+        // The expanded command pushes the others out of the window
+        app.scroll(10 + 1 - app.expanded_height(10));
+        assert_eq!(app.results_window, (0, 2));
+        app.scroll(3 + 1 - app.expanded_height(3));
+        assert_eq!(app.results_window, (1, 2));
+
+        app.results_state.select(Some(0));
+        assert_eq!(app.expanded_height(10), 1);
+    }
+
+    #[test]
+    fn jump_modes() {
+        let mut app = state("");
+        app.results = (0..20)
+            .map(|i| history(&format!("command {}", i), 0, None))
+            .collect();
+
+        app.results_state.select(Some(12));
+        app.scroll(10);
+        assert_eq!(app.results_window, (3, 13));
+
+        assert_eq!(app.jump_number(12), None);
+        assert_eq!(app.jump_number(13), Some(1));
+        assert_eq!(app.jump_number(19), Some(7));
+        assert_eq!(app.jump_target(2), Some(14));
+
+        // Counting up from the bottom of the screen instead
+        app.jump_mode = JumpMode::Absolute;
+        assert_eq!(app.jump_number(2), None);
+        assert_eq!(app.jump_number(3), Some(1));
+        assert_eq!(app.jump_number(12), None);
+        assert_eq!(app.jump_number(11), Some(9));
+        assert_eq!(app.jump_target(2), Some(4));
+
+        // Which doesn't need anything to be selected
+        app.results_state.select(None);
+        assert_eq!(app.jump_target(1), Some(3));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn search_with_aliases() {
+        async fn search(db: &mut Sqlite, query: &str, aliases: Option<&Aliases>) -> Vec<String> {
+            super::search_with_aliases(
+                db,
+                None,
+                SearchMode::Fuzzy,
+                query,
+                &Filters::default(),
+                aliases,
+            )
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|h| h.command)
+            .collect()
+        }
+
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        for (offset, command) in [(1, "gco main"), (2, "git checkout fix"), (0, "ls")] {
+            let history = history(command, offset, None);
+            db.save(&history).await.unwrap();
+        }
+
+        let aliases = Aliases::new(HashMap::from([(
+            String::from("gco"),
+            String::from("git checkout"),
+        )]));
+
+        assert_eq!(
+            search(&mut db, "git checkout", None).await,
+            ["git checkout fix"]
+        );
+        assert_eq!(
+            search(&mut db, "git checkout", Some(&aliases)).await,
+            ["git checkout fix", "gco main"]
+        );
+        assert_eq!(
+            search(&mut db, "gco", Some(&aliases)).await,
+            ["git checkout fix", "gco main"]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn invalid_regex() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        for command in ["git push", "git pull", "git status"] {
+            db.save(&history(command, 0, None)).await.unwrap();
+        }
+
+        let mut app = state("^git (push|pull)");
+        app.max_entries = 10;
+        query_results(&mut app, SearchMode::Regex, &mut db)
+            .await
+            .unwrap();
+        assert_eq!(app.results.len(), 2);
+        assert_eq!(app.query_error, None);
+
+        // The results so far stay, along with what's wrong
+        app.input = String::from("^git (push|pull");
+        query_results(&mut app, SearchMode::Regex, &mut db)
+            .await
+            .unwrap();
+        assert_eq!(app.results.len(), 2);
+        assert_eq!(
+            app.query_error.as_deref(),
+            Some("invalid regex: unclosed group")
+        );
+
+        assert!(super::check_regex(SearchMode::Prefix, "(").is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn blank_empty_query() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        let history = history("ls", 0, None);
+        db.save(&history).await.unwrap();
+
+        let mut app = state("");
+        app.max_entries = 10;
+        query_results(&mut app, SearchMode::Prefix, &mut db)
+            .await
+            .unwrap();
+        assert_eq!(app.results.len(), 1);
+
+        app.empty_query = EmptyQuery::Blank;
+        query_results(&mut app, SearchMode::Prefix, &mut db)
+            .await
+            .unwrap();
+        assert!(app.results.is_empty());
+        assert_eq!(app.results_state.selected(), None);
+
+        app.input = String::from("l");
+        query_results(&mut app, SearchMode::Prefix, &mut db)
+            .await
+            .unwrap();
+        assert_eq!(app.results.len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn incremental_search() {
+        async fn commands(app: &mut State, input: &str, db: &mut Sqlite) -> Vec<String> {
+            app.input = input.to_string();
+            query_results(app, SearchMode::FullText, db).await.unwrap();
+            app.results.iter().map(|h| h.command.clone()).collect()
+        }
+
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        for command in ["cargo build", "cargo test", "git commit"] {
+            db.save(&history(command, 0, None)).await.unwrap();
+        }
+
+        let mut app = state("");
+        app.max_entries = 10;
+        app.incremental = true;
+
+        assert_eq!(commands(&mut app, "c", &mut db).await.len(), 3);
+        assert!(!app.query_stats.narrowed);
+
+        // Typing more narrows what was loaded, so this new command isn't seen
+        db.save(&history("cargo run", 0, None)).await.unwrap();
+        assert_eq!(commands(&mut app, "cargo t", &mut db).await, ["cargo test"]);
+        assert!(app.query_stats.narrowed);
+
+        // Going back past the loaded query asks the database again
+        assert_eq!(commands(&mut app, "", &mut db).await.len(), 4);
+        assert!(!app.query_stats.narrowed);
+
+        // Which is also needed when the loaded query may have missed some
+        app.max_entries = 2;
+        commands(&mut app, "", &mut db).await;
+        commands(&mut app, "ca", &mut db).await;
+        assert!(!app.query_stats.narrowed);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tag_filter_before_limit() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        let mut saved = Vec::new();
+        for (secs, command) in [(0, "make deploy"), (1, "make test"), (2, "make")] {
+            let history = history(command, secs, None);
+            db.save(&history).await.unwrap();
+            saved.push(history);
+        }
+        db.add_tag(&saved[0], "prod").await.unwrap();
+
+        let mut app = state("ma");
+        app.max_entries = 1;
+        app.incremental = true;
+        query_results(&mut app, SearchMode::FullText, &mut db)
+            .await
+            .unwrap();
+        assert_eq!(app.results[0].command, "make");
+
+        // Older than the one result the limit allows, but the only one tagged
+        for input in ["tag:prod", "ma tag:prod"] {
+            app.input = input.to_string();
+            query_results(&mut app, SearchMode::FullText, &mut db)
+                .await
+                .unwrap();
+            assert_eq!(app.results.len(), 1);
+            assert_eq!(app.results[0].command, "make deploy");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn form_filters_before_limit() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        let start = chrono::Utc::now() - chrono::Duration::hours(1);
+        for (mins, command, exit) in [(0, "make deploy", 2), (10, "make test", 1), (20, "make", 0)]
+        {
+            let history = History {
+                timestamp: start + chrono::Duration::minutes(mins),
+                exit,
+                ..history(command, 0, None)
+            };
+            db.save(&history).await.unwrap();
+        }
+
+        let mut app = state("");
+        app.max_entries = 1;
+
+        // Both older than the one result the limit allows
+        app.exit_filter = Some(2);
+        query_results(&mut app, SearchMode::FullText, &mut db)
+            .await
+            .unwrap();
+        assert_eq!(app.results.len(), 1);
+        assert_eq!(app.results[0].command, "make deploy");
+
+        app.exit_filter = None;
+        let before = start + chrono::Duration::minutes(15);
+        app.before_filter = Some((String::new(), before));
+        app.after_filter = Some((String::new(), start + chrono::Duration::minutes(5)));
+        app.input = String::from("ma");
+        query_results(&mut app, SearchMode::FullText, &mut db)
+            .await
+            .unwrap();
+        assert_eq!(app.results.len(), 1);
+        assert_eq!(app.results[0].command, "make test");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cwd_filter_before_limit() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        for (secs, cwd, command) in [(-20, "/src", "make"), (-10, "/tmp", "ls")] {
+            let history = History {
+                cwd: cwd.to_string(),
+                ..history(command, secs, None)
+            };
+            db.save(&history).await.unwrap();
+        }
+
+        let mut app = state("");
+        app.max_entries = 1;
+
+        // Older than the one result the limit allows
+        app.cwd_filter = Some(String::from("/src"));
+        query_results(&mut app, SearchMode::FullText, &mut db)
+            .await
+            .unwrap();
+        assert_eq!(app.results.len(), 1);
+        assert_eq!(app.results[0].command, "make");
+
+        // Alt-w filters the same way, by the directory the UI was opened in
+        app.cwd_filter = None;
+        app.current_dir = Some(String::from("/src"));
+        app.toggle_current_dir_filter();
+        query_results(&mut app, SearchMode::FullText, &mut db)
+            .await
+            .unwrap();
+        assert_eq!(app.results.len(), 1);
+        assert_eq!(app.results[0].command, "make");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failed_filter_before_limit() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        for (secs, command, exit, duration) in [
+            (-30, "make", 1, 1),
+            (-20, "make test", 1, -1),
+            (-10, "ls", 0, 1),
+        ] {
+            let history = History {
+                duration,
+                exit,
+                ..history(command, secs, None)
+            };
+            db.save(&history).await.unwrap();
+        }
+
+        let mut app = state("");
+        app.max_entries = 1;
+
+        // Older than the one result the limit allows, past one still running
+        app.failed_only = true;
+        query_results(&mut app, SearchMode::FullText, &mut db)
+            .await
+            .unwrap();
+        assert_eq!(app.results.len(), 1);
+        assert_eq!(app.results[0].command, "make");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn host_filter_before_limit() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        for (secs, hostname, command) in [(-20, "prod-1:deploy", "make"), (-10, "laptop:me", "ls")]
+        {
+            let history = History {
+                hostname: hostname.to_string(),
+                ..history(command, secs, None)
+            };
+            db.save(&history).await.unwrap();
+        }
+
+        // As given with --host and --interactive, without the user
+        let mut app = state("");
+        app.max_entries = 1;
+        app.host_filter = Some(String::from("prod-1"));
+        query_results(&mut app, SearchMode::FullText, &mut db)
+            .await
+            .unwrap();
+        assert_eq!(app.results.len(), 1);
+        assert_eq!(app.results[0].command, "make");
+
+        // Alt-h cycles through the hosts found without a host filter
+        let mut app = state("");
+        app.max_entries = 2;
+        query_results(&mut app, SearchMode::FullText, &mut db)
+            .await
+            .unwrap();
+        app.max_entries = 1;
+
+        for expected in ["ls", "make", "ls"] {
+            app.cycle_host_filter();
+            query_results(&mut app, SearchMode::FullText, &mut db)
+                .await
+                .unwrap();
+            assert_eq!(app.results.len(), 1);
+            assert_eq!(app.results[0].command, expected);
+        }
+        assert!(app.host_filter.is_none());
+    }
+
+    #[test]
+    fn matches_loaded() {
+        use super::{can_match_loaded, matches_loaded};
+
+        assert!(matches_loaded(SearchMode::Prefix, "Car", "cargo build"));
+        assert!(!matches_loaded(SearchMode::Prefix, "build", "cargo build"));
+        assert!(matches_loaded(SearchMode::FullText, "build", "cargo build"));
+        assert!(matches_loaded(SearchMode::Fuzzy, "cg bld", "cargo build"));
+        assert!(!matches_loaded(SearchMode::Fuzzy, "gc", "cargo build"));
+
+        assert!(can_match_loaded(SearchMode::Fuzzy, "cg bld"));
+        assert!(!can_match_loaded(SearchMode::Fuzzy, "Cargo"));
+        assert!(!can_match_loaded(SearchMode::Fuzzy, "!cargo"));
+        assert!(!can_match_loaded(SearchMode::Prefix, "cargo_*"));
+        assert!(!can_match_loaded(SearchMode::FullTextIndexed, "cargo"));
+    }
+
+    #[test]
+    fn change_limit() {
+        let mut app = state("");
+        app.max_entries = 200;
+
+        app.change_limit(true);
+        assert_eq!(app.max_entries, 400);
+
+        for _ in 0..10 {
+            app.change_limit(false);
+        }
+        assert_eq!(app.max_entries, 10);
+    }
+
+    #[test]
+    fn input_width() {
+        assert_eq!(state("ls").input_width(), 2);
+        assert_eq!(state("cafe\u{301}").input_width(), 4);
+        assert_eq!(state("👨‍👩‍👧").input_width(), 2);
+    }
+
+    #[test]
+    fn select_wraps() {
+        let mut app = state("");
+        app.results = (0..3)
+            .map(|i| history(&format!("command {}", i), 0, None))
+            .collect();
+        app.results_state.select(Some(0));
+
+        app.select_down(EdgeBehavior::Stop);
+        assert_eq!(app.results_state.selected(), Some(0));
+        app.select_down(EdgeBehavior::Wrap);
+        assert_eq!(app.results_state.selected(), Some(2));
+
+        app.select_up(EdgeBehavior::Stop);
+        assert_eq!(app.results_state.selected(), Some(2));
+        app.select_up(EdgeBehavior::Wrap);
+        assert_eq!(app.results_state.selected(), Some(0));
+
+        app.select_up(EdgeBehavior::Wrap);
+        assert_eq!(app.results_state.selected(), Some(1));
+
+        // Nothing to move through, but nothing to panic over either
+        app.results.clear();
+        app.select_up(EdgeBehavior::Wrap);
+        app.select_down(EdgeBehavior::Wrap);
+        assert_eq!(app.results_state.selected(), Some(0));
+    }
+
+    #[test]
+    fn select_deselects() {
+        let mut app = state("");
+        app.results = (0..3)
+            .map(|i| history(&format!("command {}", i), 0, None))
+            .collect();
+        app.results_state.select(Some(1));
+
+        app.select_up(EdgeBehavior::Deselect);
+        assert_eq!(app.results_state.selected(), Some(2));
+        app.select_up(EdgeBehavior::Deselect);
+        assert_eq!(app.results_state.selected(), None);
+
+        // Comes back in from the newest
+        app.select_down(EdgeBehavior::Deselect);
+        assert_eq!(app.results_state.selected(), Some(0));
+        app.select_down(EdgeBehavior::Deselect);
+        assert_eq!(app.results_state.selected(), None);
+        app.select_up(EdgeBehavior::Deselect);
+        assert_eq!(app.results_state.selected(), Some(0));
+    }
+
+    #[test]
+    fn hide_unknown_duration() {
+        let history = |duration| History {
+            duration,
+            ..history("make", 0, None)
+        };
+
+        let mut app = state("");
+        assert!(app.matches(&history(-1)));
+
+        app.hide_unknown_duration = true;
+        assert!(!app.matches(&history(-1)));
+        assert!(app.matches(&history(0)));
+        assert!(app.matches(&history(1_000_000)));
+    }
+
+    #[test]
+    fn recall_query() {
+        let mut app = state("");
+
+        // Nothing to recall
+        app.recall_query();
+        assert_eq!(app.input, "");
+
+        app.input = String::from("git");
+        app.remember_query();
+        app.input = String::from("cargo");
+        app.remember_query();
+        app.remember_query();
+        app.input = String::from("make");
+
+        app.recall_query();
+        assert_eq!(app.input, "cargo");
+        app.recall_query();
+        assert_eq!(app.input, "git");
+        app.recall_query();
+        assert_eq!(app.input, "make");
+        app.recall_query();
+        assert_eq!(app.input, "cargo");
+
+        assert_eq!(app.past_queries, vec!["git", "cargo", "make"]);
+    }
+
+    #[test]
+    fn empty_scrollbar() {
+        use tui::{backend::TestBackend, layout::Rect, Terminal};
+
+        let mut terminal = Terminal::new(TestBackend::new(10, 5)).unwrap();
+        terminal
+            .draw(|f| super::render_scrollbar(f, Rect::new(0, 0, 1, 0), 0, 10))
+            .unwrap();
+    }
+
+    #[test]
+    fn host_colors() {
+        use tui::style::Color;
+
+        // The same from one run, and one build, to the next
+        assert_eq!(super::host_color("laptop"), Color::LightRed);
+        assert_eq!(super::host_color("prod-1"), Color::Blue);
+        assert_eq!(super::host_color("server:root"), Color::Cyan);
+    }
+
+    #[test]
+    fn align_columns() {
+        assert_eq!(align("5s", 4, true), "  5s");
+        assert_eq!(align("/tmp", 6, false), "/tmp  ");
+        assert_eq!(align("too long", 3, false), "too long");
+    }
+
+    #[test]
+    fn index_cell() {
+        let mut app = state("");
+        let h = History {
+            exit: 1,
+            cwd: String::from("/tmp"),
+            ..history("ls", 0, None)
+        };
+        assert_eq!(app.cell(Column::Index, 3, &h, true), "   ");
+
+        app.results_state.select(Some(1));
+        assert_eq!(app.cell(Column::Index, 1, &h, true), "   ");
+        assert_eq!(app.cell(Column::Index, 3, &h, true), " 2 ");
+        assert_eq!(app.cell(Column::Index, 11, &h, true), "   ");
+        assert_eq!(app.cell(Column::Exit, 0, &h, true), "1");
+        assert_eq!(app.cell(Column::Cwd, 0, &h, true), "/tmp");
+
+        assert_eq!(app.cell(Column::Count, 0, &h, true), "");
+        app.counts.insert(String::from("ls"), 12);
+        assert_eq!(app.cell(Column::Count, 0, &h, true), "(x12)");
+
+        assert_eq!(app.cell(Column::Terms, 0, &h, true), "");
+        app.terms.insert(String::from("ls"), (1, 2));
+        assert_eq!(app.cell(Column::Terms, 0, &h, true), "1/2");
+    }
+
+    #[test]
+    fn matched_terms() {
+        use super::matched_terms;
+
+        let command = "kubectl get pods -n Prod";
+        assert_eq!(matched_terms("", command), None);
+        assert_eq!(matched_terms("kubectl", command), Some((1, 1)));
+        assert_eq!(matched_terms("get prod logs", command), Some((2, 3)));
+        assert_eq!(matched_terms("^kube pods$ 'get", command), Some((3, 3)));
+        assert_eq!(matched_terms("!logs | !pods", command), Some((1, 2)));
+    }
+
+    #[test]
+    fn cwd_filter() {
+        let history = |cwd: &str| History {
+            cwd: cwd.to_string(),
+            ..history("ls", 0, None)
+        };
+
+        let mut app = state("");
+        app.results = vec![history("/src"), history("/tmp")];
+
+        // Nothing selected, so nothing to filter by
+        app.toggle_cwd_filter();
+        assert!(app.cwd_filter.is_none());
+
+        app.results_state.select(Some(1));
+        app.toggle_cwd_filter();
+        assert_eq!(app.cwd_filter.as_deref(), Some("/tmp"));
+        assert_eq!(app.active_filters(), vec!["cwd: /tmp"]);
+        assert_eq!(app.sql_filters(&[]).cwd.as_deref(), Some("/tmp"));
+
+        app.toggle_cwd_filter();
+        assert!(app.cwd_filter.is_none());
+        assert!(app.sql_filters(&[]).cwd.is_none());
+    }
+
+    #[test]
+    fn current_dir_filter() {
+        let mut app = state("");
+
+        // Without a directory, such as one that isn't valid unicode, there's
+        // nothing to filter by
+        app.toggle_current_dir_filter();
+        assert!(app.cwd_filter.is_none());
+
+        app.current_dir = Some(String::from("/src"));
+        app.toggle_current_dir_filter();
+        assert_eq!(app.active_filters(), vec!["cwd: /src"]);
+
+        app.toggle_current_dir_filter();
+        assert!(app.cwd_filter.is_none());
+
+        // Another directory's filter is swapped for this one
+        app.cwd_filter = Some(String::from("/tmp"));
+        app.toggle_current_dir_filter();
+        assert_eq!(app.cwd_filter.as_deref(), Some("/src"));
+    }
+
+    #[test]
+    fn filter_form() {
+        let history = |cwd: &str, exit: i64| History {
+            exit,
+            cwd: cwd.to_string(),
+            hostname: String::from("laptop"),
+            ..history("make", -3600, None)
+        };
+
+        let mut app = state("");
+        app.failed_only = true;
+        app.open_form();
+        let mut form = app.form.take().unwrap();
+        assert_eq!(form, FilterForm::default());
+
+        *form.value_mut(FormField::Cwd) = String::from("/src");
+        *form.value_mut(FormField::Exit) = String::from("2");
+        *form.value_mut(FormField::Host) = String::from("laptop");
+        *form.value_mut(FormField::Before) = String::from("now");
+        *form.value_mut(FormField::After) = String::from("1 day ago");
+        app.apply_form(&form).unwrap();
+        assert_eq!(
+            app.active_filters(),
+            vec![
+                "host: laptop",
+                "cwd: /src",
+                "exit: 2",
+                "before: now",
+                "after: 1 day ago",
+                "failed"
+            ]
+        );
+        assert!(app.matches(&history("/src", 2)));
+
+        // The database applies these, before the results are limited
+        let filters = app.sql_filters(&[]);
+        assert_eq!(filters.exit, Some(2));
+        assert_eq!(filters.cwd.as_deref(), Some("/src"));
+        assert_eq!(filters.host.as_deref(), Some("laptop"));
+        assert_eq!(filters.exclude_exit, Some(0));
+        assert!(filters.finished);
+        assert!(filters.before.is_some());
+        assert!(filters.after.is_some());
+
+        // Opening it again starts from what is applied
+        app.open_form();
+        assert_eq!(app.form.as_ref(), Some(&form));
+
+        // A mistake leaves the filters alone, and emptying fields clears them
+        *form.value_mut(FormField::Exit) = String::from("two");
+        assert!(app.apply_form(&form).is_err());
+        assert_eq!(app.exit_filter, Some(2));
+
+        form.values = Default::default();
+        app.apply_form(&form).unwrap();
+        assert_eq!(app.active_filters(), vec!["failed"]);
+    }
+
+    #[test]
+    fn accept_confirms_dangerous() {
+        let mut app = state("");
+        app.dangerous = vec![Regex::new(r"^rm -r?f").unwrap()];
+
+        assert_eq!(
+            app.accept("ls".to_string()),
+            Some(Selected::One("ls".to_string()))
+        );
+        assert!(app.confirm.is_none());
+
+        assert_eq!(app.accept("rm -rf /tmp/x".to_string()), None);
+        assert_eq!(
+            app.confirm,
+            Some(Selected::One("rm -rf /tmp/x".to_string()))
+        );
+    }
+
+    #[test]
+    fn marked_in_order() {
+        let now = chrono::Utc::now();
+        let history = |command: &str, ago: i64| History {
+            timestamp: now - chrono::Duration::minutes(ago),
+            cwd: String::from("/tmp"),
+            ..history(command, 0, None)
+        };
+
+        let mut app = state("");
+        app.dangerous = vec![Regex::new(r"^rm -r?f").unwrap()];
+        app.results = vec![
+            history("make", 1),
+            history("rm -rf out", 3),
+            history("cd src", 5),
+        ];
+
+        for i in [0, 2] {
+            app.results_state.select(Some(i));
+            app.toggle_marked();
+        }
+        assert_eq!(app.marked_commands(), vec!["cd src", "make"]);
+        assert_eq!(
+            app.accept_marked(),
+            Some(Selected::Marked(vec![
+                String::from("cd src"),
+                String::from("make")
+            ]))
+        );
+
+        app.results_state.select(Some(1));
+        app.toggle_marked();
+        assert_eq!(app.accept_marked(), None);
+        assert_eq!(
+            app.confirm.as_ref().map(Selected::commands),
+            Some(&["cd src", "rm -rf out", "make"].map(String::from)[..])
+        );
+
+        // Marking again unmarks
+        app.toggle_marked();
+        assert_eq!(app.marked_commands(), vec!["cd src", "make"]);
+    }
+
+    #[test]
+    fn run_action() {
+        let mut app = state("");
+        app.results = vec![History {
+            duration: -1,
+            cwd: String::from("/home/me/it's here"),
+            ..history("make", 0, None)
+        }];
+
+        // Nothing is selected yet
+        assert_eq!(app.run_action(Action::CopyCommand), None);
+        assert!(app.clipboard.is_none());
+
+        app.results_state.select(Some(0));
+
+        assert_eq!(app.run_action(Action::CopyCommand), None);
+        assert_eq!(app.clipboard.take(), Some(String::from("make")));
+
+        assert_eq!(app.run_action(Action::CopyCwd), None);
+        assert_eq!(
+            app.clipboard.take(),
+            Some(String::from("/home/me/it's here"))
+        );
+
+        assert_eq!(
+            app.run_action(Action::Cd),
+            Some(String::from(r"cd '/home/me/it'\''s here'"))
+        );
+        assert!(app.clipboard.is_none());
+    }
+
+    #[test]
+    fn copy_share() {
+        use chrono::TimeZone;
+
+        let mut app = state("");
+        app.share_format = String::from("# {time} in {cwd}, exited {exit}\n{command}");
+        app.time_format = String::from("%Y-%m-%d %H:%M");
+        app.results = vec![History {
+            timestamp: chrono::Utc.ymd(2022, 10, 1).and_hms(9, 30, 0),
+            duration: -1,
+            exit: 130,
+            cwd: String::from("/srv/app"),
+            ..history("  make deploy ", 0, None)
+        }];
+        app.results_state.select(Some(0));
+
+        assert_eq!(app.run_action(Action::CopyShare), None);
+        assert_eq!(
+            app.clipboard.take().as_deref(),
+            Some("# 2022-10-01 09:30 in /srv/app, exited SIGINT\nmake deploy")
+        );
+    }
+
+    #[test]
+    fn fill_prompt_encodes_for_fish() {
+        let command = "cat <<EOF\nlet's go +1 ✓\nEOF";
+
+        assert_eq!(fill_prompt(command, Some(Shell::Bash)), command);
+        assert_eq!(fill_prompt(command, Some(Shell::Zsh)), command);
+        assert_eq!(fill_prompt(command, None), command);
+        assert_eq!(
+            fill_prompt(command, Some(Shell::Fish)),
+            "cat%20%3C%3CEOF%0Alet%27s%20go%20%2B1%20%E2%9C%93%0AEOF"
+        );
+        assert_eq!(
+            fill_prompt("ls ~/src/a-b_c.rs", Some(Shell::Fish)),
+            "ls%20~/src/a-b_c.rs"
+        );
+    }
+
+    #[test]
+    fn split_tags_from_query() {
+        assert_eq!(split_tags("git push"), (String::from("git push"), vec![]));
+        assert_eq!(
+            split_tags("tag:deploy make tag:prod"),
+            (String::from("make"), vec!["deploy", "prod"])
+        );
+        assert_eq!(split_tags("tag: ls"), (String::from("tag: ls"), vec![]));
+    }
+
+    #[test]
+    fn pasted_lines_become_spaces() {
+        assert_eq!(pasted_query("git log\n"), "git log");
+        assert_eq!(pasted_query("a\nb\tc"), "a b c");
+    }
+
+    #[test]
+    fn shell_quote_words() {
+        assert_eq!(shell_quote("/tmp"), "'/tmp'");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parses_btime() {
+        let stat = "cpu  1 2 3\nintr 4\nbtime 1665000000\nprocesses 5\n";
+        assert_eq!(super::parse_btime(stat), Some(1_665_000_000));
+        assert_eq!(super::parse_btime("cpu  1 2 3\n"), None);
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn parses_boottime() {
+        let boottime = "{ sec = 1665000000, usec = 123 } Wed Oct  5 20:00:00 2022\n";
+        assert_eq!(super::parse_boottime(boottime), Some(1_665_000_000));
+        assert_eq!(super::parse_boottime("garbage"), None);
+    }
+
+    #[test]
+    fn watch_is_not_interactive() {
+        assert!(Cmd::try_parse_from(["search", "--watch", "git"]).is_ok());
+        assert!(Cmd::try_parse_from(["search", "--watch", "--interactive"]).is_err());
+    }
+
+    #[test]
+    fn invert_filters() {
+        let history = |exit| History {
+            duration: -1,
+            exit,
+            ..history("make", 0, None)
+        };
+
+        let cmd = Cmd::parse_from(["search", "--exit", "0"]);
+        assert!(cmd.keep(&history(0)));
+        assert!(!cmd.keep(&history(1)));
+        assert!(!cmd.keep(&history(-1)));
+
+        let cmd = Cmd::parse_from(["search", "--exit", "0", "--invert"]);
+        assert!(!cmd.keep(&history(0)));
+        assert!(cmd.keep(&history(1)));
+        assert!(cmd.keep(&history(-1)));
+
+        // With no filters everything matches, so inverting hides everything
+        let cmd = Cmd::parse_from(["search", "-v", "make"]);
+        assert!(!cmd.keep(&history(1)));
+    }
+
+    #[test]
+    fn only_cd() {
+        let history = |command: &str, session: &str| History {
+            duration: -1,
+            ..history(command, 0, Some(session))
+        };
+
+        let mut cmd = Cmd::parse_from(["search", "--only-cd"]);
+        cmd.cd_commands = vec![String::from("cd"), String::from("pushd")];
+
+        assert!(cmd.keep(&history("cd /tmp", "a")));
+        assert!(cmd.keep(&history("pushd ..", "a")));
+        assert!(!cmd.keep(&history("popd", "a")));
+        assert!(!cmd.keep(&history("cdk deploy", "a")));
+        assert!(!cmd.keep(&history("echo cd", "a")));
+
+        cmd.session_id = Some(String::from("a"));
+        assert!(cmd.keep(&history("cd /tmp", "a")));
+        assert!(!cmd.keep(&history("cd /tmp", "b")));
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(super::arguments("git push --force"), "push --force");
+        assert_eq!(super::arguments("  ls\t-la "), "-la ");
+        assert_eq!(super::arguments("ls"), "");
+
+        let cmd = Cmd::parse_from(["search", "--arg", "--force", "--arg", "^push"]);
+        let history = |command: &str| History {
+            duration: -1,
+            ..history(command, 0, None)
+        };
+
+        assert!(cmd.keep(&history("git push --force origin")));
+        assert!(!cmd.keep(&history("git push origin")));
+        assert!(!cmd.keep(&history("rm --force push")));
+
+        // The program's name is never matched
+        let cmd = Cmd::parse_from(["search", "--arg", "git"]);
+        assert!(!cmd.keep(&history("git status")));
+        assert!(cmd.keep(&history("sudo git status")));
+    }
+
+    #[test]
+    fn pipelines() {
+        for command in [
+            "ps aux | grep atuin",
+            "make 2>&1|tee log",
+            "echo hi > out.txt",
+            "cat >> notes",
+            "wc -l < big.csv",
+            "diff <(ls a) <(ls b)",
+            "echo 'a|b' | sort",
+        ] {
+            assert!(super::is_pipeline(command), "{}", command);
+        }
+
+        for command in [
+            "ls -la",
+            "echo \"a|b\"",
+            "echo 'x > y'",
+            r"echo a\|b",
+            "make || make clean",
+            "grep \"it's <here>\" log",
+            "ls # | head",
+        ] {
+            assert!(!super::is_pipeline(command), "{}", command);
+        }
+
+        let cmd = Cmd::parse_from(["search", "--pipelines"]);
+        let history = |command: &str| History {
+            duration: -1,
+            ..history(command, 0, None)
+        };
+
+        assert!(cmd.keep(&history("journalctl -u atuin | less")));
+        assert!(!cmd.keep(&history("journalctl -u atuin")));
+    }
+
+    #[test]
+    fn explain_filters() {
+        let history = |command: &str, exit: i64, duration: i64| History {
+            duration,
+            exit,
+            ..history(command, 0, None)
+        };
+
+        let results = vec![
+            history("make", 0, 1),
+            history("make test", 1, 1),
+            history("make docs", 1, 1),
+            history("ls", 0, 1),
+            history("make run", 0, -1),
+        ];
+
+        let cmd = Cmd::parse_from(["search", "--exit", "0"]);
+        let (kept, removed) = cmd.apply_filters(results.clone(), 3, true, None);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].command, "make");
+        // ls has exit 0, so it counts against the length rather than the exit
+        assert_eq!(
+            removed,
+            vec![("exit", 2), ("min-length", 1), ("has-duration", 1)]
+        );
+
+        let tagged: HashSet<String> = std::iter::once(String::from("make test")).collect();
+        let cmd = Cmd::parse_from(["search", "--invert", "--exit", "0"]);
+        let (kept, removed) = cmd.apply_filters(results, 0, false, Some(&tagged));
+        assert_eq!(kept.len(), 1);
+        assert_eq!(removed, vec![("invert", 3), ("tag", 1)]);
+    }
+
+    #[test]
+    fn frequency_tiebreak() {
+        let counts: HashMap<String, i64> = [("make", 2), ("cargo", 5), ("apt", 2), ("ls", 2)]
+            .iter()
+            .map(|(c, n)| (String::from(*c), *n))
+            .collect();
+
+        let now = chrono::Utc::now();
+        let last_ran: HashMap<String, chrono::DateTime<chrono::Utc>> =
+            [("make", 1), ("cargo", 9), ("apt", 5), ("ls", 1)]
+                .iter()
+                .map(|(c, ago)| (String::from(*c), now - chrono::Duration::minutes(*ago)))
+                .collect();
+
+        let ranked = |tiebreak| -> Vec<String> {
+            rank_by_frequency(&counts, &last_ran, tiebreak)
+                .into_iter()
+                .map(|(c, _)| c.clone())
+                .collect()
+        };
+
+        // make and ls were last ran together, so fall back to the command
+        assert_eq!(
+            ranked(Tiebreak::Recency),
+            vec!["cargo", "ls", "make", "apt"]
+        );
+        assert_eq!(
+            ranked(Tiebreak::Alphabetical),
+            vec!["cargo", "apt", "ls", "make"]
+        );
+    }
+
+    #[test]
+    fn redact_aggregate_time() {
+        let history = || {
+            vec![
+                history("curl -H 'Authorization: Bearer abc' example.com", 0, None),
+                history("curl -H 'Authorization: Bearer xyz' example.com", 1, None),
+            ]
+        };
+
+        let cmd = Cmd::parse_from(["search", "--aggregate-time", "command"]);
+        let mut unredacted = history();
+        cmd.redact(&[], &mut unredacted).unwrap();
+        assert_eq!(aggregate_durations(Group::Command, &unredacted).len(), 2);
+
+        // Redacted before totalling up, so the secrets aren't printed and runs
+        // differing only by them are counted together
+        let cmd = Cmd::parse_from(["search", "--redact", "--aggregate-time", "command"]);
+        let mut redacted = history();
+        cmd.redact(&[], &mut redacted).unwrap();
+        assert_eq!(
+            aggregate_durations(Group::Command, &redacted),
+            vec![(
+                String::from("curl -H 'Authorization: Bearer <token>' example.com"),
+                2
+            )]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn aggregate_time_window() {
+        let mut db = Sqlite::new("sqlite::memory:").await.unwrap();
+        for &(days, duration) in &[(0, 10), (1, 20), (5, 400)] {
+            let history = History {
+                timestamp: chrono::Utc::now() - chrono::Duration::days(days),
+                duration,
+                ..history("make", 0, None)
+            };
+            db.save(&history).await.unwrap();
+        }
+
+        let cmd = Cmd::parse_from([
+            "search",
+            "--aggregate-time",
+            "command",
+            "--after",
+            "3 days ago",
+        ]);
+        let (start, end) = cmd.window().unwrap();
+        let history = db.range(start, end).await.unwrap();
+
+        assert_eq!(
+            aggregate_durations(Group::Command, &history),
+            vec![(String::from("make"), 30)]
+        );
+    }
+
+    #[test]
+    fn matching_lines() {
+        let command = "cat <<EOF > config\n  listen = 8080\n  Host = example.com\nEOF";
+
+        assert_eq!(
+            matching_line(command, "listen", SearchMode::Prefix),
+            Some((2, String::from("  listen = 8080")))
+        );
+        assert_eq!(
+            matching_line(command, "HOST =", SearchMode::Prefix),
+            Some((3, String::from("  Host = example.com")))
+        );
+        assert_eq!(matching_line(command, "8080", SearchMode::Prefix), None);
+        assert_eq!(
+            matching_line(command, "8080", SearchMode::FullText),
+            Some((2, String::from("  listen = 8080")))
+        );
+        assert_eq!(
+            matching_line(command, "example host", SearchMode::Fuzzy),
+            Some((3, String::from("  Host = example.com")))
+        );
+        assert_eq!(
+            matching_line(command, "listen example", SearchMode::Fuzzy),
+            None
+        );
+        assert_eq!(
+            matching_line(command, "^ +Host", SearchMode::Regex),
+            Some((3, String::from("  Host = example.com")))
+        );
+        assert_eq!(matching_line(command, "^ +host", SearchMode::Regex), None);
+        assert_eq!(matching_line(command, "(", SearchMode::Regex), None);
+
+        assert_eq!(
+            super::inner_match(command, "8080", SearchMode::FullText),
+            Some((2, String::from("  listen = 8080")))
+        );
+        assert_eq!(super::inner_match(command, "cat", SearchMode::Prefix), None);
+        assert_eq!(
+            super::inner_match("cat config", "cat", SearchMode::Prefix),
+            None
+        );
+    }
+
+    #[test]
+    fn unseen() {
+        let start = chrono::Utc::now();
+        let lookback = chrono::Duration::from_std(super::WATCH_LOOKBACK).unwrap();
+        let history = |command: &str, secs: i64| History {
+            timestamp: start + chrono::Duration::seconds(secs),
+            ..history(command, 0, None)
+        };
+        let commands = |history: Vec<History>| -> Vec<String> {
+            history.into_iter().map(|h| h.command).collect()
+        };
+        let mut seen = HashMap::new();
+
+        let (old, make, vim) = (history("old", -1), history("make", 1), history("vim", 2));
+
+        // Commands from before --watch started aren't printed
+        let found = super::unseen(vec![old, make.clone()], start, start - lookback, &mut seen);
+        assert_eq!(commands(found), vec!["make"]);
+
+        // The next fetch looks back over make again, which isn't printed
+        // twice. A command saved late, after the last fetch, still is
+        let late = history("late", 1);
+        let from = start + chrono::Duration::seconds(2) - lookback;
+        let found = super::unseen(
+            vec![make.clone(), late, vim.clone()],
+            start,
+            from,
+            &mut seen,
+        );
+        assert_eq!(commands(found), vec!["late", "vim"]);
+
+        // Once a run is older than the lookback it's forgotten
+        let from = start + chrono::Duration::seconds(2);
+        let found = super::unseen(vec![vim], start, from, &mut seen);
+        assert!(found.is_empty());
+        assert!(!seen.contains_key(&make.id));
+        assert_eq!(seen.len(), 1);
+    }
+
+    #[test]
+    fn parse_sort() {
+        assert!(matches!(
+            "time".parse::<Sort>(),
+            Ok(Sort {
+                key: SortKey::Time,
+                descending: false
+            })
+        ));
+        assert!(matches!(
+            "duration:asc".parse::<Sort>(),
+            Ok(Sort {
+                key: SortKey::Duration,
+                descending: false
+            })
+        ));
+        assert!(matches!(
+            "exit:desc".parse::<Sort>(),
+            Ok(Sort {
+                key: SortKey::Exit,
+                descending: true
+            })
+        ));
+        assert!(matches!(
+            "command".parse::<Sort>(),
+            Ok(Sort {
+                key: SortKey::Command,
+                descending: false
+            })
+        ));
+
+        assert!("size".parse::<Sort>().is_err());
+        assert!("time:up".parse::<Sort>().is_err());
+        assert!("time:".parse::<Sort>().is_err());
+        assert!("".parse::<Sort>().is_err());
+    }
+
+    #[test]
+    fn apply_sort() {
+        let mut results = vec![
+            History {
+                duration: 300,
+                exit: 1,
+                ..history("make", -30, None)
+            },
+            History {
+                duration: 100,
+                exit: 0,
+                ..history("cargo test", -10, None)
+            },
+            History {
+                duration: 200,
+                exit: 2,
+                ..history("ls", -20, None)
+            },
+        ];
+        let mut sorted = |sort: &str| -> Vec<String> {
+            sort.parse::<Sort>().unwrap().apply(&mut results);
+            results.iter().map(|h| h.command.clone()).collect()
+        };
+
+        assert_eq!(sorted("time"), vec!["make", "ls", "cargo test"]);
+        assert_eq!(sorted("time:desc"), vec!["cargo test", "ls", "make"]);
+        assert_eq!(sorted("duration"), vec!["cargo test", "ls", "make"]);
+        assert_eq!(sorted("duration:desc"), vec!["make", "ls", "cargo test"]);
+        assert_eq!(sorted("exit"), vec!["cargo test", "make", "ls"]);
+        assert_eq!(sorted("command:desc"), vec!["make", "ls", "cargo test"]);
+        assert_eq!(sorted("command:asc"), vec!["cargo test", "ls", "make"]);
+    }
+
+    #[test]
+    fn collapse_repeats() {
+        use chrono::TimeZone;
+
+        let history = |second: u32, command: &str, exit: i64| History {
+            timestamp: chrono::Utc.ymd(2022, 10, 1).and_hms(9, 0, second),
+            duration: 1_000_000_000,
+            exit,
+            ..history(command, 0, None)
+        };
+
+        let collapsed = super::collapse_repeats(vec![
+            history(0, "make", 0),
+            history(10, "kubectl get pods", 1),
+            history(20, "kubectl get pods", 1),
+            history(40, "kubectl get pods ", 0),
+            history(50, "make", 0),
+        ]);
+
+        let commands: Vec<_> = collapsed
+            .iter()
+            .map(|h| (h.command.as_str(), h.exit))
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                ("make", 0),
+                ("kubectl get pods  # ran 3 times over 31s", 0),
+                ("make", 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn nth_command() {
+        let history = |command: &str, duration: i64| History {
+            duration,
+            ..history(command, 0, None)
+        };
+        let session = || {
+            vec![
+                history("cd src", 1),
+                history("make", 1),
+                history("make test", 1),
+                history("atuin search --session --nth -1", -1),
+            ]
+        };
+        let nth = |n| super::nth_command(session(), n).map(|h| h.command);
+
+        assert_eq!(nth(-1).unwrap(), "make test");
+        assert_eq!(nth(-3).unwrap(), "cd src");
+        assert_eq!(nth(1).unwrap(), "cd src");
+        assert_eq!(nth(3).unwrap(), "make test");
+        assert!(nth(-4).is_err());
+        assert!(nth(4).is_err());
+        assert!(nth(0).is_err());
+        assert!(nth(i64::MIN).is_err());
+    }
+
+    #[test]
+    fn host_filter() {
+        let history = [History {
+            hostname: String::from("prod-1:deploy"),
+            ..history("make deploy", 0, None)
+        }];
+
+        let cmd = Cmd::parse_from(["search", "--host", "prod-1"]);
+        assert!(cmd.keep(&history[0]));
+        let cmd = Cmd::parse_from(["search", "--host", "prod-1:deploy"]);
+        assert!(cmd.keep(&history[0]));
+        let cmd = Cmd::parse_from(["search", "--host", "prod-2"]);
+        assert!(!cmd.keep(&history[0]));
+
+        let cmd = Cmd::parse_from(["search", "--exclude-host", "prod-1"]);
+        assert_eq!(cmd.failed_filter(&history[0]), Some("exclude-host"));
+        let cmd = Cmd::parse_from(["search", "--exclude-host", "prod-2"]);
+        assert!(cmd.keep(&history[0]));
+
+        // As given with --host and --interactive
+        let mut app = state("");
+        app.host_filter = Some(String::from("prod-1"));
+        assert_eq!(app.sql_filters(&[]).host.as_deref(), Some("prod-1"));
+        assert_eq!(app.active_filters(), vec!["host: prod-1"]);
+    }
+
+    #[test]
+    fn sample_days() {
+        use chrono::TimeZone;
+
+        let history = |day: u32, hour: u32, command: &str| History {
+            timestamp: chrono::Local
+                .ymd(2022, 10, day)
+                .and_hms(hour, 0, 0)
+                .with_timezone(&chrono::Utc),
+            ..history(command, 0, None)
+        };
+        let history = vec![
+            history(3, 9, "git pull"),
+            history(3, 17, "git push"),
+            history(4, 10, "make"),
+            history(6, 8, "vim notes"),
+            history(6, 18, "shutdown"),
+        ];
+        let commands = |history: Vec<History>| -> Vec<String> {
+            history.into_iter().map(|h| h.command).collect()
+        };
+
+        assert_eq!(
+            commands(super::sample_days(history.clone(), false)),
+            vec!["git pull", "make", "vim notes"]
+        );
+        assert_eq!(
+            commands(super::sample_days(history, true)),
+            vec!["git push", "make", "shutdown"]
+        );
+    }
+
+    #[test]
+    fn cwd_subdirs() {
+        assert!(in_dir("/src/atuin", "/src/atuin", false));
+        assert!(!in_dir("/src/atuin/docs", "/src/atuin", false));
+
+        assert!(in_dir("/src/atuin", "/src/atuin", true));
+        assert!(in_dir("/src/atuin/docs", "/src/atuin", true));
+        assert!(in_dir("/src/atuin/docs", "/src/atuin/", true));
+        assert!(in_dir("/src", "/", true));
+        assert!(!in_dir("/src/atuin-server", "/src/atuin", true));
+        assert!(!in_dir("/src", "/src/atuin", true));
+
+        let h = History {
+            cwd: String::from("/src/atuin/docs"),
+            ..history("ls", 0, None)
+        };
+
+        let mut cmd = Cmd::parse_from(["search", "--cwd", "/src/atuin"]);
+        assert!(!cmd.keep(&h));
+        cmd.subdirs = true;
+        assert!(cmd.keep(&h));
+    }
+
+    #[test]
+    fn sql_filters() {
+        let mut cmd = Cmd::parse_from([
+            "search",
+            "--cwd",
+            "/src",
+            "--exit",
+            "1",
+            "--host",
+            "laptop",
+            "--pipelines",
+        ]);
+        cmd.subdirs = true;
+        assert_eq!(
+            cmd.sql_filters(),
+            Filters {
+                exit: Some(1),
+                cwd: Some(String::from("/src")),
+                subdirs: true,
+                host: Some(String::from("laptop")),
+                ..Filters::default()
+            }
+        );
+
+        // These need every filter checked by hand
+        for flag in ["--invert", "--explain-filters"] {
+            let cmd = Cmd::parse_from(["search", "--exit", "1", flag]);
+            assert_eq!(cmd.sql_filters(), Filters::default());
+        }
+    }
+
+    #[test]
+    fn display_command_trims() {
+        assert_eq!(display_command("   git status ", true), "git status");
+        assert_eq!(display_command("   git status ", false), "   git status ");
+        assert_eq!(display_command(" echo a\necho b", true), "echo a echo b");
+    }
 }
@@ -0,0 +1,196 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use tui::style::{Color, Modifier, Style};
+
+const ESC: char = '\u{1b}';
+const BEL: char = '\u{7}';
+
+/// Split a string into runs of text, along with the style set by any ANSI SGR
+/// escape sequences before them. All other escape sequences are dropped, so
+/// the text is always safe to render.
+pub fn parse(s: &str) -> Vec<(String, Style)> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut style = Style::default();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != ESC {
+            text.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            // Control Sequence Introducer, eg ESC[1;31m
+            Some('[') => {
+                let mut params = String::new();
+                let mut fin = None;
+
+                for c in chars.by_ref() {
+                    if ('\u{40}'..='\u{7e}').contains(&c) {
+                        fin = Some(c);
+                        break;
+                    }
+                    params.push(c);
+                }
+
+                if fin == Some('m') {
+                    if !text.is_empty() {
+                        segments.push((std::mem::take(&mut text), style));
+                    }
+                    style = sgr(style, &params);
+                }
+            }
+            // Operating System Command, eg setting the window title
+            Some(']') => skip_osc(&mut chars),
+            // Any other escape is a single character
+            _ => {}
+        }
+    }
+
+    if !text.is_empty() {
+        segments.push((text, style));
+    }
+
+    segments
+}
+
+fn skip_osc(chars: &mut Peekable<Chars>) {
+    while let Some(c) = chars.next() {
+        if c == BEL {
+            return;
+        }
+        if c == ESC && chars.peek() == Some(&'\\') {
+            chars.next();
+            return;
+        }
+    }
+}
+
+fn sgr(mut style: Style, params: &str) -> Style {
+    let mut codes = params
+        .split(';')
+        .map(|p| if p.is_empty() { Ok(0) } else { p.parse::<u8>() });
+
+    while let Some(Ok(code)) = codes.next() {
+        style = match code {
+            0 => Style::default(),
+            1 => style.add_modifier(Modifier::BOLD),
+            2 => style.add_modifier(Modifier::DIM),
+            3 => style.add_modifier(Modifier::ITALIC),
+            4 => style.add_modifier(Modifier::UNDERLINED),
+            5 => style.add_modifier(Modifier::SLOW_BLINK),
+            7 => style.add_modifier(Modifier::REVERSED),
+            9 => style.add_modifier(Modifier::CROSSED_OUT),
+            22 => style.remove_modifier(Modifier::BOLD | Modifier::DIM),
+            23 => style.remove_modifier(Modifier::ITALIC),
+            24 => style.remove_modifier(Modifier::UNDERLINED),
+            25 => style.remove_modifier(Modifier::SLOW_BLINK),
+            27 => style.remove_modifier(Modifier::REVERSED),
+            29 => style.remove_modifier(Modifier::CROSSED_OUT),
+            30..=37 => style.fg(color(code - 30)),
+            38 => match extended(&mut codes) {
+                Some(c) => style.fg(c),
+                None => break,
+            },
+            39 => Style { fg: None, ..style },
+            40..=47 => style.bg(color(code - 40)),
+            48 => match extended(&mut codes) {
+                Some(c) => style.bg(c),
+                None => break,
+            },
+            49 => Style { bg: None, ..style },
+            90..=97 => style.fg(color(code - 90 + 8)),
+            100..=107 => style.bg(color(code - 100 + 8)),
+            _ => style,
+        }
+    }
+
+    style
+}
+
+/// Parse the 256 colour (5;n) or truecolour (2;r;g;b) form of SGR 38 and 48
+fn extended<E>(codes: &mut impl Iterator<Item = Result<u8, E>>) -> Option<Color> {
+    match codes.next()?.ok()? {
+        5 => Some(Color::Indexed(codes.next()?.ok()?)),
+        2 => Some(Color::Rgb(
+            codes.next()?.ok()?,
+            codes.next()?.ok()?,
+            codes.next()?.ok()?,
+        )),
+        _ => None,
+    }
+}
+
+const fn color(i: u8) -> Color {
+    match i {
+        0 => Color::Black,
+        1 => Color::Red,
+        2 => Color::Green,
+        3 => Color::Yellow,
+        4 => Color::Blue,
+        5 => Color::Magenta,
+        6 => Color::Cyan,
+        7 => Color::Gray,
+        8 => Color::DarkGray,
+        9 => Color::LightRed,
+        10 => Color::LightGreen,
+        11 => Color::LightYellow,
+        12 => Color::LightBlue,
+        13 => Color::LightMagenta,
+        14 => Color::LightCyan,
+        _ => Color::White,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tui::style::{Color, Modifier, Style};
+
+    use super::parse;
+
+    fn strip(s: &str) -> String {
+        parse(s).into_iter().map(|(text, _)| text).collect()
+    }
+
+    #[test]
+    fn strip_escapes() {
+        assert_eq!(strip("ls"), "ls");
+        assert_eq!(strip("echo \u{1b}[1;31mred\u{1b}[0m"), "echo red");
+        assert_eq!(strip("\u{1b}]0;title\u{7}printf a"), "printf a");
+        assert_eq!(strip("a\u{1b}]8;;http://x\u{1b}\\b"), "ab");
+        assert_eq!(strip("\u{1b}[2Jclear\u{1b}"), "clear");
+    }
+
+    #[test]
+    fn parse_sgr() {
+        assert_eq!(
+            parse("echo \u{1b}[1;31mred\u{1b}[39m bold\u{1b}[m"),
+            vec![
+                ("echo ".to_string(), Style::default()),
+                (
+                    "red".to_string(),
+                    Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+                ),
+                (
+                    " bold".to_string(),
+                    Style::default().add_modifier(Modifier::BOLD)
+                ),
+            ]
+        );
+
+        assert_eq!(
+            parse("\u{1b}[38;5;208mx\u{1b}[48;2;1;2;3my"),
+            vec![
+                ("x".to_string(), Style::default().fg(Color::Indexed(208))),
+                (
+                    "y".to_string(),
+                    Style::default()
+                        .fg(Color::Indexed(208))
+                        .bg(Color::Rgb(1, 2, 3))
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,538 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::Write;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Local, Timelike, Utc};
+use eyre::Result;
+use itertools::Itertools;
+use tabwriter::TabWriter;
+
+use atuin_client::history::History;
+
+use super::display_command;
+
+/// How the commands used in two windows of time differ
+#[derive(Debug, PartialEq, Eq)]
+pub struct CommandDiff<'a> {
+    pub added: Vec<&'a String>,
+    pub removed: Vec<&'a String>,
+    pub common: Vec<&'a String>,
+}
+
+/// The runs from before a time, and those from then on, so each run is on
+/// exactly one side
+pub fn split_runs(history: Vec<History>, split: DateTime<Utc>) -> (Vec<History>, Vec<History>) {
+    history.into_iter().partition(|h| h.timestamp < split)
+}
+
+pub fn diff_commands<'a>(old: &'a BTreeSet<String>, new: &'a BTreeSet<String>) -> CommandDiff<'a> {
+    CommandDiff {
+        added: new.difference(old).collect(),
+        removed: old.difference(new).collect(),
+        common: old.intersection(new).collect(),
+    }
+}
+
+/// The distinct commands ran in each directory
+pub fn group_by_cwd(history: &[History]) -> BTreeMap<&str, BTreeSet<&str>> {
+    let mut dirs: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+
+    for h in history {
+        dirs.entry(h.cwd.as_str())
+            .or_default()
+            .insert(h.command.as_str());
+    }
+
+    dirs
+}
+
+/// Each directory from `group_by_cwd` on a line of its own, followed by its
+/// commands indented beneath it, and a blank line between directories
+pub fn render_cwd_commands(dirs: &BTreeMap<&str, BTreeSet<&str>>, trim: bool) -> String {
+    dirs.iter()
+        .map(|(cwd, commands)| {
+            let commands: Vec<String> = commands
+                .iter()
+                .map(|c| format!("  {}\n", display_command(c, trim)))
+                .collect();
+
+            format!("{}\n{}", cwd, commands.concat())
+        })
+        .join("\n")
+}
+
+/// What --group-by sums up activity for
+#[derive(Debug, Clone, Copy)]
+pub enum GroupBy {
+    Host,
+    Cwd,
+}
+
+impl FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "host" => Ok(Self::Host),
+            "cwd" => Ok(Self::Cwd),
+            _ => Err(format!("invalid grouping {:?}, expected host or cwd", s)),
+        }
+    }
+}
+
+/// How much was ran on one host or in one directory, for --group-by
+#[derive(Debug, PartialEq)]
+pub struct GroupSummary<'a> {
+    pub key: &'a str,
+    pub runs: usize,
+    pub distinct: usize,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Sum up the runs on each host, or in each directory. The busiest come
+/// first, and ties are ordered by name
+pub fn summarize_groups(history: &[History], by: GroupBy) -> Vec<GroupSummary<'_>> {
+    let mut groups: HashMap<&str, (usize, HashSet<&str>, DateTime<Utc>)> = HashMap::new();
+
+    for h in history {
+        let key = match by {
+            // The hostname is recorded as host:user, and it's the machines
+            // that are being compared
+            GroupBy::Host => h.hostname.split(':').next().unwrap_or_default(),
+            GroupBy::Cwd => h.cwd.as_str(),
+        };
+
+        let (runs, commands, last_seen) = groups
+            .entry(key)
+            .or_insert_with(|| (0, HashSet::new(), h.timestamp));
+        *runs += 1;
+        commands.insert(h.command.as_str());
+        *last_seen = std::cmp::max(*last_seen, h.timestamp);
+    }
+
+    groups
+        .into_iter()
+        .map(|(key, (runs, commands, last_seen))| GroupSummary {
+            key,
+            runs,
+            distinct: commands.len(),
+            last_seen,
+        })
+        .sorted_by(|a, b| b.runs.cmp(&a.runs).then(a.key.cmp(b.key)))
+        .collect()
+}
+
+/// The groups from `summarize_groups` as a table, with a heading, as the
+/// numbers mean little on their own
+pub fn render_groups(groups: &[GroupSummary], by: GroupBy, time_format: &str) -> Result<String> {
+    let mut writer = TabWriter::new(Vec::new()).padding(2);
+
+    let heading = match by {
+        GroupBy::Host => "HOST",
+        GroupBy::Cwd => "DIRECTORY",
+    };
+    writeln!(writer, "{}\tRUNS\tDISTINCT\tLAST SEEN", heading)?;
+
+    for g in groups {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            g.key,
+            g.runs,
+            g.distinct,
+            g.last_seen.format(time_format)
+        )?;
+    }
+
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// How many extra copies of a command one session has, for --find-duplicates
+#[derive(Debug, PartialEq, Eq)]
+pub struct DuplicateSummary<'a> {
+    pub command: &'a str,
+    pub session: &'a str,
+    pub copies: usize,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// The copies from `Database::duplicates` counted up by session and command,
+/// most copies first
+pub fn summarize_duplicates(duplicates: &[History]) -> Vec<DuplicateSummary<'_>> {
+    let mut summaries: HashMap<(&str, &str), DuplicateSummary> = HashMap::new();
+
+    for h in duplicates {
+        let summary = summaries
+            .entry((h.session.as_str(), h.command.as_str()))
+            .or_insert(DuplicateSummary {
+                command: &h.command,
+                session: &h.session,
+                copies: 0,
+                last_seen: h.timestamp,
+            });
+        summary.copies += 1;
+        summary.last_seen = std::cmp::max(summary.last_seen, h.timestamp);
+    }
+
+    summaries
+        .into_values()
+        .sorted_by(|a, b| {
+            b.copies
+                .cmp(&a.copies)
+                .then_with(|| a.command.cmp(b.command))
+                .then_with(|| a.session.cmp(b.session))
+        })
+        .collect()
+}
+
+/// The counts from `summarize_duplicates` as a table, with a heading
+pub fn render_duplicates(summaries: &[DuplicateSummary], time_format: &str) -> Result<String> {
+    let mut writer = TabWriter::new(Vec::new()).padding(2);
+    writeln!(writer, "COPIES\tLAST SEEN\tSESSION\tCOMMAND")?;
+
+    for s in summaries {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            s.copies,
+            s.last_seen.format(time_format),
+            s.session,
+            display_command(s.command, true)
+        )?;
+    }
+
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// How many commands ran in each hour of each day of the week, in local time.
+/// Monday comes first
+pub fn bucket_times(history: &[History]) -> [[usize; 24]; 7] {
+    let mut grid = [[0; 24]; 7];
+
+    for h in history {
+        let time = h.timestamp.with_timezone(&Local);
+        grid[time.weekday().num_days_from_monday() as usize][time.hour() as usize] += 1;
+    }
+
+    grid
+}
+
+/// Draw the grid from `bucket_times` with characters getting denser as more
+/// commands ran, with the total for each day at the end of its row
+pub fn render_heatmap(grid: &[[usize; 24]; 7]) -> String {
+    const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+    const SHADES: [char; 9] = ['.', ':', '-', '=', '+', '*', '#', '%', '@'];
+
+    let max = grid.iter().flatten().copied().max().unwrap_or(0);
+    let shade = |n: usize| match n {
+        0 => ' ',
+        n => SHADES[(n * SHADES.len() - 1) / max],
+    };
+
+    // Line the hours up with the rows, which start "Mon |"
+    let hours: String = (0..24)
+        .step_by(3)
+        .map(|hour| format!("{:<6}", format!("{:02}", hour)))
+        .collect();
+    let mut lines = vec![format!("     {}", hours.trim_end())];
+
+    for (day, hours) in DAYS.iter().zip(grid) {
+        let row: String = hours
+            .iter()
+            .flat_map(|n| std::iter::repeat(shade(*n)).take(2))
+            .collect();
+        lines.push(format!("{} |{}| {}", day, row, hours.iter().sum::<usize>()));
+    }
+
+    lines.push(String::new());
+    lines.push(if max == 0 {
+        String::from("no commands")
+    } else {
+        format!(
+            "{} is the least busy hour, {} the busiest with {} command{}",
+            SHADES[0],
+            SHADES[SHADES.len() - 1],
+            max,
+            if max == 1 { "" } else { "s" }
+        )
+    });
+
+    lines.join("\n") + "\n"
+}
+
+/// What --aggregate-time totals up
+#[derive(Debug, Clone, Copy)]
+pub enum Group {
+    Command,
+    Token,
+}
+
+impl FromStr for Group {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "command" => Ok(Self::Command),
+            "token" => Ok(Self::Token),
+            _ => Err(format!(
+                "invalid grouping {:?}, expected command or token",
+                s
+            )),
+        }
+    }
+}
+
+/// Total up the time spent over the given runs, by command as is or by first
+/// word. Longest first
+pub fn aggregate_durations(group: Group, history: &[History]) -> Vec<(String, i64)> {
+    let mut totals = HashMap::<String, i64>::new();
+
+    for h in history {
+        let key = match group {
+            Group::Command => h.command.clone(),
+            Group::Token => h
+                .command
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        };
+
+        *totals.entry(key).or_default() += std::cmp::max(h.duration, 0);
+    }
+
+    totals
+        .into_iter()
+        .sorted_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)))
+        .collect()
+}
+
+#[allow(clippy::cast_sign_loss)]
+pub fn print_time_spent(out: &mut dyn Write, totals: &[(String, i64)]) -> Result<()> {
+    let mut writer = TabWriter::new(out).padding(2);
+
+    for (command, duration) in totals {
+        // Nobody needs the time spent down to the nanosecond
+        let duration = Duration::from_millis(std::cmp::max(*duration, 0) as u64 / 1_000_000);
+
+        writeln!(
+            writer,
+            "{}\t{}",
+            display_command(command, true),
+            humantime::format_duration(duration)
+        )?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use atuin_client::history::History;
+
+    use super::super::tests::history;
+    use super::{
+        aggregate_durations, diff_commands, group_by_cwd, render_cwd_commands, Group, GroupBy,
+    };
+
+    #[test]
+    fn aggregate_time() {
+        let history: Vec<History> = [
+            ("git push", 30),
+            ("git pull", 20),
+            ("cargo build", 40),
+            ("ls", 1),
+            ("ls", -1),
+        ]
+        .iter()
+        .map(|&(command, duration)| History {
+            duration,
+            ..history(command, 0, None)
+        })
+        .collect();
+
+        assert_eq!(
+            aggregate_durations(Group::Command, &history),
+            vec![
+                (String::from("cargo build"), 40),
+                (String::from("git push"), 30),
+                (String::from("git pull"), 20),
+                (String::from("ls"), 1),
+            ]
+        );
+
+        assert_eq!(
+            aggregate_durations(Group::Token, &history),
+            vec![
+                (String::from("git"), 50),
+                (String::from("cargo"), 40),
+                (String::from("ls"), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff() {
+        let set = |commands: &[&str]| -> BTreeSet<String> {
+            commands.iter().map(|c| String::from(*c)).collect()
+        };
+
+        let old = set(&["make", "vim", "ls"]);
+        let new = set(&["cargo build", "vim", "ls"]);
+        let diff = diff_commands(&old, &new);
+
+        assert_eq!(diff.added, vec!["cargo build"]);
+        assert_eq!(diff.removed, vec!["make"]);
+        assert_eq!(diff.common, vec!["ls", "vim"]);
+    }
+
+    #[test]
+    fn split_runs() {
+        let split = chrono::Utc::now();
+        let history = |command: &str, secs: i64| History {
+            timestamp: split + chrono::Duration::seconds(secs),
+            ..history(command, 0, None)
+        };
+        let commands = |history: Vec<History>| -> Vec<String> {
+            history.into_iter().map(|h| h.command).collect()
+        };
+
+        // A run right at the split counts as since, and only as since
+        let (before, since) = super::split_runs(
+            vec![history("make", -1), history("vim", 0), history("ls", 1)],
+            split,
+        );
+        assert_eq!(commands(before), vec!["make"]);
+        assert_eq!(commands(since), vec!["vim", "ls"]);
+    }
+
+    #[test]
+    fn summarize_duplicates() {
+        use chrono::TimeZone;
+
+        let history = |command: &str, session: &str, secs: i64| History {
+            timestamp: chrono::Utc.timestamp(1_600_000_000 + secs, 0),
+            ..history(command, 0, Some(session))
+        };
+
+        let duplicates = vec![
+            history("ls", "a", 1),
+            history("make", "a", 2),
+            history("ls", "a", 3),
+            history("ls", "b", 4),
+        ];
+        let summary = super::summarize_duplicates(&duplicates);
+        let counts: Vec<(&str, &str, usize)> = summary
+            .iter()
+            .map(|s| (s.command, s.session, s.copies))
+            .collect();
+        assert_eq!(counts, [("ls", "a", 2), ("ls", "b", 1), ("make", "a", 1)]);
+        assert_eq!(summary[0].last_seen, duplicates[2].timestamp);
+
+        assert_eq!(
+            super::render_duplicates(&summary[..1], "%s").unwrap(),
+            "COPIES  LAST SEEN   SESSION  COMMAND\n2       1600000003  a        ls\n"
+        );
+    }
+
+    #[test]
+    fn group_by_host() {
+        use chrono::TimeZone;
+
+        let history = |secs: i64, host: &str, command: &str| History {
+            timestamp: chrono::Utc.timestamp(secs, 0),
+            cwd: String::from("/srv"),
+            hostname: host.to_string(),
+            ..history(command, 0, None)
+        };
+
+        let history = vec![
+            history(100, "laptop:me", "git pull"),
+            history(200, "prod-1:deploy", "make deploy"),
+            history(300, "laptop:me", "git pull"),
+            history(400, "laptop:root", "apt upgrade"),
+            history(500, "prod-2:deploy", "make deploy"),
+        ];
+
+        let groups = super::summarize_groups(&history, GroupBy::Host);
+        assert_eq!(
+            groups
+                .iter()
+                .map(|g| (g.key, g.runs, g.distinct, g.last_seen.timestamp()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("laptop", 3, 2, 400),
+                ("prod-1", 1, 1, 200),
+                ("prod-2", 1, 1, 500)
+            ]
+        );
+
+        assert_eq!(
+            super::render_groups(&groups[..1], GroupBy::Host, "%s").unwrap(),
+            "HOST    RUNS  DISTINCT  LAST SEEN\nlaptop  3     2         400\n"
+        );
+    }
+
+    #[test]
+    fn explain_time() {
+        use chrono::TimeZone;
+
+        // 2022-10-03 was a Monday
+        let history = |day: u32, hour: u32| History {
+            timestamp: chrono::Local
+                .ymd(2022, 10, day)
+                .and_hms(hour, 30, 0)
+                .with_timezone(&chrono::Utc),
+            ..history("make", 0, None)
+        };
+        let history: Vec<History> = std::iter::repeat_with(|| history(3, 9))
+            .take(9)
+            .chain(std::iter::once(history(4, 0)))
+            .chain(std::iter::once(history(9, 23)))
+            .collect();
+
+        let grid = super::bucket_times(&history);
+        assert_eq!(grid[0][9], 9);
+        assert_eq!(grid[1][0], 1);
+        assert_eq!(grid[6][23], 1);
+        assert_eq!(grid.iter().flatten().sum::<usize>(), 11);
+
+        let heatmap = super::render_heatmap(&grid);
+        let lines: Vec<&str> = heatmap.lines().collect();
+        assert_eq!(lines[0].find("09"), lines[1].find("@@"));
+        assert_eq!(
+            lines[1],
+            format!("Mon |{}@@{}| 9", " ".repeat(18), " ".repeat(28))
+        );
+        assert_eq!(lines[2], format!("Tue |..{}| 1", " ".repeat(46)));
+        assert!(lines[7].starts_with("Sun |"));
+        assert!(lines[7].ends_with("..| 1"));
+
+        assert!(super::render_heatmap(&[[0; 24]; 7]).ends_with("no commands\n"));
+    }
+
+    #[test]
+    fn distinct_cwd_commands() {
+        let history = |cwd: &str, command: &str| History {
+            cwd: cwd.to_string(),
+            ..history(command, 0, None)
+        };
+
+        let history = vec![
+            history("/srv/prod", "make deploy"),
+            history("/home/me", "ls"),
+            history("/srv/prod", "git pull"),
+            history("/srv/prod", "make deploy"),
+        ];
+
+        assert_eq!(
+            render_cwd_commands(&group_by_cwd(&history), true),
+            "/home/me\n  ls\n\n/srv/prod\n  git pull\n  make deploy\n"
+        );
+    }
+}
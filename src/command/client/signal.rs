@@ -0,0 +1,91 @@
+//! Names for the signals commands are most often killed by. A shell reports a
+//! command killed by signal N as exiting with 128 + N
+
+use std::str::FromStr;
+
+// Only the signals that are numbered the same on Linux and macOS
+const SIGNALS: [(&str, i64); 12] = [
+    ("SIGHUP", 1),
+    ("SIGINT", 2),
+    ("SIGQUIT", 3),
+    ("SIGILL", 4),
+    ("SIGTRAP", 5),
+    ("SIGABRT", 6),
+    ("SIGFPE", 8),
+    ("SIGKILL", 9),
+    ("SIGSEGV", 11),
+    ("SIGPIPE", 13),
+    ("SIGALRM", 14),
+    ("SIGTERM", 15),
+];
+
+/// A signal, given by name or number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signal(i64);
+
+impl Signal {
+    /// The exit code of a command killed by this signal
+    pub const fn exit_code(self) -> i64 {
+        128 + self.0
+    }
+}
+
+impl FromStr for Signal {
+    type Err = String;
+
+    /// The SIG prefix is optional, and case doesn't matter
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_uppercase();
+        let name = name.strip_prefix("SIG").unwrap_or(&name);
+
+        if let Ok(n) = name.parse() {
+            return Ok(Self(n));
+        }
+
+        SIGNALS
+            .iter()
+            .find(|(signal, _)| &signal[3..] == name)
+            .map(|(_, n)| Self(*n))
+            .ok_or_else(|| {
+                format!(
+                    "unknown signal {:?}, expected a number or one of {}",
+                    s,
+                    SIGNALS
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
+
+/// The name of the signal an exit code says the command was killed by, if any
+pub fn name(exit: i64) -> Option<&'static str> {
+    SIGNALS
+        .iter()
+        .find(|(_, n)| 128 + n == exit)
+        .map(|(name, _)| *name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{name, Signal};
+
+    #[test]
+    fn parse_signal() {
+        assert_eq!("SIGINT".parse::<Signal>().unwrap().exit_code(), 130);
+        assert_eq!("int".parse::<Signal>().unwrap().exit_code(), 130);
+        assert_eq!("KILL".parse::<Signal>().unwrap().exit_code(), 137);
+        assert_eq!("15".parse::<Signal>().unwrap().exit_code(), 143);
+        assert!("SIGNOPE".parse::<Signal>().is_err());
+    }
+
+    #[test]
+    fn signal_names() {
+        assert_eq!(name(130), Some("SIGINT"));
+        assert_eq!(name(141), Some("SIGPIPE"));
+        assert_eq!(name(1), None);
+        assert_eq!(name(0), None);
+    }
+}
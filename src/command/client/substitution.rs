@@ -0,0 +1,140 @@
+//! sed style `s/old/new/` substitutions, for tweaking a command from history
+//! before it is returned to the shell
+
+use std::str::FromStr;
+
+use regex::{NoExpand, Regex};
+
+/// Replace what `pattern` matches with `replacement`, either the first match
+/// or, with a trailing `g`, every match
+#[derive(Debug)]
+pub struct Substitution {
+    pattern: Regex,
+    // Taken literally, as commands are full of $ that would otherwise be read
+    // as references to groups
+    replacement: String,
+    global: bool,
+}
+
+impl Substitution {
+    /// The command with the substitution made, or None if nothing matched
+    pub fn apply(&self, command: &str) -> Option<String> {
+        if !self.pattern.is_match(command) {
+            return None;
+        }
+
+        let replaced = if self.global {
+            self.pattern
+                .replace_all(command, NoExpand(&self.replacement))
+        } else {
+            self.pattern.replace(command, NoExpand(&self.replacement))
+        };
+
+        Some(replaced.into_owned())
+    }
+}
+
+impl FromStr for Substitution {
+    type Err = String;
+
+    /// Like sed, any punctuation can take the place of `/`, and a backslash
+    /// stops it from ending a part. The final delimiter is optional
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        let delimiter = match (chars.next(), chars.next()) {
+            (Some('s'), Some(d)) if d.is_ascii_punctuation() && d != '\\' => d,
+            _ => return Err(String::from("expected s/old/new/")),
+        };
+
+        let parts = split_unescaped(chars.as_str(), delimiter);
+        let (pattern, replacement, global) = match parts.as_slice() {
+            [pattern, replacement] => (pattern, replacement, false),
+            [pattern, replacement, flags] if flags.is_empty() || flags == "g" => {
+                (pattern, replacement, flags == "g")
+            }
+            _ => return Err(String::from("expected s/old/new/ or s/old/new/g")),
+        };
+
+        if pattern.is_empty() {
+            return Err(String::from("nothing to substitute"));
+        }
+
+        Ok(Self {
+            pattern: Regex::new(pattern).map_err(|e| format!("invalid pattern: {}", e))?,
+            replacement: replacement.clone(),
+            global,
+        })
+    }
+}
+
+/// Split on `delimiter`, except where it follows a backslash. The backslash
+/// is dropped there, and kept everywhere else, so regex escapes still work
+fn split_unescaped(s: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().unwrap();
+
+        match c {
+            '\\' if chars.peek() == Some(&delimiter) => part.push(chars.next().unwrap()),
+            c if c == delimiter => parts.push(String::new()),
+            c => part.push(c),
+        }
+    }
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Substitution;
+
+    fn substitute(expression: &str, command: &str) -> Option<String> {
+        expression.parse::<Substitution>().unwrap().apply(command)
+    }
+
+    #[test]
+    fn substitutes() {
+        assert_eq!(
+            substitute("s/staging/prod/", "deploy staging").as_deref(),
+            Some("deploy prod")
+        );
+        assert_eq!(substitute("s/a/o", "cat a").as_deref(), Some("cot a"));
+        assert_eq!(substitute("s/a/o/g", "cat a").as_deref(), Some("cot o"));
+        assert_eq!(substitute("s/prod/staging/", "deploy staging"), None);
+    }
+
+    #[test]
+    fn delimiters_and_escapes() {
+        assert_eq!(
+            substitute("s|/tmp|/var/tmp|", "ls /tmp").as_deref(),
+            Some("ls /var/tmp")
+        );
+        assert_eq!(
+            substitute(r"s/\/tmp/\/srv/", "ls /tmp").as_deref(),
+            Some("ls /srv")
+        );
+        assert_eq!(
+            substitute(r"s/v\d+/v2/", "helm install --version v14").as_deref(),
+            Some("helm install --version v2")
+        );
+
+        // The replacement is taken as it is
+        assert_eq!(
+            substitute("s/HOME/$HOME/", "ls HOME").as_deref(),
+            Some("ls $HOME")
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        for expression in ["", "x/a/b/", "s/a", "s//b/", "s/a/b/x", "s/(/b/"] {
+            assert!(
+                expression.parse::<Substitution>().is_err(),
+                "{}",
+                expression
+            );
+        }
+    }
+}
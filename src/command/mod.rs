@@ -6,6 +6,7 @@ mod server;
 
 #[derive(Subcommand)]
 #[clap(infer_subcommands = true)]
+#[allow(clippy::large_enum_variant)]
 pub enum AtuinCmd {
     #[clap(flatten)]
     Client(client::Cmd),
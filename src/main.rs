@@ -3,7 +3,7 @@
 
 use clap::AppSettings;
 use clap::Parser;
-use eyre::Result;
+use eyre::{bail, Result};
 
 #[macro_use]
 extern crate log;
@@ -21,16 +21,48 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
     global_setting(AppSettings::DeriveDisplayOrder),
 )]
 struct Atuin {
+    /// Use a separate config and history, such as for work and personal use
+    #[clap(long, global = true)]
+    profile: Option<String>,
+
+    /// Create the profile given with --profile, if it does not exist yet
+    #[clap(long, global = true, requires = "profile")]
+    create_profile: bool,
+
     #[clap(subcommand)]
     atuin: AtuinCmd,
 }
 
 impl Atuin {
     async fn run(self) -> Result<()> {
+        if let Some(profile) = &self.profile {
+            use_profile(profile, self.create_profile)?;
+        }
+
         self.atuin.run().await
     }
 }
 
+/// Switch to a profile's config and data directories. This goes through
+/// `ATUIN_PROFILE`, so anything ran from here, like the shell hooks, uses the
+/// profile too
+fn use_profile(profile: &str, create: bool) -> Result<()> {
+    if profile.is_empty() || profile == "." || profile == ".." || profile.contains(['/', '\\']) {
+        bail!("invalid profile name {:?}", profile);
+    }
+
+    std::env::set_var("ATUIN_PROFILE", profile);
+
+    if !create && !atuin_common::utils::data_dir().exists() {
+        bail!(
+            "profile {:?} does not exist, pass --create-profile to create it",
+            profile
+        );
+    }
+
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     Atuin::parse().run().await
@@ -2,12 +2,19 @@ ATUIN_SESSION=$(atuin uuid)
 export ATUIN_SESSION
 
 _atuin_preexec() {
+    # Picked in scratch mode, so not recorded
+    if [[ -n "${_atuin_scratch-}" ]]; then
+        unset _atuin_scratch ATUIN_HISTORY_ID
+        return
+    fi
+
     id=$(atuin history start "$1")
     export ATUIN_HISTORY_ID="$id"
 }
 
 _atuin_precmd() {
     local EXIT="$?"
+    unset _atuin_scratch
 
     [[ -z "${ATUIN_HISTORY_ID}" ]] && return
 
@@ -18,9 +25,16 @@ _atuin_precmd() {
 __atuin_history ()
 {
     tput rmkx
-    HISTORY="$(RUST_LOG=error atuin search -i "$BUFFER" 3>&1 1>&2 2>&3)"
+    HISTORY="$(RUST_LOG=error atuin search --shell bash -i "$BUFFER" 3>&1 1>&2 2>&3)"
     tput smkx
 
+    # The marker is on a line of its own, with the command after it if any
+    if [[ $HISTORY == "# atuin scratch" || $HISTORY == "# atuin scratch"$'\n'* ]]; then
+        _atuin_scratch=1
+        HISTORY=${HISTORY#"# atuin scratch"}
+        HISTORY=${HISTORY#$'\n'}
+    fi
+
     READLINE_LINE=${HISTORY}
     READLINE_POINT=${#READLINE_LINE}
 }
@@ -1,6 +1,13 @@
 set -gx ATUIN_SESSION (atuin uuid)
 
 function _atuin_preexec --on-event fish_preexec
+    # Picked in scratch mode, so not recorded
+    if set -q _atuin_scratch
+        set -e _atuin_scratch
+        set -e ATUIN_HISTORY_ID
+        return
+    end
+
     set -gx ATUIN_HISTORY_ID (atuin history start "$argv[1]")
 end
 
@@ -12,11 +19,22 @@ function _atuin_postexec --on-event fish_postexec
     end
 end
 
+function _atuin_prompt --on-event fish_prompt
+    # A command picked in scratch mode but never ran, such as one cleared with
+    # Ctrl-C, leaves the next command to be recorded as usual
+    set -e _atuin_scratch
+end
+
 function _atuin_search
-    set h (RUST_LOG=error atuin search -i (commandline -b) 3>&1 1>&2 2>&3)
+    # The command comes back url encoded, so newlines survive being captured
+    set h (RUST_LOG=error atuin search --shell fish -i (commandline -b) 3>&1 1>&2 2>&3)
     commandline -f repaint
+    if test "$h[1]" = "# atuin scratch"
+        set -g _atuin_scratch 1
+        set -e h[1]
+    end
     if test -n "$h"
-        commandline -r $h
+        commandline -r -- (string unescape --style=url -- $h | string collect)
     end
 end
 